@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::auth::AuthenticatedClient;
+use crate::oxygen::{acl_entry::Principal, AclEntry, Permission};
+
+/// Access control lists of collections, indexed by collection id.
+///
+/// Grants are inherited down the collection tree: a client gets the highest
+/// permission granted to it on the collection or any of its ancestors.
+/// Collections without any entry on the way up to their root are open to every
+/// client, for reading and writing. Setting the first list on them is up to the
+/// server's admin.
+#[derive(Debug, Default)]
+pub struct AccessControl {
+    lists: RwLock<HashMap<u64, Vec<AclEntry>>>,
+}
+
+impl AccessControl {
+    /// Access control with the lists that were stored, by collection id
+    pub fn with_lists(lists: HashMap<u64, Vec<AclEntry>>) -> Self {
+        Self {
+            lists: RwLock::new(lists),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<u64, Vec<AclEntry>>> {
+        self.lists.read().expect("acl lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<u64, Vec<AclEntry>>> {
+        self.lists.write().expect("acl lock poisoned")
+    }
+
+    /// Entries set on the collection `id` itself
+    pub fn get(&self, id: u64) -> Vec<AclEntry> {
+        self.read().get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Replaces the entries of the collection `id`, no entries clears the list
+    pub fn set(&self, id: u64, entries: Vec<AclEntry>) -> Result<(), String> {
+        validate(&entries)?;
+        if entries.is_empty() {
+            self.write().remove(&id);
+        } else {
+            self.write().insert(id, entries);
+        }
+        Ok(())
+    }
+
+    /// Forgets the entries of (deleted) collections
+    pub fn remove(&self, ids: impl IntoIterator<Item = u64>) {
+        let mut lists = self.write();
+        for id in ids {
+            lists.remove(&id);
+        }
+    }
+
+    /// Whether a list is set on the collection with the given lineage (the
+    /// collection and its ancestors) or any of its ancestors
+    pub fn restricted(&self, lineage: &[u64]) -> bool {
+        let lists = self.read();
+        lineage.iter().any(|id| lists.contains_key(id))
+    }
+
+    /// Permission of `client` on the collection with the given lineage (the
+    /// collection and its ancestors)
+    pub fn permission(&self, client: &AuthenticatedClient, lineage: &[u64]) -> Permission {
+        let lists = self.read();
+        let mut restricted = false;
+        let mut granted = Permission::None;
+        for entries in lineage.iter().filter_map(|id| lists.get(id)) {
+            restricted = true;
+            for entry in entries.iter().filter(|entry| applies_to(entry, client)) {
+                granted = granted.max(entry.permission());
+            }
+        }
+        if restricted {
+            granted
+        } else {
+            Permission::Write
+        }
+    }
+}
+
+/// Rejects entries that don't name who they grant access to or don't grant
+/// anything
+pub fn validate(entries: &[AclEntry]) -> Result<(), String> {
+    for entry in entries {
+        if entry.principal.is_none() {
+            return Err("ACL entries must name who they grant access to".to_string());
+        }
+        if entry.permission() == Permission::None {
+            return Err("ACL entries must grant a permission".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn applies_to(entry: &AclEntry, client: &AuthenticatedClient) -> bool {
+    match &entry.principal {
+        Some(Principal::ClientUuid(uuid)) => *uuid == client.uuid,
+        Some(Principal::User(user)) => client.user.as_ref() == Some(user),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccessControl;
+    use crate::auth::AuthenticatedClient;
+    use crate::oxygen::{acl_entry::Principal, AclEntry, Permission};
+
+    fn client(uuid: &str) -> AuthenticatedClient {
+        AuthenticatedClient {
+            uuid: uuid.to_string(),
+            session: String::new(),
+            user: None,
+        }
+    }
+
+    fn grant(uuid: &str, permission: Permission) -> AclEntry {
+        AclEntry {
+            principal: Some(Principal::ClientUuid(uuid.to_string())),
+            permission: permission as i32,
+        }
+    }
+
+    #[test]
+    fn collections_without_entries_are_open() {
+        let acl = AccessControl::default();
+        assert_eq!(acl.permission(&client("a"), &[0, 1]), Permission::Write);
+        assert!(!acl.restricted(&[0, 1]));
+        acl.set(2, vec![grant("a", Permission::Read)]).unwrap();
+        assert_eq!(acl.permission(&client("b"), &[0, 1]), Permission::Write);
+        assert!(acl.restricted(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn grants_are_inherited() {
+        let acl = AccessControl::default();
+        acl.set(0, vec![grant("a", Permission::Read)]).unwrap();
+        acl.set(
+            1,
+            vec![grant("a", Permission::Write), grant("b", Permission::Read)],
+        )
+        .unwrap();
+        assert_eq!(acl.permission(&client("a"), &[0]), Permission::Read);
+        assert_eq!(acl.permission(&client("a"), &[0, 1, 2]), Permission::Write);
+        assert_eq!(acl.permission(&client("b"), &[0]), Permission::None);
+        assert_eq!(acl.permission(&client("b"), &[0, 1, 2]), Permission::Read);
+        assert_eq!(acl.permission(&client("c"), &[0, 1, 2]), Permission::None);
+
+        acl.set(1, vec![]).unwrap();
+        assert!(acl.get(1).is_empty());
+        assert_eq!(acl.permission(&client("a"), &[0, 1, 2]), Permission::Read);
+        acl.remove([0]);
+        assert_eq!(acl.permission(&client("c"), &[0, 1, 2]), Permission::Write);
+    }
+
+    #[test]
+    fn users_get_what_is_granted_to_them() {
+        let acl = AccessControl::default();
+        let user_grant = AclEntry {
+            principal: Some(Principal::User("alice".to_string())),
+            permission: Permission::Write as i32,
+        };
+        acl.set(0, vec![user_grant, grant("a", Permission::Read)])
+            .unwrap();
+        let alice = AuthenticatedClient {
+            user: Some("alice".to_string()),
+            ..client("a")
+        };
+        assert_eq!(acl.permission(&alice, &[0]), Permission::Write);
+        assert_eq!(acl.permission(&client("a"), &[0]), Permission::Read);
+        let other_device = AuthenticatedClient {
+            user: Some("alice".to_string()),
+            ..client("b")
+        };
+        assert_eq!(acl.permission(&other_device, &[0]), Permission::Write);
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let acl = AccessControl::default();
+        assert!(acl.set(0, vec![grant("a", Permission::None)]).is_err());
+        let anonymous = AclEntry {
+            principal: None,
+            permission: Permission::Read as i32,
+        };
+        assert!(acl.set(0, vec![anonymous]).is_err());
+        assert!(acl.get(0).is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+use tonic::{metadata::MetadataMap, service::Interceptor, Request, Status};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Metadata carrying the session token issued by `register`, as
+/// `Bearer <token>`
+pub const TOKEN_METADATA_KEY: &str = "authorization";
+/// Metadata carrying the admin secret, needed to register new clients when the
+/// server has one
+pub const ADMIN_SECRET_METADATA_KEY: &str = "x-oxygen-admin-secret";
+/// Scheme of the password hashes made by [`hash_password`]
+const PASSWORD_SCHEME: &str = "pbkdf2-sha256";
+/// PBKDF2 rounds of new password hashes. Hashes keep the number of rounds they
+/// were made with, so it can be raised without breaking existing accounts.
+const PASSWORD_ROUNDS: u32 = 100_000;
+
+/// Client that proved who it is with a valid token. Added to the extensions of
+/// the requests that carry one by [`AuthInterceptor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedClient {
+    pub uuid: String,
+    /// Session the token was issued for. The token is only good as long as
+    /// that session lives.
+    pub session: String,
+    /// User the client is logged in as. Tokens only prove who the client is,
+    /// this is filled in from its session.
+    pub user: Option<String>,
+}
+
+/// Issues and checks session tokens. Tokens are the client uuid and session id
+/// signed with a key that only lives as long as the server, like the sessions
+/// they are for.
+pub struct Authenticator {
+    mac: HmacSha256,
+    // signature of the admin secret, so that secrets are compared in constant
+    // time
+    admin_secret: Option<Vec<u8>>,
+}
+
+impl Authenticator {
+    pub fn new(admin_secret: Option<&str>) -> Self {
+        let mut key = [0u8; 32];
+        getrandom::getrandom(&mut key).expect("failed to generate the token key");
+        let mac = HmacSha256::new_from_slice(&key).expect("hmac accepts keys of any size");
+        let admin_secret = admin_secret.map(|secret| {
+            primed(&mac, "admin", secret)
+                .finalize()
+                .into_bytes()
+                .to_vec()
+        });
+        Self { mac, admin_secret }
+    }
+
+    pub fn issue_token(&self, uuid: &str, session: &str) -> String {
+        let signed = format!("{}.{}", uuid, session);
+        let signature = primed(&self.mac, "token", &signed).finalize().into_bytes();
+        format!("{}.{}", signed, to_hex(&signature))
+    }
+
+    /// The client and session the token was issued for if it has a valid
+    /// signature. Whether the session is still live is up to the caller.
+    pub fn verify_token(&self, token: &str) -> Option<AuthenticatedClient> {
+        let (signed, signature) = token.rsplit_once('.')?;
+        let signature = from_hex(signature)?;
+        primed(&self.mac, "token", signed)
+            .verify_slice(&signature)
+            .ok()?;
+        let (uuid, session) = signed.rsplit_once('.')?;
+        Some(AuthenticatedClient {
+            uuid: uuid.to_string(),
+            session: session.to_string(),
+            user: None,
+        })
+    }
+
+    /// Whether new clients have to prove they may register with the admin
+    /// secret
+    pub fn has_admin_secret(&self) -> bool {
+        self.admin_secret.is_some()
+    }
+
+    /// Checks the admin secret in `metadata`. Nobody is an admin when the
+    /// server doesn't have a secret.
+    pub fn is_admin(&self, metadata: &MetadataMap) -> bool {
+        let Some(admin_secret) = &self.admin_secret else {
+            return false;
+        };
+        let Some(secret) = metadata
+            .get(ADMIN_SECRET_METADATA_KEY)
+            .and_then(|secret| secret.to_str().ok())
+        else {
+            return false;
+        };
+        primed(&self.mac, "admin", secret)
+            .verify_slice(admin_secret)
+            .is_ok()
+    }
+}
+
+/// Copy of `mac` fed with `value`. The purpose keeps signatures made for
+/// different things apart.
+fn primed(mac: &HmacSha256, purpose: &str, value: &str) -> HmacSha256 {
+    let mut mac = mac.clone();
+    mac.update(purpose.as_bytes());
+    mac.update(b"\0");
+    mac.update(value.as_bytes());
+    mac
+}
+
+/// Salted hash of `password` to store in place of the password, as
+/// `pbkdf2-sha256$<rounds>$<salt>$<hash>`
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).expect("failed to generate a salt");
+    let mut hash = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, PASSWORD_ROUNDS, &mut hash);
+    format!(
+        "{}${}${}${}",
+        PASSWORD_SCHEME,
+        PASSWORD_ROUNDS,
+        to_hex(&salt),
+        to_hex(&hash)
+    )
+}
+
+/// Checks `password` against a hash made by [`hash_password`]
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    let mut parts = password_hash.split('$');
+    let (Some(PASSWORD_SCHEME), Some(rounds), Some(salt), Some(hash), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return false;
+    };
+    let (Ok(rounds), Some(salt), Some(hash)) = (rounds.parse(), from_hex(salt), from_hex(hash))
+    else {
+        return false;
+    };
+    let mut expected = vec![0u8; hash.len()];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, rounds, &mut expected);
+    // compared in constant time
+    !hash.is_empty()
+        && expected
+            .iter()
+            .zip(&hash)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Verifies the token of the requests that carry one. Requests without a token
+/// are let through unauthenticated, handlers decide whether they need one.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    authenticator: Arc<Authenticator>,
+}
+
+impl AuthInterceptor {
+    pub fn new(authenticator: Arc<Authenticator>) -> Self {
+        Self { authenticator }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(value) = request.metadata().get(TOKEN_METADATA_KEY) else {
+            return Ok(request);
+        };
+        let token = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Malformed authorization metadata"))?;
+        let client = self
+            .authenticator
+            .verify_token(token)
+            .ok_or_else(|| Status::unauthenticated("Invalid session token"))?;
+        request.extensions_mut().insert(client);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tonic::{metadata::MetadataMap, service::Interceptor, Code, Request};
+
+    use super::{
+        hash_password, verify_password, AuthInterceptor, AuthenticatedClient, Authenticator,
+        ADMIN_SECRET_METADATA_KEY, TOKEN_METADATA_KEY,
+    };
+
+    #[test]
+    fn tokens_are_tied_to_the_server_client_and_session() {
+        let authenticator = Authenticator::new(None);
+        let token = authenticator.issue_token("a", "1");
+        assert_eq!(
+            authenticator.verify_token(&token),
+            Some(AuthenticatedClient {
+                uuid: "a".to_string(),
+                session: "1".to_string(),
+                user: None,
+            })
+        );
+        let forged = token.replacen('a', "b", 1);
+        assert_eq!(authenticator.verify_token(&forged), None);
+        let forged = token.replacen('1', "2", 1);
+        assert_eq!(authenticator.verify_token(&forged), None);
+        assert_eq!(authenticator.verify_token("a"), None);
+        assert_eq!(authenticator.verify_token("a.zz"), None);
+        assert_eq!(Authenticator::new(None).verify_token(&token), None);
+    }
+
+    #[test]
+    fn admin_secret_is_checked_when_set() {
+        let mut metadata = MetadataMap::new();
+        let authenticator = Authenticator::new(None);
+        assert!(!authenticator.has_admin_secret());
+        assert!(!authenticator.is_admin(&metadata));
+        let authenticator = Authenticator::new(Some("secret"));
+        assert!(authenticator.has_admin_secret());
+        assert!(!authenticator.is_admin(&metadata));
+        metadata.insert(ADMIN_SECRET_METADATA_KEY, "wrong".parse().unwrap());
+        assert!(!authenticator.is_admin(&metadata));
+        metadata.insert(ADMIN_SECRET_METADATA_KEY, "secret".parse().unwrap());
+        assert!(authenticator.is_admin(&metadata));
+    }
+
+    #[test]
+    fn passwords_are_salted_and_hashed() {
+        let hash = hash_password("hunter2");
+        assert!(!hash.contains("hunter2"));
+        assert_ne!(hash, hash_password("hunter2"));
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "hunter2"));
+        assert!(!verify_password("hunter2", "pbkdf2-sha256$1$00$"));
+    }
+
+    #[test]
+    fn interceptor_only_lets_valid_tokens_through() {
+        let authenticator = Arc::new(Authenticator::new(None));
+        let token = authenticator.issue_token("a", "1");
+        let mut interceptor = AuthInterceptor::new(authenticator);
+
+        let request = interceptor
+            .call(Request::new(()))
+            .expect("requests without tokens are let through");
+        assert!(request.extensions().get::<AuthenticatedClient>().is_none());
+
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            TOKEN_METADATA_KEY,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let request = interceptor.call(request).expect("token should be valid");
+        assert_eq!(
+            request.extensions().get::<AuthenticatedClient>(),
+            Some(&AuthenticatedClient {
+                uuid: "a".to_string(),
+                session: "1".to_string(),
+                user: None,
+            })
+        );
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(TOKEN_METADATA_KEY, token.parse().unwrap());
+        let status = interceptor
+            .call(request)
+            .expect_err("tokens must be bearer tokens");
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+}
@@ -1,14 +1,110 @@
-use oxygen::{oxygen_client::OxygenClient, ClientId};
+use std::fs;
 
+use oxygen::{oxygen_client::OxygenClient, ClientId, RegisterRequest};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+// streaming rpcs get an associated type named after the (camel case) rpc
+#[allow(non_camel_case_types)]
 pub mod oxygen {
     tonic::include_proto!("oxygen_lib");
 }
 
+const USAGE: &str = "\
+Usage: oxygen-client [options]
+
+Options:
+    --server <url>       server to connect to (default: http://[::1]:50050)
+    --ca <file>          PEM certificate of the CA that signed the server
+                         certificate, enables TLS
+    --domain <name>      name the server certificate is checked against
+                         (default: the host of the server url)
+    --cert <file>        PEM certificate of the client, for mutual TLS
+    --key <file>         PEM private key of the client certificate";
+
+/// Command line options of `oxygen-client`
+#[derive(Debug, Default)]
+struct Options {
+    server: Option<String>,
+    ca: Option<String>,
+    domain: Option<String>,
+    cert: Option<String>,
+    key: Option<String>,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            let option = match arg.as_str() {
+                "--server" => &mut options.server,
+                "--ca" => &mut options.ca,
+                "--domain" => &mut options.domain,
+                "--cert" => &mut options.cert,
+                "--key" => &mut options.key,
+                _ => return Err(format!("Unknown option {}", arg)),
+            };
+            *option = Some(value);
+        }
+        if options.cert.is_some() != options.key.is_some() {
+            return Err("--cert and --key go together".to_string());
+        }
+        if options.cert.is_some() && options.ca.is_none() {
+            return Err("--cert requires --ca".to_string());
+        }
+        Ok(options)
+    }
+
+    async fn connect(&self) -> Result<Channel, Box<dyn std::error::Error>> {
+        let scheme = if self.ca.is_some() { "https" } else { "http" };
+        let server = match &self.server {
+            Some(server) => server.clone(),
+            None => format!("{}://[::1]:50050", scheme),
+        };
+        let mut endpoint = Channel::from_shared(server)?;
+        if let Some(ca) = &self.ca {
+            let mut tls =
+                ClientTlsConfig::new().ca_certificate(Certificate::from_pem(fs::read(ca)?));
+            if let Some(domain) = &self.domain {
+                tls = tls.domain_name(domain);
+            }
+            if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+                tls = tls.identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+            }
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        Ok(endpoint.connect().await?)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
     let uuid = uuid::Uuid::new_v4().to_string();
-    let mut client = OxygenClient::connect("http://[::1]:50050").await?;
-    let reg_request = tonic::Request::new(ClientId { uuid });
+    let mut client = OxygenClient::new(options.connect().await?);
+    let mut reg_request = tonic::Request::new(RegisterRequest {
+        client_id: Some(ClientId { uuid }),
+        metadata: [("name".to_string(), "oxygen-client".to_string())].into(),
+    });
+    // only needed when the server has an admin secret
+    if let Ok(secret) = std::env::var("OXYGEN_ADMIN_SECRET") {
+        reg_request
+            .metadata_mut()
+            .insert("x-oxygen-admin-secret", secret.parse()?);
+    }
     let reg_response = client.register(reg_request).await?;
     println!("REG RES = {:?}", reg_response);
     Ok(())
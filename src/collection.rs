@@ -1,23 +1,383 @@
 // XXX: it is better if the collection module take the view of files as is instead of
 // trying to match the gRPC message types
-use crate::oxygen::{Collection, File, FileContent};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::Path,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use prost::Message;
+use tokio::sync::broadcast;
+use tonic::{Code, Status};
+use trash::Trash;
+use tree::{FileMeta, Tree};
+
+use crate::oxygen::{
+    trash_item, AclEntry, ChangeEvent, Collection, File, FileContent, FileType, Revision, TrashItem,
+};
+
+pub mod async_storage;
+#[cfg(test)]
+pub mod conformance;
+pub mod filesystem;
+pub mod search;
+pub mod sqlite;
+mod trash;
+mod tree;
+
+/// Reasons a storage operation can fail. Each variant carries a human readable
+/// description of what went wrong, except [`StorageError::Stale`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageError {
+    /// The requested collection or file doesn't exist
+    NotFound(String),
+    PermissionDenied(String),
+    /// The underlying storage failed (disk errors and the like)
+    Io(String),
+    /// The operation clashes with the current state of the storage (ex: a file
+    /// with the same name already exists)
+    Conflict(String),
+    InvalidName(String),
+    /// The request doesn't make sense for the current state of the storage
+    /// (ex: moving a collection into itself)
+    InvalidArgument(String),
+    /// The stored data can't be interpreted
+    Corrupt(String),
+    /// A write expected another version of the file, carries the current one
+    Stale(Box<File>),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Either a collection or a file, by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ItemId {
+    Collection(u64),
+    File(u64),
+}
+
+impl fmt::Display for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemId::Collection(id) => write!(f, "collection with id: {}", id),
+            ItemId::File(id) => write!(f, "file with id: {}", id),
+        }
+    }
+}
+
+/// Account of a person using the server, possibly from several clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub username: String,
+    /// See [`crate::auth::hash_password`]
+    pub password_hash: String,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::NotFound(message) => write!(f, "not found: {}", message),
+            StorageError::PermissionDenied(message) => write!(f, "permission denied: {}", message),
+            StorageError::Io(message) => write!(f, "io error: {}", message),
+            StorageError::Conflict(message) => write!(f, "conflict: {}", message),
+            StorageError::InvalidName(message) => write!(f, "invalid name: {}", message),
+            StorageError::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            StorageError::Corrupt(message) => write!(f, "corrupt data: {}", message),
+            StorageError::Stale(file) => write!(
+                f,
+                "stale write: file with id: {} is at version {}",
+                file.id, file.etag
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound(err.to_string()),
+            io::ErrorKind::PermissionDenied => StorageError::PermissionDenied(err.to_string()),
+            io::ErrorKind::AlreadyExists | io::ErrorKind::DirectoryNotEmpty => {
+                StorageError::Conflict(err.to_string())
+            }
+            io::ErrorKind::InvalidData => StorageError::Corrupt(err.to_string()),
+            _ => StorageError::Io(err.to_string()),
+        }
+    }
+}
+
+impl From<StorageError> for Status {
+    fn from(err: StorageError) -> Self {
+        let code = match &err {
+            StorageError::NotFound(_) => Code::NotFound,
+            StorageError::PermissionDenied(_) => Code::PermissionDenied,
+            StorageError::Io(_) => Code::Internal,
+            StorageError::Conflict(_) => Code::Aborted,
+            StorageError::InvalidName(_) | StorageError::InvalidArgument(_) => {
+                Code::InvalidArgument
+            }
+            StorageError::Corrupt(_) => Code::DataLoss,
+            // clients merge with the current version before trying again
+            StorageError::Stale(file) => {
+                return Status::with_details(
+                    Code::FailedPrecondition,
+                    err.to_string(),
+                    file.encode_to_vec().into(),
+                )
+            }
+        };
+        Status::new(code, err.to_string())
+    }
+}
+
+/// Checks that `name` can be used as the name of a collection or a file.
+/// Names must be a single, non empty path component.
+pub fn validate_name(name: &str) -> StorageResult<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(StorageError::InvalidName(format!("{:?}", name)));
+    }
+    if name.contains(['/', '\\', '\0']) {
+        return Err(StorageError::InvalidName(format!(
+            "{:?} contains a path separator or a nul character",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that `username` can be used as the name of a user. Usernames are
+/// made of ASCII letters, digits, `-`, `_` and `.`.
+pub fn validate_username(username: &str) -> StorageResult<()> {
+    let valid = !username.is_empty()
+        && username.len() <= 64
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidName(format!(
+            "{:?} is not a valid username",
+            username
+        )))
+    }
+}
+
+/// Guesses the type of a file from the extension of its name
+pub fn file_type(name: &str) -> FileType {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "md" | "markdown" => FileType::Markdown,
+        "png" | "jpg" | "jpeg" | "gif" | "webp" | "svg" | "bmp" => FileType::Image,
+        "pdf" => FileType::Pdf,
+        _ => FileType::Other,
+    }
+}
+
+/// Collection or file `item` of the trash is
+pub fn trash_item_id(item: &TrashItem) -> Option<ItemId> {
+    match item.item.as_ref()? {
+        trash_item::Item::Collection(collection) => Some(ItemId::Collection(collection.id)),
+        trash_item::Item::File(file) => Some(ItemId::File(file.id)),
+    }
+}
+
+/// Milliseconds between the Unix epoch and `time`, 0 for earlier times
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Backend keeping the collections, files and users.
+///
+/// The methods reading the collection tree (`get_collection_all`,
+/// `get_collection`, `get_file`, `collection_lineage`, `resolve_path` and
+/// `get_path`) are called from async code and should answer from memory, so
+/// backends must not hold the locks they need across I/O. The others can block
+/// on I/O, see [`async_storage::AsyncStorage`].
 pub trait Storage {
-    // TODO: this needs to be a singleton
-    fn new() -> Self
-    where
-        Self: Sized;
     fn get_collection_all(&self) -> Vec<Collection>;
-    // TODO: this needs to return proper errors
-    fn get_collection(&self, id: u64) -> Result<Collection, ()>;
-    // TODO: this needs to return proper errors
-    fn get_file(&self, id: u64) -> Result<File, ()>;
-    fn get_file_content(&self, id: u64) -> Result<FileContent, ()>;
+    fn get_collection(&self, id: u64) -> StorageResult<Collection>;
+    fn get_file(&self, id: u64) -> StorageResult<File>;
+    fn get_file_content(&self, id: u64) -> StorageResult<FileContent>;
+    /// Ids of the collection `id` and all of its ancestors, starting from the
+    /// root collection
+    fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>>;
+    /// Reads up to `length` bytes (or everything if `None`) of the file `id`
+    /// starting at `offset`. Reading past the end of the file gives an empty
+    /// body.
+    ///
+    /// The default implementation reads the whole file, backends that can read
+    /// only the requested range should override it.
+    fn read_file_range(&self, id: u64, offset: u64, length: Option<u64>) -> StorageResult<Vec<u8>> {
+        let body = self.get_file_content(id)?.body;
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(body.len());
+        let end = match length.map(usize::try_from) {
+            Some(Ok(length)) => start.saturating_add(length).min(body.len()),
+            _ => body.len(),
+        };
+        Ok(body[start..end].to_vec())
+    }
+    /// Creates a new file named `name` in the collection `collection_id`. Fails
+    /// with [`StorageError::Conflict`] if the collection already has a file
+    /// with that name. `author` is recorded with the first revision, see
+    /// [`Storage::list_revisions`].
+    fn create_file(
+        &self,
+        collection_id: u64,
+        name: &str,
+        body: Vec<u8>,
+        author: &str,
+    ) -> StorageResult<File>;
+    /// Replaces the content of the file `id` with `body`, recorded as a new
+    /// revision by `author`. When `if_match` is given the file must still have
+    /// that etag, otherwise fails with [`StorageError::Stale`].
+    fn update_file_content(
+        &self,
+        id: u64,
+        body: Vec<u8>,
+        author: &str,
+        if_match: Option<&str>,
+    ) -> StorageResult<File>;
+    /// Revisions of the content of the file `id`, oldest first. Every write of
+    /// the content, including the creation of the file, records one. The
+    /// author is the uuid of the client that wrote the content, empty if it
+    /// wasn't written through the storage (ex: edited on disk). Revisions go
+    /// away with the file.
+    fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>>;
+    fn get_revision_content(&self, id: u64, revision_id: u64) -> StorageResult<FileContent>;
+    /// Makes the content of the revision `revision_id` the current content of
+    /// the file `id`, recorded as a new revision by `author`
+    fn restore_revision(&self, id: u64, revision_id: u64, author: &str) -> StorageResult<File> {
+        let body = self.get_revision_content(id, revision_id)?.body;
+        self.update_file_content(id, body, author, None)
+    }
+    /// Moves the file `id` to the trash returning the file as it was before
+    /// deletion. `if_match` works as for [`Storage::update_file_content`].
+    fn delete_file(&self, id: u64, if_match: Option<&str>) -> StorageResult<File>;
+    /// Moves the file `id` into the collection `collection_id` under the name
+    /// `name`, keeping its id. An existing file with the same name is only
+    /// replaced if `overwrite` is set, otherwise fails with
+    /// [`StorageError::Conflict`]. The replaced file goes to the trash, as with
+    /// [`Storage::delete_file`]. Returns the moved file and its new parent.
+    fn move_file(
+        &self,
+        id: u64,
+        collection_id: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<(File, Collection)>;
+    /// Creates an empty collection named `name` under the collection `parent_id`
+    fn create_collection(&self, parent_id: u64, name: &str) -> StorageResult<Collection>;
+    fn rename_collection(&self, id: u64, name: &str) -> StorageResult<Collection>;
+    /// Moves the collection `id` under the collection `parent_id`. Ids of the
+    /// collection and everything in it stay the same. Fails with
+    /// [`StorageError::InvalidArgument`] if `parent_id` is the collection itself
+    /// or one of its descendants.
+    fn move_collection(&self, id: u64, parent_id: u64) -> StorageResult<Collection>;
+    /// Moves the collection `id`, with everything in it, to the trash returning
+    /// the collection as it was before deletion. Unless `recursive` is set only
+    /// empty collections can be deleted, otherwise fails with
+    /// [`StorageError::Conflict`].
+    fn delete_collection(&self, id: u64, recursive: bool) -> StorageResult<Collection>;
+    /// Collections and files deleted with [`Storage::delete_file`] and
+    /// [`Storage::delete_collection`], oldest first. Items in the trash can't
+    /// be reached by id or path until they are restored.
+    fn list_trash(&self) -> StorageResult<Vec<TrashItem>>;
+    /// Puts `item` back from the trash, in the collection `parent_id` or the
+    /// one it was deleted from. The item and everything in it keep their ids.
+    /// Fails with [`StorageError::NotFound`] if that collection is gone and
+    /// with [`StorageError::Conflict`] if it has an item with the same name.
+    /// Returns the restored item.
+    fn restore_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item>;
+    /// Deletes `item` from the trash for good, along with the revisions of the
+    /// files in it. Returns the item as it was listed.
+    fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem>;
+    /// Purges every item deleted before `cutoff` (milliseconds since the Unix
+    /// epoch), returns the purged items
+    fn purge_trash_before(&self, cutoff: u64) -> StorageResult<Vec<TrashItem>> {
+        let mut purged = vec![];
+        for item in self.list_trash()? {
+            if item.deleted >= cutoff {
+                continue;
+            }
+            let Some(id) = trash_item_id(&item) else {
+                continue;
+            };
+            match self.purge_trash(id) {
+                Ok(item) => purged.push(item),
+                // restored or purged in the meantime
+                Err(StorageError::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(purged)
+    }
+    /// Collection or file at `path`: the names from a root collection down to
+    /// the item, separated by `/` (ex: `work/meetings/2026-10-01.md`). Root
+    /// collections are the empty path. Fails with
+    /// [`StorageError::InvalidArgument`] if several items match the path.
+    fn resolve_path(&self, path: &str) -> StorageResult<ItemId>;
+    /// Path of the collection or file `item`, see [`Storage::resolve_path`]
+    fn get_path(&self, item: ItemId) -> StorageResult<String>;
+    fn get_user(&self, username: &str) -> StorageResult<User>;
+    /// Adds the account of a new user. Fails with [`StorageError::Conflict`]
+    /// if the username is taken.
+    fn create_user(&self, user: User) -> StorageResult<User>;
+    /// Access control lists of the collections that have one, by collection
+    /// id
+    fn get_acls(&self) -> StorageResult<HashMap<u64, Vec<AclEntry>>>;
+    /// Replaces the access control list of the collection `id`, no entries
+    /// removes it. Lists go away with their collection when it is purged from
+    /// the trash.
+    fn set_acl(&self, id: u64, entries: Vec<AclEntry>) -> StorageResult<()>;
+    /// Feed of changes the storage picks up on its own (ex: edits made directly
+    /// on disk). Changes made through the methods of this trait are not sent
+    /// to the feed. Storages that only change through this trait don't need a
+    /// feed.
+    fn changes(&self) -> Option<broadcast::Receiver<Change>> {
+        None
+    }
+}
+
+/// A change to the content of a storage
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub event: ChangeEvent,
+    /// Ids of every collection the change happened in, along with all of their
+    /// ancestors. Used to decide which watchers are interested in the change.
+    pub collections: Vec<u64>,
 }
 
+/// In memory storage seeded with a hard coded set of collections and files.
+/// Changes are kept only for the lifetime of the storage.
 pub struct HardCodedStorage {
-    hard_coded_collections: Vec<Collection>,
-    hard_coded_files: Vec<File>,
+    state: RwLock<MemoryState>,
+}
+
+struct MemoryState {
+    tree: Tree,
+    contents: HashMap<u64, Vec<u8>>,
+    /// Past contents of each file, oldest first. Trashed files keep their
+    /// content and revisions until they are purged.
+    revisions: HashMap<u64, Vec<(Revision, Vec<u8>)>>,
+    trash: Trash,
+    users: HashMap<String, User>,
+    acls: HashMap<u64, Vec<AclEntry>>,
 }
 
 /// Hardcoded file structure
@@ -30,100 +390,647 @@ pub struct HardCodedStorage {
 /// -- -- -- collection_0
 /// -- -- f 2.md
 /// -- f_1.md
-impl Storage for HardCodedStorage {
-    fn new() -> Self
-    where
-        Self: Sized,
-    {
-        let collection_0 = Collection {
-            name: "collection_1".to_string(),
-            id: 0,
-            child_collections: vec![],
-            files: vec![],
-        };
-        let f_2 = File {
-            name: "f 2.md".to_string(),
-            id: 0,
-        };
-        let collection_1 = Collection {
-            name: "collection 1".to_string(),
-            id: 1,
-            child_collections: vec![collection_0.clone()],
-            files: vec![f_2.clone()],
-        };
-        let f_3 = File {
-            name: "f 3.md".to_string(),
-            id: 1,
-        };
-        let f_4 = File {
-            name: "f_4.md".to_string(),
-            id: 2,
-        };
-        let collection_2 = Collection {
-            name: "collection 2".to_string(),
-            id: 2,
-            child_collections: vec![],
-            files: vec![f_3.clone(), f_4.clone()],
-        };
-        let collection_3 = Collection {
-            name: "collection 3".to_string(),
-            id: 3,
-            child_collections: vec![collection_2.clone(), collection_1.clone()],
-            files: vec![f_2.clone()],
-        };
-        let f_1 = File {
-            name: "f_1.md".to_string(),
-            id: 3,
-        };
-        let collection_4 = Collection {
-            name: "collection 4".to_string(),
-            id: 4,
-            child_collections: vec![collection_3.clone()],
-            files: vec![f_1.clone()],
+impl HardCodedStorage {
+    // TODO: this needs to be a singleton
+    pub fn new() -> Self {
+        let mut tree = Tree::new();
+        tree.insert_collection_with_id(4, None, "collection 4".to_string());
+        tree.insert_collection_with_id(3, Some(4), "collection 3".to_string());
+        tree.insert_collection_with_id(2, Some(3), "collection 2".to_string());
+        tree.insert_collection_with_id(1, Some(3), "collection 1".to_string());
+        tree.insert_collection_with_id(0, Some(1), "collection_1".to_string());
+        tree.insert_file_with_id(0, 3, "f 2.md".to_string());
+        tree.insert_file_with_id(1, 2, "f 3.md".to_string());
+        tree.insert_file_with_id(2, 2, "f_4.md".to_string());
+        tree.insert_file_with_id(3, 4, "f_1.md".to_string());
+        let now = unix_millis(SystemTime::now());
+        let mut state = MemoryState {
+            tree,
+            contents: HashMap::new(),
+            revisions: HashMap::new(),
+            trash: Trash::new(),
+            users: HashMap::new(),
+            acls: HashMap::new(),
         };
-        let hard_coded_collections = vec![
-            collection_0,
-            collection_1,
-            collection_2,
-            collection_3,
-            collection_4,
-        ];
-        let hard_coded_files = vec![f_1, f_2, f_3, f_4];
+        for id in 0..4 {
+            let name = &state
+                .tree
+                .file(id)
+                .expect("hard coded file must exist")
+                .name;
+            let body = format!("# {} content", name).into_bytes();
+            let meta = FileMeta::of(&body, now, now);
+            state.write_content(id, body, meta, "");
+        }
         Self {
-            hard_coded_collections,
-            hard_coded_files,
+            state: RwLock::new(state),
         }
     }
 
+    fn read(&self) -> RwLockReadGuard<'_, MemoryState> {
+        self.state.read().expect("storage lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MemoryState> {
+        self.state.write().expect("storage lock poisoned")
+    }
+}
+
+impl MemoryState {
+    /// Makes `body` the content of the file `id`, recording a new revision
+    fn write_content(&mut self, id: u64, body: Vec<u8>, meta: FileMeta, author: &str) {
+        let revisions = self.revisions.entry(id).or_default();
+        let revision_id = revisions.last().map_or(1, |(revision, _)| revision.id + 1);
+        revisions.push((meta.to_revision(id, revision_id, author), body.clone()));
+        self.tree.set_file_meta(id, meta);
+        self.contents.insert(id, body);
+    }
+}
+
+impl Storage for HardCodedStorage {
     fn get_collection_all(&self) -> Vec<Collection> {
-        self.hard_coded_collections.clone()
+        self.read().tree.to_collection_all()
     }
 
-    fn get_collection(&self, id: u64) -> Result<Collection, ()> {
-        let index: usize = id.try_into().unwrap();
-        if index < self.hard_coded_collections.len() {
-            Ok(self.hard_coded_collections[index].clone())
-        } else {
-            Err(())
+    fn get_collection(&self, id: u64) -> StorageResult<Collection> {
+        let state = self.read();
+        state.tree.require_collection(id)?;
+        Ok(state.tree.to_collection(id).expect("collection exists"))
+    }
+
+    fn get_file(&self, id: u64) -> StorageResult<File> {
+        let state = self.read();
+        state.tree.require_file(id)?;
+        Ok(state.tree.to_file(id).expect("file exists"))
+    }
+
+    fn get_file_content(&self, id: u64) -> StorageResult<FileContent> {
+        let state = self.read();
+        state.tree.require_file(id)?;
+        Ok(FileContent {
+            body: state.contents[&id].clone(),
+        })
+    }
+
+    fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>> {
+        let state = self.read();
+        state.tree.require_collection(id)?;
+        Ok(state.tree.collection_lineage(id))
+    }
+
+    fn create_file(
+        &self,
+        collection_id: u64,
+        name: &str,
+        body: Vec<u8>,
+        author: &str,
+    ) -> StorageResult<File> {
+        validate_name(name)?;
+        let mut state = self.write();
+        state.tree.check_name_free(collection_id, name)?;
+        let id = state.tree.insert_file(collection_id, name.to_string());
+        let now = unix_millis(SystemTime::now());
+        let meta = FileMeta::of(&body, now, now);
+        state.write_content(id, body, meta, author);
+        Ok(state.tree.to_file(id).expect("file was just inserted"))
+    }
+
+    fn update_file_content(
+        &self,
+        id: u64,
+        body: Vec<u8>,
+        author: &str,
+        if_match: Option<&str>,
+    ) -> StorageResult<File> {
+        let mut state = self.write();
+        state.tree.check_etag(id, if_match)?;
+        let created = state.tree.require_file(id)?.meta.created;
+        let now = unix_millis(SystemTime::now());
+        let meta = FileMeta::of(&body, created, now);
+        state.write_content(id, body, meta, author);
+        Ok(state.tree.to_file(id).expect("file exists"))
+    }
+
+    fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>> {
+        let state = self.read();
+        state.tree.require_file(id)?;
+        Ok(state.revisions[&id]
+            .iter()
+            .map(|(revision, _)| revision.clone())
+            .collect())
+    }
+
+    fn get_revision_content(&self, id: u64, revision_id: u64) -> StorageResult<FileContent> {
+        let state = self.read();
+        state.tree.require_file(id)?;
+        state.revisions[&id]
+            .iter()
+            .find(|(revision, _)| revision.id == revision_id)
+            .map(|(_, body)| FileContent { body: body.clone() })
+            .ok_or_else(|| {
+                StorageError::NotFound(format!("revision {} of file with id: {}", revision_id, id))
+            })
+    }
+
+    fn delete_file(&self, id: u64, if_match: Option<&str>) -> StorageResult<File> {
+        let mut state = self.write();
+        state.tree.check_etag(id, if_match)?;
+        let file = state.tree.to_file(id).expect("file exists");
+        let state = &mut *state;
+        let now = unix_millis(SystemTime::now());
+        state.trash.put(&mut state.tree, ItemId::File(id), now)?;
+        Ok(file)
+    }
+
+    fn move_file(
+        &self,
+        id: u64,
+        collection_id: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<(File, Collection)> {
+        validate_name(name)?;
+        let mut state = self.write();
+        let replaced = state
+            .tree
+            .check_file_move(id, collection_id, name, overwrite)?;
+        let state = &mut *state;
+        if let Some(replaced) = replaced {
+            let now = unix_millis(SystemTime::now());
+            state
+                .trash
+                .put(&mut state.tree, ItemId::File(replaced), now)?;
+        }
+        state.tree.move_file(id, collection_id, name.to_string());
+        Ok((
+            state.tree.to_file(id).expect("file exists"),
+            state
+                .tree
+                .to_collection(collection_id)
+                .expect("collection exists"),
+        ))
+    }
+
+    fn create_collection(&self, parent_id: u64, name: &str) -> StorageResult<Collection> {
+        validate_name(name)?;
+        let mut state = self.write();
+        state.tree.check_name_free(parent_id, name)?;
+        let id = state
+            .tree
+            .insert_collection(Some(parent_id), name.to_string());
+        Ok(state
+            .tree
+            .to_collection(id)
+            .expect("collection was just inserted"))
+    }
+
+    fn rename_collection(&self, id: u64, name: &str) -> StorageResult<Collection> {
+        validate_name(name)?;
+        let mut state = self.write();
+        if let Some(parent) = state.tree.require_collection(id)?.parent {
+            state.tree.check_name_free(parent, name)?;
+        }
+        state.tree.rename_collection(id, name.to_string());
+        Ok(state.tree.to_collection(id).expect("collection exists"))
+    }
+
+    fn move_collection(&self, id: u64, parent_id: u64) -> StorageResult<Collection> {
+        let mut state = self.write();
+        state.tree.check_move(id, parent_id)?;
+        let node = state.tree.require_collection(id)?;
+        if node.parent != Some(parent_id) {
+            let name = node.name.clone();
+            state.tree.check_name_free(parent_id, &name)?;
+            state.tree.move_collection(id, parent_id);
+        }
+        Ok(state.tree.to_collection(id).expect("collection exists"))
+    }
+
+    fn delete_collection(&self, id: u64, recursive: bool) -> StorageResult<Collection> {
+        let mut state = self.write();
+        let node = state.tree.require_collection(id)?;
+        if !recursive && !node.is_empty() {
+            return Err(StorageError::Conflict(format!(
+                "collection with id: {} is not empty",
+                id
+            )));
+        }
+        let collection = state.tree.to_collection(id).expect("collection exists");
+        let state = &mut *state;
+        let now = unix_millis(SystemTime::now());
+        state
+            .trash
+            .put(&mut state.tree, ItemId::Collection(id), now)?;
+        Ok(collection)
+    }
+
+    fn list_trash(&self) -> StorageResult<Vec<TrashItem>> {
+        Ok(self.read().trash.list())
+    }
+
+    fn restore_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item> {
+        let mut state = self.write();
+        let state = &mut *state;
+        let parent = state.trash.check_restore(&state.tree, item, parent_id)?;
+        state.trash.restore(&mut state.tree, item, parent);
+        Ok(state.tree.to_item(item).expect("item was just restored"))
+    }
+
+    fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem> {
+        let mut state = self.write();
+        let purged = state.trash.to_trash_item(item)?;
+        let (collections, files) = state.trash.purge(item)?;
+        for collection in collections {
+            state.acls.remove(&collection);
+        }
+        for file in files {
+            state.contents.remove(&file);
+            state.revisions.remove(&file);
+        }
+        Ok(purged)
+    }
+
+    fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+        self.read().tree.resolve_path(path)
+    }
+
+    fn get_path(&self, item: ItemId) -> StorageResult<String> {
+        self.read().tree.path_of(item)
+    }
+
+    fn get_user(&self, username: &str) -> StorageResult<User> {
+        self.read()
+            .users
+            .get(username)
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(format!("user: {}", username)))
+    }
+
+    fn create_user(&self, user: User) -> StorageResult<User> {
+        validate_username(&user.username)?;
+        let mut state = self.write();
+        if state.users.contains_key(&user.username) {
+            return Err(StorageError::Conflict(format!(
+                "user {} already exists",
+                user.username
+            )));
         }
+        state.users.insert(user.username.clone(), user.clone());
+        Ok(user)
+    }
+
+    fn get_acls(&self) -> StorageResult<HashMap<u64, Vec<AclEntry>>> {
+        Ok(self.read().acls.clone())
     }
 
-    fn get_file(&self, id: u64) -> Result<File, ()> {
-        let index: usize = id.try_into().unwrap();
-        if index < self.hard_coded_files.len() {
-            Ok(self.hard_coded_files[index].clone())
+    fn set_acl(&self, id: u64, entries: Vec<AclEntry>) -> StorageResult<()> {
+        let mut state = self.write();
+        state.tree.require_collection(id)?;
+        if entries.is_empty() {
+            state.acls.remove(&id);
         } else {
-            Err(())
+            state.acls.insert(id, entries);
         }
+        Ok(())
     }
+}
 
-    fn get_file_content(&self, id: u64) -> Result<FileContent, ()> {
-        match self.get_file(id) {
-            Ok(file) => {
-                let body = format!("# {} content", file.name).as_bytes().to_vec();
-                Ok(FileContent { body })
-            }
-            Err(_) => Err(()),
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use tonic::{Code, Status};
+
+    use super::{
+        conformance, file_type, validate_name, validate_username, HardCodedStorage, ItemId,
+        Storage, StorageError, User,
+    };
+    use crate::oxygen::FileType;
+
+    #[test]
+    fn io_errors_map_to_storage_errors() {
+        let cases = [
+            (io::ErrorKind::NotFound, "NotFound"),
+            (io::ErrorKind::PermissionDenied, "PermissionDenied"),
+            (io::ErrorKind::AlreadyExists, "Conflict"),
+            (io::ErrorKind::InvalidData, "Corrupt"),
+            (io::ErrorKind::Other, "Io"),
+        ];
+        for (kind, expected) in cases {
+            let err = StorageError::from(io::Error::new(kind, "test"));
+            assert!(format!("{:?}", err).starts_with(expected), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn storage_errors_map_to_status_codes() {
+        let cases = [
+            (StorageError::NotFound("x".to_string()), Code::NotFound),
+            (
+                StorageError::PermissionDenied("x".to_string()),
+                Code::PermissionDenied,
+            ),
+            (StorageError::Io("x".to_string()), Code::Internal),
+            (StorageError::Conflict("x".to_string()), Code::Aborted),
+            (
+                StorageError::InvalidName("x".to_string()),
+                Code::InvalidArgument,
+            ),
+            (StorageError::Corrupt("x".to_string()), Code::DataLoss),
+            (
+                StorageError::Stale(Box::default()),
+                Code::FailedPrecondition,
+            ),
+        ];
+        for (err, code) in cases {
+            assert_eq!(Status::from(err).code(), code);
+        }
+    }
+
+    #[test]
+    fn names_must_be_a_single_path_component() {
+        for name in ["note.md", "f 2.md", "día 1.md", ".hidden"] {
+            assert!(validate_name(name).is_ok(), "{:?} should be valid", name);
+        }
+        for name in ["", ".", "..", "a/b.md", "a\\b.md", "a\0b"] {
+            assert!(
+                matches!(validate_name(name), Err(StorageError::InvalidName(_))),
+                "{:?} should be invalid",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn usernames_are_restricted_to_a_few_characters() {
+        for username in ["alice", "bob.smith", "carol_2", "d-e"] {
+            assert!(validate_username(username).is_ok(), "{:?}", username);
+        }
+        for username in ["", "a b", "a:b", "a\nb", "día", &"a".repeat(65)] {
+            assert!(
+                matches!(
+                    validate_username(username),
+                    Err(StorageError::InvalidName(_))
+                ),
+                "{:?} should be invalid",
+                username
+            );
+        }
+    }
+
+    #[test]
+    fn hard_coded_storage_keeps_users() {
+        let storage = HardCodedStorage::new();
+        let alice = User {
+            username: "alice".to_string(),
+            password_hash: "hash".to_string(),
+        };
+        assert!(matches!(
+            storage.get_user("alice"),
+            Err(StorageError::NotFound(_))
+        ));
+        storage
+            .create_user(alice.clone())
+            .expect("failed to create user");
+        assert_eq!(storage.get_user("alice"), Ok(alice.clone()));
+        assert!(matches!(
+            storage.create_user(alice),
+            Err(StorageError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn items_can_be_addressed_by_path() {
+        let storage = HardCodedStorage::new();
+        let cases = [
+            ("", ItemId::Collection(4)),
+            ("/collection 3/", ItemId::Collection(3)),
+            (
+                "collection 3/collection 1/collection_1",
+                ItemId::Collection(0),
+            ),
+            ("collection 3/collection 2/f 3.md", ItemId::File(1)),
+            ("f_1.md", ItemId::File(3)),
+        ];
+        for (path, item) in cases {
+            assert_eq!(storage.resolve_path(path), Ok(item), "{:?}", path);
+            assert_eq!(
+                storage.get_path(item),
+                Ok(path.trim_matches('/').to_string())
+            );
+        }
+        for path in ["missing.md", "f_1.md/f_1.md", "collection 3/f 3.md"] {
+            assert!(
+                matches!(storage.resolve_path(path), Err(StorageError::NotFound(_))),
+                "{:?}",
+                path
+            );
+        }
+        for path in ["collection 3//f 2.md", "collection 3/../f_1.md"] {
+            assert!(
+                matches!(
+                    storage.resolve_path(path),
+                    Err(StorageError::InvalidArgument(_))
+                ),
+                "{:?}",
+                path
+            );
         }
+        assert!(matches!(
+            storage.get_path(ItemId::File(100)),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn paths_matching_several_items_are_ambiguous() {
+        let storage = HardCodedStorage::new();
+        storage
+            .write()
+            .tree
+            .insert_collection_with_id(5, None, "other root".to_string());
+        storage
+            .create_file(5, "f_1.md", vec![], "")
+            .expect("failed to create file");
+        let Err(StorageError::InvalidArgument(message)) = storage.resolve_path("f_1.md") else {
+            panic!("expected the path to be ambiguous");
+        };
+        assert!(message.contains("file with id: 3"), "{}", message);
+    }
+
+    #[test]
+    fn file_types_are_guessed_from_names() {
+        let cases = [
+            ("note.md", FileType::Markdown),
+            ("plan 2.MD", FileType::Markdown),
+            ("logo.png", FileType::Image),
+            ("photo.JPEG", FileType::Image),
+            ("paper.pdf", FileType::Pdf),
+            ("notes.txt", FileType::Other),
+            ("Makefile", FileType::Other),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(file_type(name), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn hard_coded_storage_supports_writes() {
+        let storage = HardCodedStorage::new();
+        let file = storage
+            .create_file(1, "new.md", b"# new".to_vec(), "")
+            .expect("failed to create file");
+        assert_eq!(storage.get_file_content(file.id).unwrap().body, b"# new");
+        assert_eq!(file.size, 5);
+        assert_eq!(file.file_type(), FileType::Markdown);
+        assert!(file.created > 0);
+        assert!(matches!(
+            storage.create_file(1, "new.md", vec![], ""),
+            Err(StorageError::Conflict(_))
+        ));
+        let updated = storage
+            .update_file_content(file.id, b"# updated".to_vec(), "", None)
+            .expect("failed to update file");
+        assert_eq!(updated.size, 9);
+        assert_eq!(updated.created, file.created);
+        assert_ne!(updated.sha256, file.sha256);
+        assert_eq!(
+            storage.get_file_content(file.id).unwrap().body,
+            b"# updated"
+        );
+        storage
+            .delete_file(file.id, None)
+            .expect("failed to delete file");
+        assert!(matches!(
+            storage.get_file_content(file.id),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.delete_file(file.id, None),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn hard_coded_storage_supports_collection_changes() {
+        let storage = HardCodedStorage::new();
+        let created = storage
+            .create_collection(4, "new")
+            .expect("failed to create collection");
+        assert!(storage
+            .get_collection(4)
+            .unwrap()
+            .child_collections
+            .contains(&created));
+        assert!(matches!(
+            storage.create_collection(4, "collection 3"),
+            Err(StorageError::Conflict(_))
+        ));
+
+        let renamed = storage
+            .rename_collection(created.id, "renamed")
+            .expect("failed to rename collection");
+        assert_eq!(renamed.name, "renamed");
+        assert_eq!(renamed.id, created.id);
+
+        // collection 3 -> collection 1 would put collection 1 inside itself
+        assert!(matches!(
+            storage.move_collection(3, 1),
+            Err(StorageError::InvalidArgument(_))
+        ));
+        let moved = storage
+            .move_collection(2, created.id)
+            .expect("failed to move collection");
+        assert_eq!(moved.id, 2);
+        assert_eq!(moved.files.len(), 2);
+        assert_eq!(
+            storage
+                .get_collection(created.id)
+                .unwrap()
+                .child_collections,
+            vec![moved]
+        );
+        assert!(matches!(
+            storage.delete_collection(created.id, false),
+            Err(StorageError::Conflict(_))
+        ));
+        storage
+            .delete_collection(created.id, true)
+            .expect("failed to delete collection");
+        for id in [created.id, 2] {
+            assert!(matches!(
+                storage.get_collection(id),
+                Err(StorageError::NotFound(_))
+            ));
+        }
+        // files of collection 2
+        for id in [1, 2] {
+            assert!(matches!(
+                storage.get_file_content(id),
+                Err(StorageError::NotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn hard_coded_storage_supports_moving_files() {
+        let storage = HardCodedStorage::new();
+        // f 3.md from collection 2 to collection 1
+        let (file, collection) = storage
+            .move_file(1, 1, "moved.md", false)
+            .expect("failed to move file");
+        assert_eq!(file.id, 1);
+        assert_eq!(file.name, "moved.md");
+        assert_eq!(collection.files, vec![file.clone()]);
+        assert!(!storage.get_collection(2).unwrap().files.contains(&file));
+        assert_eq!(
+            storage.get_file_content(1).unwrap().body,
+            b"# f 3.md content"
+        );
+
+        // f_4.md onto moved.md
+        assert!(matches!(
+            storage.move_file(2, 1, "moved.md", false),
+            Err(StorageError::Conflict(_))
+        ));
+        let (file, collection) = storage
+            .move_file(2, 1, "moved.md", true)
+            .expect("failed to overwrite file");
+        assert_eq!(file.id, 2);
+        assert_eq!(collection.files, vec![file]);
+        assert!(matches!(
+            storage.get_file(1),
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(
+            storage.get_file_content(2).unwrap().body,
+            b"# f_4.md content"
+        );
+    }
+
+    #[test]
+    fn file_ranges_can_be_read() {
+        let storage = HardCodedStorage::new();
+        // f 2.md
+        assert_eq!(storage.read_file_range(0, 2, Some(6)).unwrap(), b"f 2.md");
+        assert_eq!(storage.read_file_range(0, 9, None).unwrap(), b"content");
+        assert_eq!(
+            storage.read_file_range(0, 9, Some(100)).unwrap(),
+            b"content"
+        );
+        assert!(storage.read_file_range(0, 100, Some(4)).unwrap().is_empty());
+        assert!(matches!(
+            storage.read_file_range(100, 0, None),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn hard_coded_storage_conforms() {
+        conformance::check(&HardCodedStorage::new());
     }
 }
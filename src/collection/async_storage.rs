@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use super::{search::Searchable, Change, ItemId, Storage, StorageError, StorageResult, User};
+use crate::oxygen::{
+    trash_item, AclEntry, Collection, File, FileContent, Revision, SearchHit, TrashItem,
+};
+
+/// [`Storage`] as seen from async code (ex: the gRPC handlers).
+///
+/// Methods that can block on I/O run on tokio's blocking thread pool, so that a
+/// slow disk or database doesn't hold up the runtime threads serving the other
+/// clients. Methods reading the collection tree are expected to answer from
+/// memory, without waiting for writes in progress, and are called as is.
+pub struct AsyncStorage<S> {
+    storage: Arc<S>,
+}
+
+// not derived, that would require the storage to be `Clone`
+impl<S> Clone for AsyncStorage<S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: Arc::clone(&self.storage),
+        }
+    }
+}
+
+impl<S: Storage + Send + Sync + 'static> AsyncStorage<S> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage: Arc::new(storage),
+        }
+    }
+
+    /// Runs `call` on the blocking thread pool
+    async fn run<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(&S) -> StorageResult<T> + Send + 'static,
+    ) -> StorageResult<T> {
+        let storage = Arc::clone(&self.storage);
+        tokio::task::spawn_blocking(move || call(&storage))
+            .await
+            .map_err(|err| StorageError::Io(format!("storage task failed: {}", err)))?
+    }
+
+    pub fn get_collection_all(&self) -> Vec<Collection> {
+        self.storage.get_collection_all()
+    }
+
+    pub fn get_collection(&self, id: u64) -> StorageResult<Collection> {
+        self.storage.get_collection(id)
+    }
+
+    pub fn get_file(&self, id: u64) -> StorageResult<File> {
+        self.storage.get_file(id)
+    }
+
+    pub fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>> {
+        self.storage.collection_lineage(id)
+    }
+
+    pub fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+        self.storage.resolve_path(path)
+    }
+
+    pub fn get_path(&self, item: ItemId) -> StorageResult<String> {
+        self.storage.get_path(item)
+    }
+
+    pub fn changes(&self) -> Option<broadcast::Receiver<Change>> {
+        self.storage.changes()
+    }
+
+    pub async fn get_file_content(&self, id: u64) -> StorageResult<FileContent> {
+        self.run(move |storage| storage.get_file_content(id)).await
+    }
+
+    pub async fn read_file_range(
+        &self,
+        id: u64,
+        offset: u64,
+        length: Option<u64>,
+    ) -> StorageResult<Vec<u8>> {
+        self.run(move |storage| storage.read_file_range(id, offset, length))
+            .await
+    }
+
+    pub async fn create_file(
+        &self,
+        collection_id: u64,
+        name: &str,
+        body: Vec<u8>,
+        author: &str,
+    ) -> StorageResult<File> {
+        let (name, author) = (name.to_string(), author.to_string());
+        self.run(move |storage| storage.create_file(collection_id, &name, body, &author))
+            .await
+    }
+
+    pub async fn update_file_content(
+        &self,
+        id: u64,
+        body: Vec<u8>,
+        author: &str,
+        if_match: Option<&str>,
+    ) -> StorageResult<File> {
+        let (author, if_match) = (author.to_string(), if_match.map(str::to_string));
+        self.run(move |storage| storage.update_file_content(id, body, &author, if_match.as_deref()))
+            .await
+    }
+
+    pub async fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>> {
+        self.run(move |storage| storage.list_revisions(id)).await
+    }
+
+    pub async fn get_revision_content(
+        &self,
+        id: u64,
+        revision_id: u64,
+    ) -> StorageResult<FileContent> {
+        self.run(move |storage| storage.get_revision_content(id, revision_id))
+            .await
+    }
+
+    pub async fn restore_revision(
+        &self,
+        id: u64,
+        revision_id: u64,
+        author: &str,
+    ) -> StorageResult<File> {
+        let author = author.to_string();
+        self.run(move |storage| storage.restore_revision(id, revision_id, &author))
+            .await
+    }
+
+    pub async fn delete_file(&self, id: u64, if_match: Option<&str>) -> StorageResult<File> {
+        let if_match = if_match.map(str::to_string);
+        self.run(move |storage| storage.delete_file(id, if_match.as_deref()))
+            .await
+    }
+
+    pub async fn move_file(
+        &self,
+        id: u64,
+        collection_id: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<(File, Collection)> {
+        let name = name.to_string();
+        self.run(move |storage| storage.move_file(id, collection_id, &name, overwrite))
+            .await
+    }
+
+    pub async fn create_collection(&self, parent_id: u64, name: &str) -> StorageResult<Collection> {
+        let name = name.to_string();
+        self.run(move |storage| storage.create_collection(parent_id, &name))
+            .await
+    }
+
+    pub async fn rename_collection(&self, id: u64, name: &str) -> StorageResult<Collection> {
+        let name = name.to_string();
+        self.run(move |storage| storage.rename_collection(id, &name))
+            .await
+    }
+
+    pub async fn move_collection(&self, id: u64, parent_id: u64) -> StorageResult<Collection> {
+        self.run(move |storage| storage.move_collection(id, parent_id))
+            .await
+    }
+
+    pub async fn delete_collection(&self, id: u64, recursive: bool) -> StorageResult<Collection> {
+        self.run(move |storage| storage.delete_collection(id, recursive))
+            .await
+    }
+
+    pub async fn list_trash(&self) -> StorageResult<Vec<TrashItem>> {
+        self.run(|storage| storage.list_trash()).await
+    }
+
+    pub async fn restore_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item> {
+        self.run(move |storage| storage.restore_trash(item, parent_id))
+            .await
+    }
+
+    pub async fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem> {
+        self.run(move |storage| storage.purge_trash(item)).await
+    }
+
+    pub async fn purge_trash_before(&self, cutoff: u64) -> StorageResult<Vec<TrashItem>> {
+        self.run(move |storage| storage.purge_trash_before(cutoff))
+            .await
+    }
+
+    pub async fn get_user(&self, username: &str) -> StorageResult<User> {
+        let username = username.to_string();
+        self.run(move |storage| storage.get_user(&username)).await
+    }
+
+    pub async fn create_user(&self, user: User) -> StorageResult<User> {
+        self.run(move |storage| storage.create_user(user)).await
+    }
+
+    pub async fn set_acl(&self, id: u64, entries: Vec<AclEntry>) -> StorageResult<()> {
+        self.run(move |storage| storage.set_acl(id, entries)).await
+    }
+}
+
+impl<S: Storage + Send + Sync + 'static> AsyncStorage<Searchable<S>> {
+    pub async fn search(
+        &self,
+        query: String,
+        limit: usize,
+        visible: impl Fn(&File) -> bool + Send + 'static,
+    ) -> StorageResult<Vec<SearchHit>> {
+        self.run(move |storage| storage.search(&query, limit, visible))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::AsyncStorage;
+    use crate::collection::{HardCodedStorage, Storage, StorageError, StorageResult};
+
+    #[tokio::test]
+    async fn blocking_calls_run_off_the_runtime_thread() {
+        let storage = AsyncStorage::new(HardCodedStorage::new());
+        let runtime_thread = thread::current().id();
+        let storage_thread = storage
+            .run(|_| StorageResult::Ok(thread::current().id()))
+            .await
+            .unwrap();
+        assert_ne!(storage_thread, runtime_thread);
+
+        let file = storage
+            .update_file_content(0, b"# updated".to_vec(), "", None)
+            .await
+            .unwrap();
+        assert_eq!(storage.storage.get_file(0), Ok(file));
+        assert_eq!(
+            storage.get_file_content(0).await.unwrap().body,
+            b"# updated"
+        );
+        assert!(matches!(
+            storage.delete_file(42, None).await,
+            Err(StorageError::NotFound(_))
+        ));
+    }
+}
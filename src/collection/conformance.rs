@@ -0,0 +1,636 @@
+//! Behaviors every [`Storage`] backend must share. Each backend runs the suite
+//! from its own tests with [`check`].
+//!
+//! The checks don't assume anything about what the storage starts with besides
+//! having a root collection, they work in collections of their own under it.
+//! Names end with `.md` so that backends restricted to markdown files take them.
+use std::collections::HashSet;
+
+use super::{trash_item_id, ItemId, Storage, StorageError, StorageResult, User};
+use crate::oxygen::{acl_entry::Principal, trash_item, AclEntry, Collection, File, Permission};
+
+/// Runs every check against `storage`
+pub fn check<S: Storage>(storage: &S) {
+    files_round_trip_their_content(storage);
+    files_can_be_moved_and_deleted(storage);
+    revisions_are_recorded(storage);
+    stale_writes_are_rejected(storage);
+    collections_can_be_renamed_moved_and_deleted(storage);
+    deleted_items_can_be_restored_and_purged(storage);
+    names_are_validated(storage);
+    missing_items_are_not_found(storage);
+    users_are_unique(storage);
+    acls_are_kept_until_purged(storage);
+    tree_is_consistent(storage);
+}
+
+/// Id of a root collection of `storage`
+fn root<S: Storage>(storage: &S) -> u64 {
+    storage
+        .get_collection_all()
+        .into_iter()
+        .map(|collection| collection.id)
+        .find(|id| storage.collection_lineage(*id) == Ok(vec![*id]))
+        .expect("storages have a root collection")
+}
+
+/// New collection for a check to work in
+fn scratch<S: Storage>(storage: &S, name: &str) -> Collection {
+    storage
+        .create_collection(root(storage), name)
+        .unwrap_or_else(|err| panic!("failed to create collection {:?}: {}", name, err))
+}
+
+pub fn files_round_trip_their_content<S: Storage>(storage: &S) {
+    let collection = scratch(storage, "round trip");
+    // not valid utf-8
+    let body = b"# notes\n\xff\xfe\0 end".to_vec();
+    let file = storage
+        .create_file(collection.id, "notes.md", body.clone(), "")
+        .expect("failed to create file");
+    assert_eq!(file.name, "notes.md");
+    assert_eq!(file.collection_id, collection.id);
+    assert_eq!(file.size, body.len() as u64);
+    assert_eq!(storage.get_file(file.id), Ok(file.clone()));
+    assert_eq!(storage.get_file_content(file.id).unwrap().body, body);
+    assert_eq!(
+        storage.get_collection(collection.id).unwrap().files,
+        vec![file.clone()]
+    );
+
+    let mut read = vec![];
+    let mut offset = 0;
+    loop {
+        let chunk = storage
+            .read_file_range(file.id, offset, Some(3))
+            .expect("failed to read range");
+        if chunk.is_empty() {
+            break;
+        }
+        offset += chunk.len() as u64;
+        read.extend(chunk);
+    }
+    assert_eq!(read, body);
+    assert_eq!(
+        storage.read_file_range(file.id, 2, None).unwrap(),
+        body[2..]
+    );
+    assert!(storage
+        .read_file_range(file.id, body.len() as u64 + 1, Some(3))
+        .unwrap()
+        .is_empty());
+
+    let updated = storage
+        .update_file_content(file.id, vec![], "", None)
+        .expect("failed to update file");
+    assert_eq!(updated.id, file.id);
+    assert_eq!(updated.size, 0);
+    assert_eq!(updated.created, file.created);
+    assert!(updated.modified >= file.modified);
+    assert_ne!(updated.sha256, file.sha256);
+    assert!(storage.get_file_content(file.id).unwrap().body.is_empty());
+    assert_eq!(storage.get_file(file.id), Ok(updated));
+}
+
+pub fn files_can_be_moved_and_deleted<S: Storage>(storage: &S) {
+    let from = scratch(storage, "move from");
+    let to = scratch(storage, "move to");
+    let file = storage
+        .create_file(from.id, "moved.md", b"# moved".to_vec(), "")
+        .expect("failed to create file");
+    let existing = storage
+        .create_file(to.id, "existing.md", b"# existing".to_vec(), "")
+        .expect("failed to create file");
+
+    let (moved, collection) = storage
+        .move_file(file.id, to.id, "renamed.md", false)
+        .expect("failed to move file");
+    assert_eq!(moved.id, file.id);
+    assert_eq!(moved.name, "renamed.md");
+    assert_eq!(moved.collection_id, to.id);
+    assert_eq!(collection.id, to.id);
+    assert!(collection.files.contains(&moved));
+    assert!(storage.get_collection(from.id).unwrap().files.is_empty());
+    assert_eq!(storage.get_file_content(file.id).unwrap().body, b"# moved");
+
+    assert!(matches!(
+        storage.move_file(file.id, to.id, "existing.md", false),
+        Err(StorageError::Conflict(_))
+    ));
+    storage
+        .move_file(file.id, to.id, "existing.md", true)
+        .expect("failed to overwrite file");
+    assert!(matches!(
+        storage.get_file(existing.id),
+        Err(StorageError::NotFound(_))
+    ));
+    assert_eq!(storage.get_file_content(file.id).unwrap().body, b"# moved");
+    // the replaced file went to the trash, as if it had been deleted
+    let trash = storage.list_trash().expect("failed to list the trash");
+    let trashed = trash
+        .iter()
+        .find(|item| trash_item_id(item) == Some(ItemId::File(existing.id)))
+        .expect("replaced file is in the trash");
+    assert_eq!(
+        trashed.path,
+        storage.get_path(ItemId::Collection(to.id)).unwrap() + "/existing.md"
+    );
+    storage
+        .restore_trash(ItemId::File(existing.id), Some(from.id))
+        .expect("failed to restore the replaced file");
+    assert_eq!(
+        storage.get_file_content(existing.id).unwrap().body,
+        b"# existing"
+    );
+    assert_eq!(storage.list_revisions(existing.id).unwrap().len(), 1);
+
+    let deleted = storage
+        .delete_file(file.id, None)
+        .expect("failed to delete file");
+    assert_eq!(deleted.id, file.id);
+    assert!(matches!(
+        storage.get_file(file.id),
+        Err(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+        storage.get_file_content(file.id),
+        Err(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+        storage.delete_file(file.id, None),
+        Err(StorageError::NotFound(_))
+    ));
+    // ids aren't handed out again
+    let recreated = storage
+        .create_file(from.id, "moved.md", b"# moved".to_vec(), "")
+        .expect("failed to create file");
+    assert_ne!(recreated.id, file.id);
+    assert_ne!(recreated.id, existing.id);
+}
+
+pub fn revisions_are_recorded<S: Storage>(storage: &S) {
+    let collection = scratch(storage, "revisions");
+    let file = storage
+        .create_file(collection.id, "history.md", b"# first".to_vec(), "alice")
+        .expect("failed to create file");
+    let updated = storage
+        .update_file_content(file.id, b"# second".to_vec(), "bob", None)
+        .expect("failed to update file");
+    let revisions = storage
+        .list_revisions(file.id)
+        .expect("failed to list revisions");
+    assert_eq!(revisions.len(), 2);
+    for (revision, (author, content)) in revisions.iter().zip([("alice", &file), ("bob", &updated)])
+    {
+        assert_eq!(revision.file_id, file.id);
+        assert_eq!(revision.author, author);
+        assert_eq!(revision.sha256, content.sha256);
+        assert_eq!(revision.size, content.size);
+    }
+    assert!(revisions[0].id < revisions[1].id);
+    assert!(revisions[0].created <= revisions[1].created);
+    assert_eq!(
+        storage
+            .get_revision_content(file.id, revisions[0].id)
+            .unwrap()
+            .body,
+        b"# first"
+    );
+
+    let restored = storage
+        .restore_revision(file.id, revisions[0].id, "carol")
+        .expect("failed to restore revision");
+    assert_eq!(restored.sha256, file.sha256);
+    assert_eq!(storage.get_file_content(file.id).unwrap().body, b"# first");
+    let after = storage.list_revisions(file.id).unwrap();
+    assert_eq!(after[..2], revisions[..]);
+    assert_eq!(after.len(), 3);
+    assert_eq!(after[2].author, "carol");
+    assert_eq!(after[2].sha256, file.sha256);
+
+    let missing = after[2].id + 1;
+    assert!(matches!(
+        storage.get_revision_content(file.id, missing),
+        Err(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+        storage.restore_revision(file.id, missing, "carol"),
+        Err(StorageError::NotFound(_))
+    ));
+    // revisions go to the trash with the file, another file taking its place
+    // starts afresh
+    storage
+        .delete_file(file.id, None)
+        .expect("failed to delete file");
+    assert!(matches!(
+        storage.list_revisions(file.id),
+        Err(StorageError::NotFound(_))
+    ));
+    let recreated = storage
+        .create_file(collection.id, "history.md", b"# again".to_vec(), "alice")
+        .expect("failed to create file");
+    assert_eq!(storage.list_revisions(recreated.id).unwrap().len(), 1);
+}
+
+pub fn stale_writes_are_rejected<S: Storage>(storage: &S) {
+    let collection = scratch(storage, "etags");
+    let file = storage
+        .create_file(collection.id, "shared.md", b"# shared".to_vec(), "alice")
+        .expect("failed to create file");
+    let updated = storage
+        .update_file_content(file.id, b"# alice".to_vec(), "alice", Some(&file.etag))
+        .expect("failed to update file at its current version");
+    assert_ne!(updated.etag, file.etag);
+
+    let stale = |result: StorageResult<File>| match result {
+        Err(StorageError::Stale(current)) => assert_eq!(*current, updated),
+        result => panic!("expected a stale write, got {:?}", result),
+    };
+    stale(storage.update_file_content(file.id, b"# bob".to_vec(), "bob", Some(&file.etag)));
+    stale(storage.delete_file(file.id, Some(&file.etag)));
+    assert_eq!(storage.get_file_content(file.id).unwrap().body, b"# alice");
+
+    // writing the same content again doesn't make other writes stale
+    let rewritten = storage
+        .update_file_content(file.id, b"# alice".to_vec(), "alice", None)
+        .expect("failed to update file");
+    assert_eq!(rewritten.etag, updated.etag);
+    storage
+        .delete_file(file.id, Some(&updated.etag))
+        .expect("failed to delete file at its current version");
+}
+
+pub fn collections_can_be_renamed_moved_and_deleted<S: Storage>(storage: &S) {
+    let parent = scratch(storage, "parent");
+    let child = storage
+        .create_collection(parent.id, "child")
+        .expect("failed to create collection");
+    let file = storage
+        .create_file(child.id, "inside.md", b"# inside".to_vec(), "")
+        .expect("failed to create file");
+    assert_eq!(
+        storage.get_collection(parent.id).unwrap().child_collections,
+        vec![storage.get_collection(child.id).unwrap()]
+    );
+
+    let renamed = storage
+        .rename_collection(child.id, "renamed")
+        .expect("failed to rename collection");
+    assert_eq!(renamed.id, child.id);
+    assert_eq!(renamed.name, "renamed");
+    assert_eq!(storage.get_file(file.id).unwrap().collection_id, child.id);
+
+    let other = scratch(storage, "other parent");
+    assert!(matches!(
+        storage.rename_collection(other.id, "parent"),
+        Err(StorageError::Conflict(_))
+    ));
+    let moved = storage
+        .move_collection(child.id, other.id)
+        .expect("failed to move collection");
+    assert_eq!(moved.id, child.id);
+    assert_eq!(moved.files[0].id, file.id);
+    assert_eq!(
+        storage.collection_lineage(child.id).unwrap(),
+        [
+            storage.collection_lineage(other.id).unwrap(),
+            vec![child.id]
+        ]
+        .concat()
+    );
+    assert!(storage
+        .get_collection(parent.id)
+        .unwrap()
+        .child_collections
+        .is_empty());
+    assert_eq!(storage.get_file_content(file.id).unwrap().body, b"# inside");
+    assert!(matches!(
+        storage.move_collection(other.id, child.id),
+        Err(StorageError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        storage.move_collection(other.id, other.id),
+        Err(StorageError::InvalidArgument(_))
+    ));
+
+    assert!(matches!(
+        storage.delete_collection(other.id, false),
+        Err(StorageError::Conflict(_))
+    ));
+    let deleted = storage
+        .delete_collection(other.id, true)
+        .expect("failed to delete collection");
+    assert_eq!(deleted.child_collections[0].id, child.id);
+    for item in [other.id, child.id] {
+        assert!(matches!(
+            storage.get_collection(item),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+    assert!(matches!(
+        storage.get_file(file.id),
+        Err(StorageError::NotFound(_))
+    ));
+    storage
+        .delete_collection(parent.id, false)
+        .expect("failed to delete empty collection");
+}
+
+pub fn deleted_items_can_be_restored_and_purged<S: Storage>(storage: &S) {
+    let collection = scratch(storage, "trash");
+    let lineage = storage.collection_lineage(collection.id).unwrap();
+    let child = storage
+        .create_collection(collection.id, "child")
+        .expect("failed to create collection");
+    let inside = storage
+        .create_file(child.id, "inside.md", b"# inside".to_vec(), "alice")
+        .expect("failed to create file");
+    let file = storage
+        .create_file(collection.id, "deleted.md", b"# deleted".to_vec(), "alice")
+        .expect("failed to create file");
+    storage
+        .update_file_content(file.id, b"# changed".to_vec(), "bob", None)
+        .expect("failed to update file");
+    let revisions = storage.list_revisions(file.id).unwrap();
+    storage
+        .delete_file(file.id, None)
+        .expect("failed to delete file");
+    storage
+        .delete_collection(child.id, true)
+        .expect("failed to delete collection");
+
+    let trash = storage.list_trash().expect("failed to list the trash");
+    let trashed = |item| {
+        trash
+            .iter()
+            .find(|trashed| trash_item_id(trashed) == Some(item))
+            .unwrap_or_else(|| panic!("{} should be in the trash", item))
+    };
+    let trashed_file = trashed(ItemId::File(file.id));
+    assert_eq!(trashed_file.lineage, lineage);
+    assert_eq!(
+        trashed_file.path,
+        storage.get_path(ItemId::Collection(collection.id)).unwrap() + "/deleted.md"
+    );
+    let Some(trash_item::Item::File(view)) = &trashed_file.item else {
+        panic!("expected a file, got {:?}", trashed_file.item);
+    };
+    assert_eq!(view.collection_id, collection.id);
+    assert_eq!(view.sha256, revisions[1].sha256);
+    let trashed_child = trashed(ItemId::Collection(child.id));
+    assert!(trashed_file.deleted <= trashed_child.deleted);
+    let Some(trash_item::Item::Collection(view)) = &trashed_child.item else {
+        panic!("expected a collection, got {:?}", trashed_child.item);
+    };
+    assert_eq!(view.files[0].id, inside.id);
+
+    // something took the name of the file in the meantime
+    let taken = storage
+        .create_file(collection.id, "deleted.md", vec![], "")
+        .expect("failed to create file");
+    assert!(matches!(
+        storage.restore_trash(ItemId::File(file.id), None),
+        Err(StorageError::Conflict(_))
+    ));
+    storage.delete_file(taken.id, None).unwrap();
+    storage.purge_trash(ItemId::File(taken.id)).unwrap();
+    let Ok(trash_item::Item::File(restored)) = storage.restore_trash(ItemId::File(file.id), None)
+    else {
+        panic!("failed to restore file");
+    };
+    assert_eq!(restored.id, file.id);
+    assert_eq!(restored.collection_id, collection.id);
+    assert_eq!(
+        storage.get_file_content(file.id).unwrap().body,
+        b"# changed"
+    );
+    assert_eq!(storage.list_revisions(file.id), Ok(revisions));
+    assert!(matches!(
+        storage.restore_trash(ItemId::File(file.id), None),
+        Err(StorageError::NotFound(_))
+    ));
+
+    // collections come back with everything in them, wherever asked
+    let elsewhere = scratch(storage, "restored");
+    assert!(matches!(
+        storage.restore_trash(ItemId::Collection(child.id), Some(inside.id + 1000)),
+        Err(StorageError::NotFound(_))
+    ));
+    let Ok(trash_item::Item::Collection(restored)) =
+        storage.restore_trash(ItemId::Collection(child.id), Some(elsewhere.id))
+    else {
+        panic!("failed to restore collection");
+    };
+    assert_eq!(restored.id, child.id);
+    assert_eq!(restored.files[0].id, inside.id);
+    assert_eq!(
+        storage.collection_lineage(child.id).unwrap(),
+        [
+            storage.collection_lineage(elsewhere.id).unwrap(),
+            vec![child.id]
+        ]
+        .concat()
+    );
+    assert_eq!(
+        storage.get_file_content(inside.id).unwrap().body,
+        b"# inside"
+    );
+
+    storage
+        .delete_collection(elsewhere.id, true)
+        .expect("failed to delete collection");
+    let purged = storage
+        .purge_trash(ItemId::Collection(elsewhere.id))
+        .expect("failed to purge collection");
+    assert_eq!(
+        trash_item_id(&purged),
+        Some(ItemId::Collection(elsewhere.id))
+    );
+    assert!(matches!(
+        storage.purge_trash(ItemId::Collection(elsewhere.id)),
+        Err(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+        storage.restore_trash(ItemId::Collection(child.id), None),
+        Err(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+        storage.list_revisions(inside.id),
+        Err(StorageError::NotFound(_))
+    ));
+    assert!(storage
+        .list_trash()
+        .unwrap()
+        .iter()
+        .all(|trashed| trash_item_id(trashed) != Some(ItemId::Collection(elsewhere.id))));
+}
+
+pub fn names_are_validated<S: Storage>(storage: &S) {
+    let collection = scratch(storage, "names");
+    storage
+        .create_file(collection.id, "taken.md", vec![], "")
+        .expect("failed to create file");
+    for name in ["", "..", "a/b.md", "a\\b.md"] {
+        assert!(
+            matches!(
+                storage.create_file(collection.id, name, vec![], ""),
+                Err(StorageError::InvalidName(_))
+            ),
+            "{:?} should be rejected",
+            name
+        );
+        assert!(
+            matches!(
+                storage.create_collection(collection.id, name),
+                Err(StorageError::InvalidName(_))
+            ),
+            "{:?} should be rejected",
+            name
+        );
+    }
+    // files and collections share names
+    assert!(matches!(
+        storage.create_file(collection.id, "taken.md", vec![], ""),
+        Err(StorageError::Conflict(_))
+    ));
+    assert!(matches!(
+        storage.create_collection(collection.id, "taken.md"),
+        Err(StorageError::Conflict(_))
+    ));
+}
+
+pub fn missing_items_are_not_found<S: Storage>(storage: &S) {
+    let missing = u64::MAX;
+    let not_found = |result: Result<(), StorageError>| {
+        assert!(
+            matches!(result, Err(StorageError::NotFound(_))),
+            "{:?}",
+            result
+        )
+    };
+    not_found(storage.get_collection(missing).map(drop));
+    not_found(storage.get_file(missing).map(drop));
+    not_found(storage.get_file_content(missing).map(drop));
+    not_found(storage.collection_lineage(missing).map(drop));
+    not_found(storage.read_file_range(missing, 0, None).map(drop));
+    not_found(storage.create_file(missing, "new.md", vec![], "").map(drop));
+    not_found(
+        storage
+            .update_file_content(missing, vec![], "", None)
+            .map(drop),
+    );
+    not_found(storage.delete_file(missing, None).map(drop));
+    not_found(storage.list_revisions(missing).map(drop));
+    not_found(storage.get_revision_content(missing, 1).map(drop));
+    not_found(storage.create_collection(missing, "new").map(drop));
+    not_found(storage.rename_collection(missing, "new").map(drop));
+    not_found(storage.delete_collection(missing, true).map(drop));
+    not_found(storage.get_path(ItemId::File(missing)).map(drop));
+    not_found(storage.resolve_path("missing/new.md").map(drop));
+    not_found(storage.get_user("missing").map(drop));
+}
+
+pub fn users_are_unique<S: Storage>(storage: &S) {
+    let user = User {
+        username: "conformance".to_string(),
+        password_hash: "pbkdf2-sha256$1$00$00".to_string(),
+    };
+    assert_eq!(storage.create_user(user.clone()), Ok(user.clone()));
+    assert_eq!(storage.get_user(&user.username), Ok(user.clone()));
+    assert!(matches!(
+        storage.create_user(user),
+        Err(StorageError::Conflict(_))
+    ));
+    assert!(matches!(
+        storage.create_user(User {
+            username: "not valid".to_string(),
+            password_hash: "pbkdf2-sha256$1$00$00".to_string(),
+        }),
+        Err(StorageError::InvalidName(_))
+    ));
+}
+
+pub fn acls_are_kept_until_purged<S: Storage>(storage: &S) {
+    let shared = scratch(storage, "shared");
+    let team = storage.create_collection(shared.id, "team").unwrap();
+    let acl = vec![
+        AclEntry {
+            principal: Some(Principal::User("conformance".to_string())),
+            permission: Permission::Admin as i32,
+        },
+        AclEntry {
+            principal: Some(Principal::ClientUuid("some device".to_string())),
+            permission: Permission::Read as i32,
+        },
+    ];
+    storage.set_acl(shared.id, acl.clone()).unwrap();
+    storage.set_acl(team.id, acl[1..].to_vec()).unwrap();
+    let acls = storage.get_acls().unwrap();
+    assert_eq!(acls.get(&shared.id), Some(&acl));
+    assert_eq!(acls.get(&team.id), Some(&acl[1..].to_vec()));
+    // entries keep their order when replaced
+    let reversed: Vec<_> = acl.iter().rev().cloned().collect();
+    storage.set_acl(shared.id, reversed.clone()).unwrap();
+    assert_eq!(storage.get_acls().unwrap().get(&shared.id), Some(&reversed));
+    assert!(matches!(
+        storage.set_acl(u64::MAX, acl.clone()),
+        Err(StorageError::NotFound(_))
+    ));
+
+    // deleted collections keep their lists until they are purged
+    storage.delete_collection(shared.id, true).unwrap();
+    assert!(storage.get_acls().unwrap().contains_key(&team.id));
+    storage
+        .restore_trash(ItemId::Collection(shared.id), None)
+        .unwrap();
+    storage.set_acl(shared.id, vec![]).unwrap();
+    assert!(!storage.get_acls().unwrap().contains_key(&shared.id));
+    storage.delete_collection(shared.id, true).unwrap();
+    storage.purge_trash(ItemId::Collection(shared.id)).unwrap();
+    assert!(!storage.get_acls().unwrap().contains_key(&team.id));
+}
+
+/// Every view of the tree the storage gives agrees with the others
+pub fn tree_is_consistent<S: Storage>(storage: &S) {
+    let collections = storage.get_collection_all();
+    let mut collection_ids = HashSet::new();
+    let mut file_ids = HashSet::new();
+    for collection in &collections {
+        assert!(
+            collection_ids.insert(collection.id),
+            "collection {} is listed twice",
+            collection.id
+        );
+        assert_eq!(
+            storage.get_collection(collection.id).as_ref(),
+            Ok(collection)
+        );
+        let lineage = storage.collection_lineage(collection.id).unwrap();
+        assert_eq!(lineage.last(), Some(&collection.id));
+        for child in &collection.child_collections {
+            assert_eq!(
+                storage.collection_lineage(child.id).unwrap(),
+                [lineage.clone(), vec![child.id]].concat()
+            );
+        }
+        for file in &collection.files {
+            assert!(file_ids.insert(file.id), "file {} is listed twice", file.id);
+            assert_eq!(file.collection_id, collection.id);
+            assert_eq!(storage.get_file(file.id).as_ref(), Ok(file));
+            let path = storage.get_path(ItemId::File(file.id)).unwrap();
+            assert_eq!(storage.resolve_path(&path), Ok(ItemId::File(file.id)));
+        }
+        let path = storage.get_path(ItemId::Collection(collection.id)).unwrap();
+        match storage.resolve_path(&path) {
+            // storages with several roots can't tell them apart by path
+            Err(StorageError::InvalidArgument(_)) if lineage.len() == 1 => {}
+            resolved => assert_eq!(resolved, Ok(ItemId::Collection(collection.id))),
+        }
+    }
+    // every collection nested in the listed ones is listed as well
+    let mut pending: Vec<_> = collections.iter().collect();
+    while let Some(collection) = pending.pop() {
+        assert!(collection_ids.contains(&collection.id));
+        pending.extend(&collection.child_collections);
+    }
+}
@@ -0,0 +1,1935 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::SystemTime,
+};
+
+use notify::RecommendedWatcher;
+use tokio::sync::broadcast;
+
+use super::{
+    trash::{Trash, TrashEntry},
+    tree::{FileMeta, Tree},
+    unix_millis, validate_name, validate_username, Change, ItemId, Storage, StorageError,
+    StorageResult, User,
+};
+use crate::oxygen::{
+    acl_entry::Principal, trash_item, AclEntry, Collection, File, FileContent, Permission,
+    Revision, TrashItem,
+};
+
+mod history;
+mod index;
+mod trash_dir;
+mod watcher;
+
+use history::History;
+use index::Index;
+use trash_dir::TrashDir;
+
+/// The root directory is always the first collection
+const ROOT_COLLECTION: u64 = 0;
+/// Number of changes picked up from the disk that are buffered for each
+/// subscriber
+const CHANGE_BUFFER_SIZE: usize = 1024;
+/// Directory under the root where the storage keeps its own data, it is never
+/// served as a collection
+const STATE_DIR: &str = ".oxygen";
+/// File in [`STATE_DIR`] holding the user accounts, one
+/// `<username> <password hash>` per line
+const USERS_FILE: &str = "users";
+/// File in [`STATE_DIR`] holding the access control lists, one
+/// `<collection id> <permission> client|user <name>` per entry
+const ACL_FILE: &str = "acl";
+/// File in [`STATE_DIR`] keeping the ids of the entries under the root
+const IDS_FILE: &str = "ids";
+
+/// Storage backed by a directory of notes on the local filesystem.
+///
+/// Every directory under (and including) the root is a collection and every
+/// markdown file is a file. Hidden entries (names starting with a `.`) are
+/// ignored unless the storage is created with [`FilesystemStorage::with_hidden`].
+///
+/// Edits made on disk by other programs are only picked up once the storage
+/// is watching the root, see [`FilesystemStorage::watch`].
+///
+/// Ids are kept in `.oxygen/ids` under the root so that they survive restarts,
+/// and follow entries that are renamed or moved, by the storage or on disk.
+///
+/// Past contents of the files are kept in `.oxygen/revisions` under the root.
+/// Edits made on disk are recorded as revisions without an author, when the
+/// watcher picks them up or otherwise the next time the history of the file is
+/// needed.
+///
+/// Deleted collections and files are moved to `.oxygen/trash` under the root.
+/// Deleting them on disk deletes them for good.
+///
+/// User accounts are kept in `.oxygen/users` under the root, and the access
+/// control lists of the collections in `.oxygen/acl`.
+pub struct FilesystemStorage {
+    root: PathBuf,
+    include_hidden: bool,
+    // only locked for writing while holding `writes`, and only for as long as
+    // it takes to update the tree in memory, so that readers are never held up
+    // by the disk
+    tree: Arc<RwLock<Tree>>,
+    // held by whatever changes the tree (writes and the watcher) while it
+    // touches the disk and until the tree matches it. Nothing else changes the
+    // tree meanwhile, so the holder can keep reading it without the write lock.
+    writes: Arc<Mutex<()>>,
+    // only locked while holding `writes`
+    stamps: Arc<Mutex<Stamps>>,
+    changes: broadcast::Sender<Change>,
+    // dropping the watcher stops the background thread applying changes
+    watcher: Option<RecommendedWatcher>,
+    users: Mutex<HashMap<String, User>>,
+    acls: Mutex<HashMap<u64, Vec<AclEntry>>>,
+    // what was last saved to the ids file, only locked while holding `writes`
+    index: Arc<Mutex<Index>>,
+    history: History,
+    // only locked while holding the tree lock
+    trash: Mutex<Trash>,
+    trash_dir: TrashDir,
+}
+
+/// Size and modification time of a file when the storage last looked at it.
+/// Used to tell edits made by other programs apart from the storage's own
+/// writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+type Stamps = HashMap<u64, Stamp>;
+
+impl FilesystemStorage {
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_hidden(root, false)
+    }
+
+    pub fn with_hidden(root: impl AsRef<Path>, include_hidden: bool) -> io::Result<Self> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+        let root_name = root
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("/")
+            .to_string();
+        let ids_path = root.join(STATE_DIR).join(IDS_FILE);
+        let mut tree = Index::load(&ids_path)?.scan(&root, root_name, include_hidden)?;
+        // records the ids of whatever changed while the storage wasn't running,
+        // later changes are added as they happen
+        let mut index = Index::of(&tree, &root);
+        if let Err(err) = index.save(&ids_path) {
+            eprintln!("Failed to save the ids of {}: {}", root.display(), err);
+        }
+        tree.track_touched();
+        let users = load_users(&root.join(STATE_DIR).join(USERS_FILE))?;
+        let acls = load_acls(&root.join(STATE_DIR).join(ACL_FILE))?;
+        let trash_dir = TrashDir::new(&root);
+        let mut trash = Trash::new();
+        trash_dir.load(&mut trash)?;
+        let (changes, _) = broadcast::channel(CHANGE_BUFFER_SIZE);
+        Ok(Self {
+            history: History::new(&root),
+            trash: Mutex::new(trash),
+            trash_dir,
+            root,
+            include_hidden,
+            tree: Arc::new(RwLock::new(tree)),
+            writes: Arc::new(Mutex::new(())),
+            stamps: Arc::new(Mutex::new(Stamps::new())),
+            changes,
+            watcher: None,
+            users: Mutex::new(users),
+            acls: Mutex::new(acls),
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    /// Starts watching the root for edits made by other programs (ex: text
+    /// editors). The storage keeps itself up to date with the disk and reports
+    /// the edits through [`Storage::changes`].
+    pub fn watch(mut self) -> io::Result<Self> {
+        let reconciler = watcher::Reconciler {
+            root: self.root.clone(),
+            include_hidden: self.include_hidden,
+            tree: Arc::clone(&self.tree),
+            writes: Arc::clone(&self.writes),
+            stamps: Arc::clone(&self.stamps),
+            changes: self.changes.clone(),
+            index: Arc::clone(&self.index),
+            history: self.history.clone(),
+        };
+        let watcher = watcher::spawn(reconciler).map_err(|err| match err.kind {
+            notify::ErrorKind::Io(err) => err,
+            _ => io::Error::other(err),
+        })?;
+        self.watcher = Some(watcher);
+        Ok(self)
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Tree> {
+        self.tree.read().expect("storage lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Tree> {
+        self.tree.write().expect("storage lock poisoned")
+    }
+
+    fn writing(&self) -> MutexGuard<'_, ()> {
+        self.writes.lock().expect("storage lock poisoned")
+    }
+
+    fn trash(&self) -> MutexGuard<'_, Trash> {
+        self.trash.lock().expect("storage lock poisoned")
+    }
+
+    fn acls(&self) -> MutexGuard<'_, HashMap<u64, Vec<AclEntry>>> {
+        self.acls.lock().expect("storage lock poisoned")
+    }
+
+    /// Replaces the content of the ACL file with `acls`
+    fn save_acls(&self, acls: &HashMap<u64, Vec<AclEntry>>) -> io::Result<()> {
+        let mut ids: Vec<_> = acls.keys().collect();
+        ids.sort();
+        let mut content = String::new();
+        for id in ids {
+            for entry in &acls[id] {
+                let (kind, name) = match &entry.principal {
+                    Some(Principal::ClientUuid(uuid)) => ("client", uuid),
+                    Some(Principal::User(user)) => ("user", user),
+                    None => continue,
+                };
+                content.push_str(&format!(
+                    "{} {} {} {}\n",
+                    id,
+                    entry.permission().as_str_name(),
+                    kind,
+                    name
+                ));
+            }
+        }
+        let dir = self.root.join(STATE_DIR);
+        fs::create_dir_all(&dir)?;
+        write_atomic(&dir.join(ACL_FILE), content.as_bytes())
+    }
+
+    fn file_path(&self, tree: &Tree, id: u64) -> StorageResult<PathBuf> {
+        let file = tree.require_file(id)?;
+        let mut path = self.collection_path(tree, file.parent)?;
+        path.push(&file.name);
+        Ok(path)
+    }
+
+    fn collection_path(&self, tree: &Tree, id: u64) -> StorageResult<PathBuf> {
+        tree.require_collection(id)?;
+        let mut path = self.root.clone();
+        // the root collection is the root directory itself
+        for ancestor in tree.collection_lineage(id).into_iter().skip(1) {
+            path.push(&tree.require_collection(ancestor)?.name);
+        }
+        Ok(path)
+    }
+
+    /// Remembers what the file `id` at `path` looks like after the storage
+    /// wrote to it so that the write isn't reported as an edit made by another
+    /// program. Must be called while holding `writes`.
+    fn record_stamp(&self, id: u64, path: &Path) {
+        if let Ok(stamp) = Stamp::of(path) {
+            self.stamps
+                .lock()
+                .expect("storage lock poisoned")
+                .insert(id, stamp);
+        }
+    }
+
+    /// Must be called while holding `writes` (but not the tree lock) after
+    /// every change to the tree
+    fn save_index(&self) {
+        save_index(&self.root, &self.tree, &self.index);
+    }
+
+    /// Records the content of the file `id` the storage just wrote as a new
+    /// revision. Must be called while holding `writes`. The content is
+    /// already written by then, so a failure is logged rather than failing the
+    /// write.
+    fn record_revision(&self, tree: &Tree, id: u64, body: &[u8], author: &str) {
+        let meta = &tree.require_file(id).expect("file was just written").meta;
+        if let Err(err) = self.history.record(id, body, meta, author) {
+            eprintln!("Failed to record a revision of file {}: {}", id, err);
+        }
+    }
+
+    /// Must be called while holding `writes` once the file `id` is gone
+    fn remove_revisions(&self, id: u64) {
+        if let Err(err) = self.history.remove(id) {
+            eprintln!("Failed to remove the revisions of file {}: {}", id, err);
+        }
+    }
+
+    /// Moves `item`, stored at `path`, to the trash. Must be called while
+    /// holding `writes`. Items already gone from the disk are deleted for good.
+    fn put_in_trash(&self, item: ItemId, path: &Path) -> StorageResult<()> {
+        let (view, entry) = {
+            let tree = self.read();
+            let now = unix_millis(SystemTime::now());
+            let entry = TrashEntry::of(&tree, item, now)?;
+            (tree.to_item(item).expect("item exists"), entry)
+        };
+        match self.trash_dir.put(&view, &entry, path) {
+            // somebody else already deleted it, we only need to catch up
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let removed_files = match item {
+                    ItemId::Collection(id) => self.write().remove_collection(id).1,
+                    ItemId::File(id) => {
+                        self.write().remove_file(id);
+                        vec![id]
+                    }
+                };
+                for file in removed_files {
+                    self.remove_revisions(file);
+                }
+            }
+            result => {
+                result?;
+                let mut tree = self.write();
+                self.trash().insert(item, entry, &mut tree);
+            }
+        }
+        self.save_index();
+        Ok(())
+    }
+
+    /// Puts `item` back from the trash, in the collection `parent_id` or where
+    /// it was deleted from. Must be called while holding `writes`.
+    fn take_out_of_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item> {
+        let (parent, path) = {
+            let tree = self.read();
+            let trash = self.trash();
+            let parent = trash
+                .check_restore(&tree, item, parent_id)?
+                .expect("the root directory can't be deleted");
+            let path = self.collection_path(&tree, parent)?.join(trash.name(item)?);
+            (parent, path)
+        };
+        check_vacant(&path)?;
+        self.trash_dir.restore(item, &path)?;
+        let restored = {
+            let mut tree = self.write();
+            self.trash().restore(&mut tree, item, Some(parent));
+            tree.to_item(item).expect("item was just restored")
+        };
+        {
+            let tree = self.read();
+            // so that the watcher doesn't take the files for edited ones
+            let mut pending = vec![];
+            match &restored {
+                trash_item::Item::Collection(collection) => pending.push(collection),
+                trash_item::Item::File(file) => self.record_stamp(file.id, &path),
+            }
+            while let Some(current) = pending.pop() {
+                for file in &current.files {
+                    if let Ok(path) = self.file_path(&tree, file.id) {
+                        self.record_stamp(file.id, &path);
+                    }
+                }
+                pending.extend(&current.child_collections);
+            }
+        }
+        self.save_index();
+        Ok(restored)
+    }
+
+    /// Brings what the tree knows about the content of the file `id` in line
+    /// with the file at `path` if the content changed, for edits the watcher
+    /// didn't get to (or every edit when nobody is watching). Must be called
+    /// while holding `writes`.
+    fn catch_up_meta(&self, id: u64, path: &Path) -> io::Result<()> {
+        let meta = file_meta(path)?;
+        // unchanged files keep the creation time the storage knows, writes
+        // replace the file on disk
+        let changed = self
+            .read()
+            .file(id)
+            .is_some_and(|file| file.meta.sha256 != meta.sha256);
+        if changed {
+            self.write().set_file_meta(id, meta);
+        }
+        Ok(())
+    }
+
+    /// Parent of the collection `id`. The root directory can't be changed
+    /// through the storage so it is rejected.
+    fn non_root_parent(&self, tree: &Tree, id: u64) -> StorageResult<u64> {
+        tree.require_collection(id)?.parent.ok_or_else(|| {
+            StorageError::PermissionDenied(format!(
+                "collection with id: {} is the root directory",
+                id
+            ))
+        })
+    }
+}
+
+/// Fails with [`StorageError::Conflict`] if something (tracked by the storage
+/// or not) already exists at `path`.
+fn check_vacant(path: &Path) -> StorageResult<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        Err(StorageError::Conflict(format!(
+            "{} already exists",
+            path.display()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Replaces the content of `path` with `body` without leaving a half written
+/// file behind if something goes wrong midway.
+fn write_atomic(path: &Path, body: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.oxygen-tmp", file_name));
+    fs::write(&tmp_path, body)?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+/// Saves the ids of what changed in `tree` since the last save to the ids file
+/// under `root`. A failure only costs the ids of what changed since the last
+/// save, so it is logged rather than failing the change.
+fn save_index(root: &Path, tree: &RwLock<Tree>, index: &Mutex<Index>) {
+    let update = {
+        let mut tree = tree.write().expect("storage lock poisoned");
+        let touched = tree.take_touched();
+        index::Update::of(&tree, touched)
+    };
+    let mut index = index.lock().expect("storage lock poisoned");
+    if let Err(err) = index.apply(update, root, &root.join(STATE_DIR).join(IDS_FILE)) {
+        eprintln!("Failed to save the ids of {}: {}", root.display(), err);
+    }
+}
+
+fn file_meta(path: &Path) -> io::Result<FileMeta> {
+    let metadata = fs::metadata(path)?;
+    let body = fs::read(path)?;
+    // not every filesystem keeps track of creation times
+    let created = metadata.created().map_or(0, unix_millis);
+    let modified = metadata.modified().map_or(0, unix_millis);
+    Ok(FileMeta::of(&body, created, modified))
+}
+
+/// Brings what `tree` knows about the content of the file `id` in line with
+/// the file at `path`
+fn refresh_meta(tree: &mut Tree, id: u64, path: &Path) {
+    match file_meta(path) {
+        Ok(meta) => tree.set_file_meta(id, meta),
+        Err(err) => eprintln!("Failed to read {}: {}", path.display(), err),
+    }
+}
+
+fn load_users(path: &Path) -> io::Result<HashMap<String, User>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (username, password_hash) = line.split_once(' ').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed user in {}: {:?}", path.display(), line),
+                )
+            })?;
+            let user = User {
+                username: username.to_string(),
+                password_hash: password_hash.to_string(),
+            };
+            Ok((user.username.clone(), user))
+        })
+        .collect()
+}
+
+fn load_acls(path: &Path) -> io::Result<HashMap<u64, Vec<AclEntry>>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+    let mut acls: HashMap<u64, Vec<AclEntry>> = HashMap::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let malformed = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed ACL entry in {}: {:?}", path.display(), line),
+            )
+        };
+        let mut fields = line.splitn(4, ' ');
+        let (Some(id), Some(permission), Some(kind), Some(name)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(malformed());
+        };
+        let id = id.parse().map_err(|_| malformed())?;
+        let permission = Permission::from_str_name(permission).ok_or_else(malformed)?;
+        let principal = match kind {
+            "client" => Principal::ClientUuid(name.to_string()),
+            "user" => Principal::User(name.to_string()),
+            _ => return Err(malformed()),
+        };
+        acls.entry(id).or_default().push(AclEntry {
+            principal: Some(principal),
+            permission: permission as i32,
+        });
+    }
+    Ok(acls)
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| {
+            extension.eq_ignore_ascii_case("md") || extension.eq_ignore_ascii_case("markdown")
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum EntryKind {
+    Collection,
+    File,
+}
+
+/// Entries of `dir` that are tracked by the storage, in name order.
+fn list_dir(dir: &Path, include_hidden: bool) -> io::Result<Vec<(String, EntryKind)>> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    let mut tracked = vec![];
+    for entry in entries {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                // TODO: setup logging
+                eprintln!("Skipping {:?}: name is not valid unicode", name);
+                continue;
+            }
+        };
+        if name == STATE_DIR || (!include_hidden && is_hidden(&name)) {
+            continue;
+        }
+        // symlinks are skipped to avoid walking out of the root (or in circles)
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            tracked.push((name, EntryKind::Collection));
+        } else if file_type.is_file() && is_markdown(&entry.path()) {
+            tracked.push((name, EntryKind::File));
+        }
+    }
+    Ok(tracked)
+}
+
+/// Adds the content of `dir` to `tree` under the collection `parent`. Entries
+/// are visited in name order so that ids are stable as long as the directory
+/// doesn't change.
+fn scan_dir(dir: &Path, parent: u64, include_hidden: bool, tree: &mut Tree) -> io::Result<()> {
+    for (name, kind) in list_dir(dir, include_hidden)? {
+        match kind {
+            EntryKind::Collection => {
+                let path = dir.join(&name);
+                let id = tree.insert_collection(Some(parent), name);
+                scan_dir(&path, id, include_hidden, tree)?;
+            }
+            EntryKind::File => {
+                let path = dir.join(&name);
+                let id = tree.insert_file(parent, name);
+                refresh_meta(tree, id, &path);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Storage for FilesystemStorage {
+    fn get_collection_all(&self) -> Vec<Collection> {
+        self.read().to_collection_all()
+    }
+
+    fn get_collection(&self, id: u64) -> StorageResult<Collection> {
+        self.read()
+            .to_collection(id)
+            .ok_or_else(|| StorageError::NotFound(format!("collection with id: {}", id)))
+    }
+
+    fn get_file(&self, id: u64) -> StorageResult<File> {
+        self.read()
+            .to_file(id)
+            .ok_or_else(|| StorageError::NotFound(format!("file with id: {}", id)))
+    }
+
+    fn get_file_content(&self, id: u64) -> StorageResult<FileContent> {
+        let path = self.file_path(&self.read(), id)?;
+        let body = fs::read(path)?;
+        Ok(FileContent { body })
+    }
+
+    fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>> {
+        let tree = self.read();
+        tree.require_collection(id)?;
+        Ok(tree.collection_lineage(id))
+    }
+
+    fn read_file_range(&self, id: u64, offset: u64, length: Option<u64>) -> StorageResult<Vec<u8>> {
+        let path = self.file_path(&self.read(), id)?;
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut body = vec![];
+        match length {
+            Some(length) => file.take(length).read_to_end(&mut body)?,
+            None => file.read_to_end(&mut body)?,
+        };
+        Ok(body)
+    }
+
+    fn create_file(
+        &self,
+        collection_id: u64,
+        name: &str,
+        body: Vec<u8>,
+        author: &str,
+    ) -> StorageResult<File> {
+        validate_name(name)?;
+        if !is_markdown(Path::new(name)) {
+            return Err(StorageError::InvalidName(format!(
+                "{:?} is not a markdown file",
+                name
+            )));
+        }
+        let _writing = self.writing();
+        let path = {
+            let tree = self.read();
+            tree.check_name_free(collection_id, name)?;
+            self.collection_path(&tree, collection_id)?.join(name)
+        };
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        if let Err(err) = io::Write::write_all(&mut file, &body) {
+            let _ = fs::remove_file(&path);
+            return Err(err.into());
+        }
+        let meta = file_meta(&path);
+        let id = {
+            let mut tree = self.write();
+            let id = tree.insert_file(collection_id, name.to_string());
+            match meta {
+                Ok(meta) => tree.set_file_meta(id, meta),
+                Err(err) => eprintln!("Failed to read {}: {}", path.display(), err),
+            }
+            id
+        };
+        self.record_stamp(id, &path);
+        self.save_index();
+        let tree = self.read();
+        self.record_revision(&tree, id, &body, author);
+        Ok(tree.to_file(id).expect("file was just inserted"))
+    }
+
+    fn update_file_content(
+        &self,
+        id: u64,
+        body: Vec<u8>,
+        author: &str,
+        if_match: Option<&str>,
+    ) -> StorageResult<File> {
+        let _writing = self.writing();
+        let path = self.file_path(&self.read(), id)?;
+        if !path.is_file() {
+            return Err(StorageError::NotFound(format!("{}", path.display())));
+        }
+        // edits made on disk that the watcher didn't get to are kept, and make
+        // the write stale
+        self.catch_up_meta(id, &path)?;
+        let created = {
+            let tree = self.read();
+            tree.check_etag(id, if_match)?;
+            tree.require_file(id)?.meta.created
+        };
+        self.history.catch_up(id, &path)?;
+        write_atomic(&path, &body)?;
+        self.record_stamp(id, &path);
+        match file_meta(&path) {
+            // the replaced file was created along with the original one
+            Ok(meta) => self.write().set_file_meta(id, FileMeta { created, ..meta }),
+            Err(err) => eprintln!("Failed to read {}: {}", path.display(), err),
+        }
+        // replacing the file gave it a new inode
+        self.save_index();
+        let tree = self.read();
+        self.record_revision(&tree, id, &body, author);
+        Ok(tree.to_file(id).expect("file path was resolved"))
+    }
+
+    fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>> {
+        // catching up writes to the history
+        let _writing = self.writing();
+        let path = self.file_path(&self.read(), id)?;
+        self.history.catch_up(id, &path)?;
+        Ok(self.history.list(id)?)
+    }
+
+    fn get_revision_content(&self, id: u64, revision_id: u64) -> StorageResult<FileContent> {
+        self.read().require_file(id)?;
+        match self.history.content(id, revision_id) {
+            Ok(body) => Ok(FileContent { body }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Err(StorageError::NotFound(
+                format!("revision {} of file with id: {}", revision_id, id),
+            )),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn delete_file(&self, id: u64, if_match: Option<&str>) -> StorageResult<File> {
+        let _writing = self.writing();
+        let path = self.file_path(&self.read(), id)?;
+        if if_match.is_some() {
+            self.catch_up_meta(id, &path)?;
+            self.read().check_etag(id, if_match)?;
+        }
+        let file = self.read().to_file(id).expect("file path was resolved");
+        self.put_in_trash(ItemId::File(id), &path)?;
+        Ok(file)
+    }
+
+    fn move_file(
+        &self,
+        id: u64,
+        collection_id: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<(File, Collection)> {
+        validate_name(name)?;
+        if !is_markdown(Path::new(name)) {
+            return Err(StorageError::InvalidName(format!(
+                "{:?} is not a markdown file",
+                name
+            )));
+        }
+        let _writing = self.writing();
+        let (replaced, path, new_path) = {
+            let tree = self.read();
+            let replaced = tree.check_file_move(id, collection_id, name, overwrite)?;
+            let path = self.file_path(&tree, id)?;
+            let new_path = self.collection_path(&tree, collection_id)?.join(name);
+            (replaced, path, new_path)
+        };
+        if let Some(replaced) = replaced {
+            self.put_in_trash(ItemId::File(replaced), &new_path)?;
+        }
+        if path != new_path {
+            // whatever is left is not tracked and can't be overwritten
+            let moved = check_vacant(&new_path).and_then(|()| Ok(fs::rename(&path, &new_path)?));
+            if let Err(err) = moved {
+                if let Some(replaced) = replaced {
+                    // the replaced file only makes way for the moved one
+                    if let Err(err) = self.take_out_of_trash(ItemId::File(replaced), None) {
+                        eprintln!("Failed to put back file {}: {}", replaced, err);
+                    }
+                }
+                return Err(err);
+            }
+        }
+        self.write().move_file(id, collection_id, name.to_string());
+        self.save_index();
+        let tree = self.read();
+        Ok((
+            tree.to_file(id).expect("file exists"),
+            tree.to_collection(collection_id)
+                .expect("collection exists"),
+        ))
+    }
+
+    fn create_collection(&self, parent_id: u64, name: &str) -> StorageResult<Collection> {
+        validate_name(name)?;
+        let _writing = self.writing();
+        let path = {
+            let tree = self.read();
+            tree.check_name_free(parent_id, name)?;
+            self.collection_path(&tree, parent_id)?.join(name)
+        };
+        fs::create_dir(path)?;
+        let id = self
+            .write()
+            .insert_collection(Some(parent_id), name.to_string());
+        self.save_index();
+        Ok(self
+            .read()
+            .to_collection(id)
+            .expect("collection was just inserted"))
+    }
+
+    fn rename_collection(&self, id: u64, name: &str) -> StorageResult<Collection> {
+        validate_name(name)?;
+        let _writing = self.writing();
+        let path = {
+            let tree = self.read();
+            let parent = self.non_root_parent(&tree, id)?;
+            tree.check_name_free(parent, name)?;
+            self.collection_path(&tree, id)?
+        };
+        let new_path = path.with_file_name(name);
+        check_vacant(&new_path)?;
+        fs::rename(path, new_path)?;
+        self.write().rename_collection(id, name.to_string());
+        self.save_index();
+        Ok(self.read().to_collection(id).expect("collection exists"))
+    }
+
+    fn move_collection(&self, id: u64, parent_id: u64) -> StorageResult<Collection> {
+        let _writing = self.writing();
+        let moved = {
+            let tree = self.read();
+            let current_parent = self.non_root_parent(&tree, id)?;
+            tree.check_move(id, parent_id)?;
+            if current_parent != parent_id {
+                let name = tree.require_collection(id)?.name.clone();
+                tree.check_name_free(parent_id, &name)?;
+                let path = self.collection_path(&tree, id)?;
+                Some((path, self.collection_path(&tree, parent_id)?.join(&name)))
+            } else {
+                None
+            }
+        };
+        if let Some((path, new_path)) = moved {
+            check_vacant(&new_path)?;
+            fs::rename(path, new_path)?;
+            self.write().move_collection(id, parent_id);
+            self.save_index();
+        }
+        Ok(self.read().to_collection(id).expect("collection exists"))
+    }
+
+    fn delete_collection(&self, id: u64, recursive: bool) -> StorageResult<Collection> {
+        let _writing = self.writing();
+        let (path, collection) = {
+            let tree = self.read();
+            self.non_root_parent(&tree, id)?;
+            let path = self.collection_path(&tree, id)?;
+            // entries the storage doesn't track go to the trash along with the
+            // directory
+            if !recursive && !tree.require_collection(id)?.is_empty() {
+                return Err(StorageError::Conflict(format!(
+                    "collection with id: {} is not empty",
+                    id
+                )));
+            }
+            (path, tree.to_collection(id).expect("collection exists"))
+        };
+        self.put_in_trash(ItemId::Collection(id), &path)?;
+        Ok(collection)
+    }
+
+    fn list_trash(&self) -> StorageResult<Vec<TrashItem>> {
+        let _tree = self.read();
+        Ok(self.trash().list())
+    }
+
+    fn restore_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item> {
+        let _writing = self.writing();
+        self.take_out_of_trash(item, parent_id)
+    }
+
+    fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem> {
+        let _writing = self.writing();
+        let purged = {
+            let _tree = self.read();
+            self.trash().to_trash_item(item)?
+        };
+        self.trash_dir.purge(item)?;
+        let (collections, files) = {
+            let _tree = self.write();
+            self.trash().purge(item)?
+        };
+        for file in files {
+            self.remove_revisions(file);
+        }
+        let mut acls = self.acls();
+        if collections.iter().any(|id| acls.contains_key(id)) {
+            let mut updated = acls.clone();
+            updated.retain(|id, _| !collections.contains(id));
+            // the ids of purged collections are never given out again, leftover
+            // lists don't apply to anything
+            if let Err(err) = self.save_acls(&updated) {
+                eprintln!("Failed to save the access control lists: {}", err);
+            }
+            *acls = updated;
+        }
+        Ok(purged)
+    }
+
+    fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+        self.read().resolve_path(path)
+    }
+
+    fn get_path(&self, item: ItemId) -> StorageResult<String> {
+        self.read().path_of(item)
+    }
+
+    fn get_user(&self, username: &str) -> StorageResult<User> {
+        self.users
+            .lock()
+            .expect("storage lock poisoned")
+            .get(username)
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(format!("user: {}", username)))
+    }
+
+    fn create_user(&self, user: User) -> StorageResult<User> {
+        validate_username(&user.username)?;
+        if user.password_hash.is_empty() || user.password_hash.contains(char::is_whitespace) {
+            return Err(StorageError::InvalidArgument(format!(
+                "password hash of {} can't be stored",
+                user.username
+            )));
+        }
+        let mut users = self.users.lock().expect("storage lock poisoned");
+        if users.contains_key(&user.username) {
+            return Err(StorageError::Conflict(format!(
+                "user {} already exists",
+                user.username
+            )));
+        }
+        let mut saved: Vec<_> = users.values().chain([&user]).collect();
+        saved.sort_by(|a, b| a.username.cmp(&b.username));
+        let content: String = saved
+            .into_iter()
+            .map(|user| format!("{} {}\n", user.username, user.password_hash))
+            .collect();
+        let dir = self.root.join(STATE_DIR);
+        fs::create_dir_all(&dir)?;
+        write_atomic(&dir.join(USERS_FILE), content.as_bytes())?;
+        users.insert(user.username.clone(), user.clone());
+        Ok(user)
+    }
+
+    fn get_acls(&self) -> StorageResult<HashMap<u64, Vec<AclEntry>>> {
+        Ok(self.acls().clone())
+    }
+
+    fn set_acl(&self, id: u64, entries: Vec<AclEntry>) -> StorageResult<()> {
+        self.read().require_collection(id)?;
+        for entry in &entries {
+            match &entry.principal {
+                Some(Principal::ClientUuid(name) | Principal::User(name))
+                    if !name.is_empty() && !name.contains(['\n', '\r']) => {}
+                _ => {
+                    return Err(StorageError::InvalidArgument(format!(
+                        "ACL entry {:?} can't be stored",
+                        entry
+                    )))
+                }
+            }
+        }
+        let mut acls = self.acls();
+        let mut updated = acls.clone();
+        if entries.is_empty() {
+            updated.remove(&id);
+        } else {
+            updated.insert(id, entries);
+        }
+        self.save_acls(&updated)?;
+        *acls = updated;
+        Ok(())
+    }
+
+    fn changes(&self) -> Option<broadcast::Receiver<Change>> {
+        self.watcher.as_ref().map(|_| self.changes.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs, thread,
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::broadcast::{self, error::TryRecvError};
+
+    use super::{FilesystemStorage, IDS_FILE, STATE_DIR};
+    use crate::collection::{
+        conformance, search::Searchable, Change, ItemId, Storage, StorageError, User,
+    };
+    use crate::oxygen::{
+        acl_entry::Principal, change_event::Kind, AclEntry, ChangeEvent, FileType, Permission,
+    };
+
+    fn notes_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("work/meetings")).unwrap();
+        fs::create_dir_all(root.join("journal ✍")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("todo.md"), "# todo").unwrap();
+        fs::write(root.join("work/meetings/2026-10-01.md"), "# standup").unwrap();
+        fs::write(root.join("work/plan 2.MD"), "# plan").unwrap();
+        fs::write(root.join("work/logo.png"), [0u8, 1, 2]).unwrap();
+        fs::write(root.join("journal ✍/día 1.md"), "# día").unwrap();
+        fs::write(root.join(".hidden.md"), "# hidden").unwrap();
+        fs::write(root.join(".git/HEAD.md"), "# hidden").unwrap();
+        dir
+    }
+
+    fn find_file(storage: &FilesystemStorage, name: &str) -> u64 {
+        storage
+            .get_collection_all()
+            .into_iter()
+            .flat_map(|collection| collection.files)
+            .find(|file| file.name == name)
+            .unwrap_or_else(|| panic!("expected to find {}", name))
+            .id
+    }
+
+    #[test]
+    fn filesystem_storage_conforms() {
+        let dir = notes_dir();
+        conformance::check(&FilesystemStorage::new(dir.path()).expect("failed to open storage"));
+    }
+
+    #[test]
+    fn maps_directories_to_collections() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let mut names: Vec<String> = storage
+            .get_collection_all()
+            .into_iter()
+            .map(|collection| collection.name)
+            .collect();
+        names.sort();
+        let root_name = dir.path().file_name().unwrap().to_str().unwrap();
+        let mut expected = vec![root_name, "journal ✍", "meetings", "work"];
+        expected.sort();
+        assert_eq!(names, expected);
+
+        let root = storage
+            .get_collection(0)
+            .expect("root collection must exist");
+        assert_eq!(root.child_collections.len(), 2);
+        let work = root
+            .child_collections
+            .iter()
+            .find(|collection| collection.name == "work")
+            .expect("expected work collection");
+        assert_eq!(work.child_collections[0].name, "meetings");
+        assert_eq!(work.child_collections[0].files[0].name, "2026-10-01.md");
+    }
+
+    #[test]
+    fn only_markdown_files_are_included() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let mut names: Vec<String> = storage
+            .get_collection_all()
+            .into_iter()
+            .flat_map(|collection| collection.files)
+            .map(|file| file.name)
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["2026-10-01.md", "día 1.md", "plan 2.MD", "todo.md"]
+        );
+    }
+
+    #[test]
+    fn reads_file_content_from_disk() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let id = find_file(&storage, "día 1.md");
+        let content = storage
+            .get_file_content(id)
+            .expect("failed to read content");
+        assert_eq!(content.body, "# día".as_bytes());
+        let id = find_file(&storage, "plan 2.MD");
+        let content = storage
+            .get_file_content(id)
+            .expect("failed to read content");
+        assert_eq!(content.body, b"# plan");
+    }
+
+    #[test]
+    fn files_describe_their_content() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let file = storage
+            .get_file(find_file(&storage, "todo.md"))
+            .expect("failed to get file");
+        assert_eq!(file.size, 6);
+        assert_eq!(file.file_type(), FileType::Markdown);
+        // sha256 of "# todo"
+        assert_eq!(
+            file.sha256,
+            "757ab56e54ebe2ddd5fabd492933f56a36b316a91ad12935483736a21a19b97b"
+        );
+        assert!(file.modified > 0);
+
+        let updated = storage
+            .update_file_content(file.id, b"# todo\n- more".to_vec(), "", None)
+            .expect("failed to update file");
+        assert_eq!(updated.size, 13);
+        assert_ne!(updated.sha256, file.sha256);
+        assert!(updated.modified >= file.modified);
+    }
+
+    #[test]
+    fn reads_file_ranges_from_disk() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let id = find_file(&storage, "2026-10-01.md");
+        assert_eq!(
+            storage.read_file_range(id, 2, Some(5)).unwrap(),
+            b"stand".to_vec()
+        );
+        assert_eq!(
+            storage.read_file_range(id, 2, None).unwrap(),
+            b"standup".to_vec()
+        );
+        assert!(storage.read_file_range(id, 100, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hidden_entries_can_be_included() {
+        let dir = notes_dir();
+        let storage =
+            FilesystemStorage::with_hidden(dir.path(), true).expect("failed to open storage");
+        find_file(&storage, ".hidden.md");
+        find_file(&storage, "HEAD.md");
+    }
+
+    #[test]
+    fn users_and_acls_are_kept_across_restarts() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let alice = User {
+            username: "alice".to_string(),
+            password_hash: "pbkdf2-sha256$1$00$00".to_string(),
+        };
+        storage
+            .create_user(alice.clone())
+            .expect("failed to create user");
+        assert!(matches!(
+            storage.create_user(alice.clone()),
+            Err(StorageError::Conflict(_))
+        ));
+        let acl = vec![
+            AclEntry {
+                principal: Some(Principal::User("alice".to_string())),
+                permission: Permission::Admin as i32,
+            },
+            AclEntry {
+                principal: Some(Principal::ClientUuid("a device".to_string())),
+                permission: Permission::Read as i32,
+            },
+        ];
+        storage.set_acl(0, acl.clone()).expect("failed to set ACL");
+        let multiline = AclEntry {
+            principal: Some(Principal::ClientUuid("a\ndevice".to_string())),
+            permission: Permission::Read as i32,
+        };
+        assert!(matches!(
+            storage.set_acl(0, vec![multiline]),
+            Err(StorageError::InvalidArgument(_))
+        ));
+        drop(storage);
+
+        let storage =
+            FilesystemStorage::with_hidden(dir.path(), true).expect("failed to open storage");
+        assert_eq!(storage.get_user("alice"), Ok(alice));
+        assert_eq!(storage.get_acls(), Ok(HashMap::from([(0, acl)])));
+        assert!(matches!(
+            storage.get_user("bob"),
+            Err(StorageError::NotFound(_))
+        ));
+        // the storage's own data isn't a collection, even with hidden entries
+        assert!(storage
+            .get_collection_all()
+            .iter()
+            .all(|collection| collection.name != ".oxygen"));
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_root() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let standup = find_file(&storage, "2026-10-01.md");
+        assert_eq!(
+            storage.resolve_path("work/meetings/2026-10-01.md"),
+            Ok(ItemId::File(standup))
+        );
+        assert_eq!(
+            storage.get_path(ItemId::File(standup)),
+            Ok("work/meetings/2026-10-01.md".to_string())
+        );
+        let dia = find_file(&storage, "día 1.md");
+        assert_eq!(
+            storage.get_path(ItemId::File(dia)),
+            Ok("journal ✍/día 1.md".to_string())
+        );
+        assert_eq!(storage.resolve_path("/"), Ok(ItemId::Collection(0)));
+        // only what the storage tracks has a path
+        assert!(matches!(
+            storage.resolve_path("work/logo.png"),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn trash_is_kept_across_restarts() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let work = storage.resolve_path("work").expect("expected work");
+        let ItemId::Collection(work) = work else {
+            panic!("work is a directory");
+        };
+        let before = storage.get_collection(work).unwrap();
+        let todo = find_file(&storage, "todo.md");
+        storage
+            .delete_file(todo, None)
+            .expect("failed to delete file");
+        storage
+            .delete_collection(work, true)
+            .expect("failed to delete collection");
+        assert!(!dir.path().join("work").exists());
+        let trash = storage.list_trash().unwrap();
+        assert_eq!(trash.len(), 2);
+        drop(storage);
+
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert_eq!(storage.list_trash(), Ok(trash));
+        assert!(matches!(
+            storage.resolve_path("work"),
+            Err(StorageError::NotFound(_))
+        ));
+        storage
+            .restore_trash(ItemId::Collection(work), None)
+            .expect("failed to restore collection");
+        assert_eq!(storage.get_collection(work), Ok(before));
+        // entries that aren't notes went along
+        assert!(dir.path().join("work/logo.png").exists());
+        storage
+            .restore_trash(ItemId::File(todo), None)
+            .expect("failed to restore file");
+        assert_eq!(storage.get_file_content(todo).unwrap().body, b"# todo");
+        storage
+            .delete_file(todo, None)
+            .expect("failed to delete file");
+        storage
+            .purge_trash(ItemId::File(todo))
+            .expect("failed to purge file");
+        drop(storage);
+
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert_eq!(storage.list_trash(), Ok(vec![]));
+        assert_eq!(storage.resolve_path("work"), Ok(ItemId::Collection(work)));
+    }
+
+    #[test]
+    fn ids_are_kept_across_restarts() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let ideas = storage
+            .create_collection(0, "ideas")
+            .expect("failed to create collection");
+        let idea = storage
+            .create_file(ideas.id, "idea.md", b"# idea".to_vec(), "")
+            .expect("failed to create file");
+        let todo = find_file(&storage, "todo.md");
+        storage
+            .delete_file(todo, None)
+            .expect("failed to delete file");
+        let collections = storage.get_collection_all();
+        drop(storage);
+
+        // a new directory sorting first would shift every id of a fresh scan
+        fs::create_dir(dir.path().join("archive")).unwrap();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        // the root collection got the new directory
+        for collection in collections.iter().filter(|collection| collection.id != 0) {
+            assert_eq!(
+                storage.get_collection(collection.id).as_ref(),
+                Ok(collection)
+            );
+        }
+        assert_eq!(find_file(&storage, "idea.md"), idea.id);
+        let archive = storage
+            .resolve_path("archive")
+            .expect("expected the new directory");
+        assert!(!collections
+            .iter()
+            .any(|collection| ItemId::Collection(collection.id) == archive));
+        // ids of deleted entries aren't handed out again
+        let file = storage
+            .create_file(0, "todo.md", b"# todo".to_vec(), "")
+            .expect("failed to create file");
+        assert_ne!(file.id, todo);
+    }
+
+    #[test]
+    fn ids_file_only_gets_the_changes() {
+        let dir = notes_dir();
+        let ids_path = dir.path().join(STATE_DIR).join(IDS_FILE);
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let saved = fs::read_to_string(&ids_path).expect("expected the ids file");
+        let Ok(ItemId::Collection(work)) = storage.resolve_path("work") else {
+            panic!("expected the work collection");
+        };
+        let standup = find_file(&storage, "2026-10-01.md");
+        let todo = find_file(&storage, "todo.md");
+        storage
+            .rename_collection(work, "job")
+            .expect("failed to rename collection");
+        storage
+            .delete_file(todo, None)
+            .expect("failed to delete file");
+        let content = fs::read_to_string(&ids_path).expect("expected the ids file");
+        let appended = content
+            .strip_prefix(&saved)
+            .expect("changes are appended to the ids file");
+        assert!(appended.contains(" job/meetings/2026-10-01.md\n"));
+        assert!(appended.contains(&format!("removed file {}\n", todo)));
+        drop(storage);
+
+        // a change cut short is left out
+        fs::write(&ids_path, format!("{}file {} - ", content, standup)).unwrap();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert_eq!(
+            storage.get_path(ItemId::File(standup)).unwrap(),
+            "job/meetings/2026-10-01.md"
+        );
+        assert_eq!(storage.resolve_path("job"), Ok(ItemId::Collection(work)));
+    }
+
+    #[test]
+    fn edits_made_on_disk_are_kept_as_revisions() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        // files found on disk start their history once it is needed
+        let todo = find_file(&storage, "todo.md");
+        let revisions = storage.list_revisions(todo).unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].author, "");
+
+        let file = storage
+            .create_file(0, "notes.md", b"# notes".to_vec(), "alice")
+            .expect("failed to create file");
+        // nobody is watching
+        fs::write(dir.path().join("notes.md"), "# by hand").unwrap();
+        storage
+            .update_file_content(file.id, b"# by bob".to_vec(), "bob", None)
+            .expect("failed to update file");
+        let revisions = storage.list_revisions(file.id).unwrap();
+        let authors: Vec<_> = revisions.iter().map(|r| r.author.as_str()).collect();
+        assert_eq!(authors, ["alice", "", "bob"]);
+        assert_eq!(
+            storage
+                .get_revision_content(file.id, revisions[1].id)
+                .unwrap()
+                .body,
+            b"# by hand"
+        );
+        drop(storage);
+
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert_eq!(storage.list_revisions(file.id), Ok(revisions));
+        storage
+            .delete_file(file.id, None)
+            .expect("failed to delete file");
+        storage
+            .purge_trash(ItemId::File(file.id))
+            .expect("failed to purge file");
+        assert!(!dir
+            .path()
+            .join(".oxygen/revisions")
+            .join(file.id.to_string())
+            .exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ids_follow_entries_moved_while_stopped() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let todo = find_file(&storage, "todo.md");
+        let standup = find_file(&storage, "2026-10-01.md");
+        let Ok(ItemId::Collection(meetings)) = storage.resolve_path("work/meetings") else {
+            panic!("expected the meetings collection");
+        };
+        drop(storage);
+
+        fs::rename(dir.path().join("todo.md"), dir.path().join("work/todo.md")).unwrap();
+        fs::rename(
+            dir.path().join("work/meetings"),
+            dir.path().join("journal ✍/standups"),
+        )
+        .unwrap();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert_eq!(
+            storage.get_path(ItemId::File(todo)).unwrap(),
+            "work/todo.md"
+        );
+        assert_eq!(
+            storage.get_path(ItemId::Collection(meetings)).unwrap(),
+            "journal ✍/standups"
+        );
+        assert_eq!(
+            storage.get_path(ItemId::File(standup)).unwrap(),
+            "journal ✍/standups/2026-10-01.md"
+        );
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert!(matches!(
+            storage.get_collection(100),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.get_file(100),
+            Err(StorageError::NotFound(_))
+        ));
+        assert!(matches!(
+            storage.get_file_content(100),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn edits_made_on_disk_make_writes_stale() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let todo = storage.get_file(find_file(&storage, "todo.md")).unwrap();
+        // nobody is watching
+        fs::write(dir.path().join("todo.md"), "# todo\n- by hand").unwrap();
+        let current = match storage.update_file_content(todo.id, vec![], "", Some(&todo.etag)) {
+            Err(StorageError::Stale(current)) => current,
+            result => panic!("expected a stale write, got {:?}", result),
+        };
+        assert_ne!(current.etag, todo.etag);
+        assert_eq!(storage.get_file(todo.id).as_ref(), Ok(&*current));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("todo.md")).unwrap(),
+            "# todo\n- by hand"
+        );
+        storage
+            .delete_file(todo.id, Some(&current.etag))
+            .expect("failed to delete file at its current version");
+    }
+
+    #[test]
+    fn files_removed_from_disk_are_not_found() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let id = find_file(&storage, "todo.md");
+        fs::remove_file(dir.path().join("todo.md")).unwrap();
+        assert!(matches!(
+            storage.get_file_content(id),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn files_can_be_created_updated_and_deleted() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let file = storage
+            .create_file(0, "new note.md", b"# new".to_vec(), "")
+            .expect("failed to create file");
+        assert_eq!(
+            fs::read(dir.path().join("new note.md")).unwrap(),
+            b"# new".to_vec()
+        );
+        assert_eq!(find_file(&storage, "new note.md"), file.id);
+
+        storage
+            .update_file_content(file.id, b"# updated".to_vec(), "", None)
+            .expect("failed to update file");
+        assert_eq!(
+            storage.get_file_content(file.id).unwrap().body,
+            b"# updated".to_vec()
+        );
+
+        storage
+            .delete_file(file.id, None)
+            .expect("failed to delete file");
+        assert!(!dir.path().join("new note.md").exists());
+        assert!(matches!(
+            storage.get_file(file.id),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_file_creation_is_rejected() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert!(matches!(
+            storage.create_file(0, "todo.md", vec![], ""),
+            Err(StorageError::Conflict(_))
+        ));
+        assert!(matches!(
+            storage.create_file(0, "../escape.md", vec![], ""),
+            Err(StorageError::InvalidName(_))
+        ));
+        assert!(matches!(
+            storage.create_file(0, "image.png", vec![], ""),
+            Err(StorageError::InvalidName(_))
+        ));
+        assert!(matches!(
+            storage.create_file(100, "note.md", vec![], ""),
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(
+            fs::read(dir.path().join("todo.md")).unwrap(),
+            b"# todo".to_vec()
+        );
+    }
+
+    #[test]
+    fn collections_can_be_created_renamed_moved_and_deleted() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let archive = storage
+            .create_collection(0, "archive 2026")
+            .expect("failed to create collection");
+        assert!(dir.path().join("archive 2026").is_dir());
+
+        let work = storage
+            .get_collection(0)
+            .unwrap()
+            .child_collections
+            .into_iter()
+            .find(|collection| collection.name == "work")
+            .unwrap();
+        let meetings = work.child_collections[0].clone();
+        let renamed = storage
+            .rename_collection(meetings.id, "réunions")
+            .expect("failed to rename collection");
+        assert_eq!(renamed.id, meetings.id);
+        assert!(dir.path().join("work/réunions/2026-10-01.md").is_file());
+
+        assert!(matches!(
+            storage.move_collection(work.id, meetings.id),
+            Err(StorageError::InvalidArgument(_))
+        ));
+        let moved = storage
+            .move_collection(work.id, archive.id)
+            .expect("failed to move collection");
+        assert_eq!(moved.id, work.id);
+        assert_eq!(moved.child_collections[0].files, meetings.files);
+        let path = dir.path().join("archive 2026/work/réunions/2026-10-01.md");
+        assert_eq!(fs::read(path).unwrap(), b"# standup".to_vec());
+        assert_eq!(
+            storage.get_file_content(meetings.files[0].id).unwrap().body,
+            b"# standup".to_vec()
+        );
+
+        assert!(matches!(
+            storage.delete_collection(archive.id, false),
+            Err(StorageError::Conflict(_))
+        ));
+        storage
+            .delete_collection(archive.id, true)
+            .expect("failed to delete collection");
+        assert!(!dir.path().join("archive 2026").exists());
+        assert!(matches!(
+            storage.get_file(meetings.files[0].id),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn files_can_be_moved_and_renamed() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let todo = find_file(&storage, "todo.md");
+        let standup = find_file(&storage, "2026-10-01.md");
+        let meetings = storage
+            .get_collection_all()
+            .into_iter()
+            .find(|collection| collection.name == "meetings")
+            .unwrap();
+
+        let (file, collection) = storage
+            .move_file(todo, meetings.id, "todo 2026.md", false)
+            .expect("failed to move file");
+        assert_eq!(file.id, todo);
+        assert_eq!(collection.id, meetings.id);
+        assert_eq!(collection.files.len(), 2);
+        assert!(!dir.path().join("todo.md").exists());
+        assert_eq!(
+            fs::read(dir.path().join("work/meetings/todo 2026.md")).unwrap(),
+            b"# todo".to_vec()
+        );
+
+        assert!(matches!(
+            storage.move_file(todo, meetings.id, "2026-10-01.md", false),
+            Err(StorageError::Conflict(_))
+        ));
+        let (file, collection) = storage
+            .move_file(todo, meetings.id, "2026-10-01.md", true)
+            .expect("failed to overwrite file");
+        assert_eq!(collection.files, vec![file]);
+        assert!(matches!(
+            storage.get_file(standup),
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(
+            storage.get_file_content(todo).unwrap().body,
+            b"# todo".to_vec()
+        );
+    }
+
+    #[test]
+    fn files_are_not_moved_over_untracked_entries() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let todo = find_file(&storage, "todo.md");
+        // hidden files aren't tracked by the storage
+        assert!(matches!(
+            storage.move_file(todo, 0, ".hidden.md", false),
+            Err(StorageError::Conflict(_))
+        ));
+        assert_eq!(
+            fs::read(dir.path().join(".hidden.md")).unwrap(),
+            b"# hidden".to_vec()
+        );
+        // not even when asked to overwrite, as only tracked files are replaced
+        assert!(matches!(
+            storage.move_file(todo, 0, ".hidden.md", true),
+            Err(StorageError::Conflict(_))
+        ));
+        // nor files written since the storage last looked
+        fs::write(dir.path().join("unseen.md"), "# unseen").unwrap();
+        assert!(matches!(
+            storage.move_file(todo, 0, "unseen.md", true),
+            Err(StorageError::Conflict(_))
+        ));
+        assert_eq!(
+            fs::read(dir.path().join("unseen.md")).unwrap(),
+            b"# unseen".to_vec()
+        );
+        assert_eq!(
+            fs::read(dir.path().join("todo.md")).unwrap(),
+            b"# todo".to_vec()
+        );
+    }
+
+    #[test]
+    fn failed_overwrites_keep_the_replaced_file() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        let todo = find_file(&storage, "todo.md");
+        let standup = find_file(&storage, "2026-10-01.md");
+        let Ok(ItemId::Collection(meetings)) = storage.resolve_path("work/meetings") else {
+            panic!("expected the meetings collection");
+        };
+        // the storage doesn't know the file is gone until it tries to move it
+        fs::remove_file(dir.path().join("todo.md")).unwrap();
+        assert!(storage
+            .move_file(todo, meetings, "2026-10-01.md", true)
+            .is_err());
+        assert_eq!(
+            fs::read(dir.path().join("work/meetings/2026-10-01.md")).unwrap(),
+            b"# standup".to_vec()
+        );
+        assert_eq!(
+            storage.get_path(ItemId::File(standup)).unwrap(),
+            "work/meetings/2026-10-01.md"
+        );
+        assert_eq!(
+            storage
+                .get_file_content(standup)
+                .map(|content| content.body),
+            Ok(b"# standup".to_vec())
+        );
+        assert_eq!(storage.list_trash(), Ok(vec![]));
+    }
+
+    #[test]
+    fn root_collection_can_not_be_changed() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert!(matches!(
+            storage.rename_collection(0, "other"),
+            Err(StorageError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            storage.delete_collection(0, true),
+            Err(StorageError::PermissionDenied(_))
+        ));
+        // name clashes with an existing file
+        assert!(matches!(
+            storage.create_collection(0, "todo.md"),
+            Err(StorageError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn root_must_be_a_directory() {
+        let dir = notes_dir();
+        assert!(FilesystemStorage::new(dir.path().join("todo.md")).is_err());
+        assert!(FilesystemStorage::new(dir.path().join("missing")).is_err());
+    }
+
+    /// Waits for the storage to pick up the next change made on disk
+    fn next_change(changes: &mut broadcast::Receiver<Change>) -> ChangeEvent {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match changes.try_recv() {
+                Ok(change) => return change.event,
+                Err(TryRecvError::Empty) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) => panic!("expected a change: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn unwatched_storage_has_no_change_feed() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert!(storage.changes().is_none());
+    }
+
+    #[test]
+    fn picks_up_files_edited_on_disk() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path())
+            .and_then(FilesystemStorage::watch)
+            .expect("failed to watch storage");
+        let mut changes = storage.changes().expect("expected a change feed");
+
+        fs::write(dir.path().join("work/new.md"), "# new").unwrap();
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::FileCreated);
+        let file = event.file.expect("expected a file");
+        assert_eq!(file.name, "new.md");
+        assert_eq!(storage.get_file(file.id), Ok(file.clone()));
+        assert_eq!(
+            storage.get_file_content(file.id).unwrap().body,
+            b"# new".to_vec()
+        );
+
+        fs::write(dir.path().join("work/new.md"), "# edited").unwrap();
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::FileModified);
+        let edited = event.file.expect("expected a file");
+        assert_eq!((edited.id, edited.size), (file.id, 8));
+        assert_ne!(edited.sha256, file.sha256);
+        assert_eq!(storage.get_file(file.id), Ok(edited.clone()));
+        let revisions = storage.list_revisions(file.id).unwrap();
+        let contents: Vec<_> = revisions
+            .iter()
+            .map(|revision| {
+                assert_eq!(revision.author, "");
+                storage
+                    .get_revision_content(file.id, revision.id)
+                    .unwrap()
+                    .body
+            })
+            .collect();
+        assert_eq!(contents, [b"# new".to_vec(), b"# edited".to_vec()]);
+
+        fs::remove_file(dir.path().join("work/new.md")).unwrap();
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::FileDeleted);
+        assert_eq!(event.file, Some(edited));
+        assert!(matches!(
+            storage.get_file(file.id),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn picks_up_directories_created_and_removed_on_disk() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path())
+            .and_then(FilesystemStorage::watch)
+            .expect("failed to watch storage");
+        let mut changes = storage.changes().expect("expected a change feed");
+
+        // written elsewhere first so that the directory shows up in one go
+        let staging = tempfile::tempdir_in(dir.path()).unwrap();
+        fs::write(staging.path().join("idea.md"), "# idea").unwrap();
+        fs::rename(staging.path(), dir.path().join("ideas")).unwrap();
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::CollectionCreated);
+        assert_eq!(event.collection_id, 0);
+        let collection = event.collection.expect("expected a collection");
+        assert_eq!(collection.name, "ideas");
+        assert_eq!(collection.files[0].name, "idea.md");
+        assert_eq!(
+            storage.get_collection(collection.id),
+            Ok(collection.clone())
+        );
+
+        fs::remove_dir_all(dir.path().join("ideas")).unwrap();
+        let mut deleted_collection = false;
+        while !deleted_collection {
+            let event = next_change(&mut changes);
+            deleted_collection = event.kind() == Kind::CollectionDeleted;
+        }
+        assert!(matches!(
+            storage.get_collection(collection.id),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn edits_made_on_disk_are_searchable() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path())
+            .and_then(FilesystemStorage::watch)
+            .map(Searchable::new)
+            .expect("failed to watch storage");
+        let mut changes = storage.changes().expect("expected a change feed");
+        let found = |query| {
+            let hits = storage
+                .search(query, 10, |_| true)
+                .expect("failed to search");
+            hits.into_iter()
+                .map(|hit| hit.file.expect("hits have a file").name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(found("standup"), ["2026-10-01.md"]);
+        // accents are ignored
+        assert_eq!(found("dia"), ["día 1.md"]);
+
+        fs::write(dir.path().join("work/meetings/2026-10-01.md"), "# retro").unwrap();
+        next_change(&mut changes);
+        assert_eq!(found("retro"), ["2026-10-01.md"]);
+        assert!(found("standup").is_empty());
+        fs::remove_file(dir.path().join("work/meetings/2026-10-01.md")).unwrap();
+        next_change(&mut changes);
+        assert!(found("retro").is_empty());
+    }
+
+    #[test]
+    fn own_writes_are_not_reported_as_edits() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path())
+            .and_then(FilesystemStorage::watch)
+            .expect("failed to watch storage");
+        let mut changes = storage.changes().expect("expected a change feed");
+
+        let file = storage
+            .create_file(0, "mine.md", b"# mine".to_vec(), "")
+            .expect("failed to create file");
+        storage
+            .update_file_content(file.id, b"# still mine".to_vec(), "", None)
+            .expect("failed to update file");
+        storage
+            .delete_file(file.id, None)
+            .expect("failed to delete file");
+        fs::write(dir.path().join("theirs.md"), "# theirs").unwrap();
+
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::FileCreated);
+        assert_eq!(event.file.expect("expected a file").name, "theirs.md");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_moved_on_disk_keep_their_ids() {
+        let dir = notes_dir();
+        let storage = FilesystemStorage::new(dir.path())
+            .and_then(FilesystemStorage::watch)
+            .expect("failed to watch storage");
+        let mut changes = storage.changes().expect("expected a change feed");
+        let todo = find_file(&storage, "todo.md");
+        let standup = find_file(&storage, "2026-10-01.md");
+        let Ok(ItemId::Collection(work)) = storage.resolve_path("work") else {
+            panic!("expected the work collection");
+        };
+        let Ok(ItemId::Collection(meetings)) = storage.resolve_path("work/meetings") else {
+            panic!("expected the meetings collection");
+        };
+
+        fs::rename(dir.path().join("todo.md"), dir.path().join("work/todo.md")).unwrap();
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::FileMoved);
+        assert_eq!(event.collection_id, work);
+        assert_eq!(event.previous_collection_id, 0);
+        assert_eq!(event.file.expect("expected a file").id, todo);
+
+        fs::rename(
+            dir.path().join("work/meetings"),
+            dir.path().join("work/standups"),
+        )
+        .unwrap();
+        let event = next_change(&mut changes);
+        assert_eq!(event.kind(), Kind::CollectionRenamed);
+        assert_eq!(event.collection_id, work);
+        let collection = event.collection.expect("expected a collection");
+        assert_eq!(collection.id, meetings);
+        assert_eq!(collection.name, "standups");
+        assert_eq!(collection.files[0].id, standup);
+        assert_eq!(
+            storage.get_path(ItemId::File(standup)).unwrap(),
+            "work/standups/2026-10-01.md"
+        );
+        drop(storage);
+
+        // the moves were saved as well
+        let storage = FilesystemStorage::new(dir.path()).expect("failed to open storage");
+        assert_eq!(
+            storage.get_path(ItemId::File(todo)).unwrap(),
+            "work/todo.md"
+        );
+        assert_eq!(
+            storage.get_path(ItemId::Collection(meetings)).unwrap(),
+            "work/standups"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{write_atomic, STATE_DIR};
+use crate::collection::{tree::FileMeta, unix_millis};
+use crate::oxygen::Revision;
+
+/// Directory in [`STATE_DIR`] keeping the past contents of the files
+const REVISIONS_DIR: &str = "revisions";
+/// File listing the revisions of a file, one
+/// `<id> <created> <size> <sha256> <author>` per line, oldest first
+const LOG_FILE: &str = "log";
+
+/// Revisions of the files of a [`super::FilesystemStorage`]. Each file with a
+/// history has a directory named after its id under `.oxygen/revisions`,
+/// holding the log of its revisions and the content of each of them in a file
+/// named after the revision id.
+#[derive(Debug, Clone)]
+pub(super) struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    /// History of the files stored at `root`
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join(STATE_DIR).join(REVISIONS_DIR),
+        }
+    }
+
+    fn file_dir(&self, id: u64) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// Revisions of the file `id`, oldest first
+    pub fn list(&self, id: u64) -> io::Result<Vec<Revision>> {
+        let path = self.file_dir(id).join(LOG_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let malformed = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed revision in {}: {:?}", path.display(), line),
+            )
+        };
+        content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.splitn(5, ' ');
+                let (Some(revision_id), Some(created), Some(size), Some(sha256), Some(author)) = (
+                    fields.next(),
+                    fields.next(),
+                    fields.next(),
+                    fields.next(),
+                    fields.next(),
+                ) else {
+                    return Err(malformed(line));
+                };
+                Ok(Revision {
+                    id: revision_id.parse().map_err(|_| malformed(line))?,
+                    file_id: id,
+                    created: created.parse().map_err(|_| malformed(line))?,
+                    author: author.to_string(),
+                    sha256: sha256.to_string(),
+                    size: size.parse().map_err(|_| malformed(line))?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn content(&self, id: u64, revision_id: u64) -> io::Result<Vec<u8>> {
+        fs::read(self.file_dir(id).join(revision_id.to_string()))
+    }
+
+    /// Records `body`, described by `meta`, as a new revision of the file `id`
+    pub fn record(&self, id: u64, body: &[u8], meta: &FileMeta, author: &str) -> io::Result<()> {
+        let dir = self.file_dir(id);
+        fs::create_dir_all(&dir)?;
+        let revision_id = self.list(id)?.last().map_or(1, |latest| latest.id + 1);
+        let revision = meta.to_revision(id, revision_id, author);
+        // the content goes first so that every logged revision has one
+        write_atomic(&dir.join(revision_id.to_string()), body)?;
+        let mut log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        writeln!(
+            log,
+            "{} {} {} {} {}",
+            revision.id, revision.created, revision.size, revision.sha256, revision.author
+        )
+    }
+
+    /// Records the content of the file `id` at `path` unless it is already the
+    /// content of its latest revision. Catches up with edits made on disk
+    /// while the storage wasn't watching, and with files that don't have a
+    /// history yet.
+    pub fn catch_up(&self, id: u64, path: &Path) -> io::Result<()> {
+        let body = fs::read(path)?;
+        let modified = fs::metadata(path)?.modified().map_or(0, unix_millis);
+        let meta = FileMeta::of(&body, 0, modified);
+        let latest = self.list(id)?.pop();
+        if latest.is_some_and(|latest| latest.sha256 == meta.sha256) {
+            return Ok(());
+        }
+        self.record(id, &body, &meta, "")
+    }
+
+    /// Forgets every revision of the file `id`
+    pub fn remove(&self, id: u64) -> io::Result<()> {
+        match fs::remove_dir_all(self.file_dir(id)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
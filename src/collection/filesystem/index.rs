@@ -0,0 +1,433 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use super::{list_dir, refresh_meta, write_atomic, EntryKind, ROOT_COLLECTION};
+use crate::collection::{tree::Tree, ItemId};
+
+/// Changes appended to the ids file before it is written again from scratch,
+/// unless the index has more entries than that
+const MIN_COMPACTION_LINES: usize = 1024;
+
+/// Where the entry with a given id was when the index was last saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Entry {
+    /// `/` separated, relative to the root like [`Tree::path_of`]
+    pub path: String,
+    /// Lets the entry be recognized once it has been renamed or moved
+    pub inode: Option<u64>,
+}
+
+/// Ids of the entries under the root of a [`super::FilesystemStorage`], saved
+/// as `<kind> <id> <inode> <path>` lines so that they survive restarts.
+/// Changes are appended to the file (removed entries as `removed <kind> <id>`),
+/// later lines win, until there are enough of them to write it again.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Index {
+    entries: HashMap<ItemId, Entry>,
+    next_collection_id: u64,
+    next_file_id: u64,
+    // lines appended since the file was last written in full
+    appended: usize,
+}
+
+/// Where the items touched since the index was last updated are now. Worked
+/// out from the tree while holding its lock, so that the disk is only touched
+/// once it is released.
+pub(super) struct Update {
+    /// `None` for items that are gone
+    items: BTreeMap<ItemId, Option<Moved>>,
+    next_ids: (u64, u64),
+}
+
+struct Moved {
+    path: String,
+    /// Entries that only moved along with their collection keep their inode
+    same_inode: bool,
+}
+
+impl Update {
+    /// Where the `touched` items of `tree` (and the content of the touched
+    /// collections) are now
+    pub fn of(tree: &Tree, touched: BTreeSet<ItemId>) -> Self {
+        let mut items = BTreeMap::new();
+        for item in touched {
+            // the root collection is the root itself, it can't move
+            if item == ItemId::Collection(ROOT_COLLECTION) {
+                continue;
+            }
+            let Ok(path) = tree.path_of(item) else {
+                items.insert(item, None);
+                continue;
+            };
+            if let ItemId::Collection(id) = item {
+                add_content(tree, id, &path, &mut items);
+            }
+            let moved = Moved {
+                path,
+                same_inode: false,
+            };
+            items.insert(item, Some(moved));
+        }
+        Self {
+            items,
+            next_ids: tree.next_ids(),
+        }
+    }
+}
+
+/// Adds everything in the collection `id`, stored at `path`, unless it was
+/// touched itself
+fn add_content(tree: &Tree, id: u64, path: &str, items: &mut BTreeMap<ItemId, Option<Moved>>) {
+    let Some(collection) = tree.collection(id) else {
+        return;
+    };
+    for file in &collection.files {
+        if let Some(node) = tree.file(*file) {
+            let path = format!("{}/{}", path, node.name);
+            items.entry(ItemId::File(*file)).or_insert(Some(Moved {
+                path,
+                same_inode: true,
+            }));
+        }
+    }
+    for child in &collection.children {
+        if let Some(node) = tree.collection(*child) {
+            let path = format!("{}/{}", path, node.name);
+            add_content(tree, *child, &path, items);
+            items
+                .entry(ItemId::Collection(*child))
+                .or_insert(Some(Moved {
+                    path,
+                    same_inode: true,
+                }));
+        }
+    }
+}
+
+/// Entry found on disk while scanning the root
+struct Found {
+    kind: EntryKind,
+    parent: String,
+    name: String,
+    inode: Option<u64>,
+}
+
+impl Found {
+    fn path(&self) -> String {
+        if self.parent.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.parent, self.name)
+        }
+    }
+}
+
+impl Index {
+    /// Index of everything in `tree`, which is stored at `root`
+    pub fn of(tree: &Tree, root: &Path) -> Self {
+        let items = tree
+            .collection_ids()
+            // the root collection is the root itself, it can't move
+            .filter(|id| *id != ROOT_COLLECTION)
+            .map(ItemId::Collection)
+            .chain(tree.file_ids().map(ItemId::File));
+        let entries = items
+            .filter_map(|item| {
+                let path = tree.path_of(item).ok()?;
+                let inode = inode(&root.join(&path));
+                Some((item, Entry { path, inode }))
+            })
+            .collect();
+        let (next_collection_id, next_file_id) = tree.next_ids();
+        Self {
+            entries,
+            next_collection_id,
+            next_file_id,
+            appended: 0,
+        }
+    }
+
+    /// Applies `update` to the entries stored under `root` and records it in
+    /// the ids file at `path`
+    pub fn apply(&mut self, update: Update, root: &Path, path: &Path) -> io::Result<()> {
+        let mut lines = vec![];
+        for (item, moved) in update.items {
+            let entry = moved.map(|moved| {
+                let inode = match self.entries.get(&item) {
+                    Some(entry) if moved.same_inode => entry.inode,
+                    _ => inode(&root.join(&moved.path)),
+                };
+                Entry {
+                    path: moved.path,
+                    inode,
+                }
+            });
+            match entry {
+                Some(entry) if self.entries.get(&item) != Some(&entry) => {
+                    lines.push(line(item, Some(&entry)));
+                    self.entries.insert(item, entry);
+                }
+                Some(_) => {}
+                None => {
+                    if self.entries.remove(&item).is_some() {
+                        lines.push(line(item, None));
+                    }
+                }
+            }
+        }
+        if update.next_ids != (self.next_collection_id, self.next_file_id) {
+            (self.next_collection_id, self.next_file_id) = update.next_ids;
+            lines.push(format!(
+                "next {} {}\n",
+                self.next_collection_id, self.next_file_id
+            ));
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.appended = self.appended.saturating_add(lines.len());
+        if self.appended > MIN_COMPACTION_LINES.max(self.entries.len()) {
+            return self.save(path);
+        }
+        let result = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(lines.concat().as_bytes()));
+        if result.is_err() {
+            // the file is missing lines (or is gone), it is written in full until
+            // that works
+            self.appended = usize::MAX;
+            return self.save(path);
+        }
+        Ok(())
+    }
+
+    pub fn inode(&self, item: ItemId) -> Option<u64> {
+        self.entries.get(&item)?.inode
+    }
+
+    /// Reads the index saved at `path`, an empty index if there is none yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        let malformed = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed id in {}: {:?}", path.display(), line),
+            )
+        };
+        // the last change might not have been written in full
+        let content = match content.rfind('\n') {
+            Some(end) => &content[..end],
+            None => "",
+        };
+        let mut index = Self::default();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let mut fields = line.splitn(4, ' ');
+            let (Some(kind), Some(first), Some(second)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(malformed(line));
+            };
+            if kind == "removed" {
+                let id = second.parse().map_err(|_| malformed(line))?;
+                let item = match first {
+                    "collection" => ItemId::Collection(id),
+                    "file" => ItemId::File(id),
+                    _ => return Err(malformed(line)),
+                };
+                index.entries.remove(&item);
+                continue;
+            }
+            let first = first.parse().map_err(|_| malformed(line))?;
+            if kind == "next" {
+                index.next_collection_id = first;
+                index.next_file_id = second.parse().map_err(|_| malformed(line))?;
+                continue;
+            }
+            let item = match kind {
+                "collection" => ItemId::Collection(first),
+                "file" => ItemId::File(first),
+                _ => return Err(malformed(line)),
+            };
+            let inode = match second {
+                "-" => None,
+                inode => Some(inode.parse().map_err(|_| malformed(line))?),
+            };
+            let path = fields.next().ok_or_else(|| malformed(line))?.to_string();
+            index.entries.insert(item, Entry { path, inode });
+        }
+        Ok(index)
+    }
+
+    /// Writes the whole index to `path`
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.path.contains('\n'))
+            .collect();
+        // collections first
+        entries.sort_by_key(|(item, _)| **item);
+        let mut content = format!("next {} {}\n", self.next_collection_id, self.next_file_id);
+        for (item, entry) in entries {
+            content.push_str(&line(*item, Some(entry)));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(path, content.as_bytes())?;
+        self.appended = 0;
+        Ok(())
+    }
+
+    /// Builds the tree of everything under `root`. Entries keep the id they
+    /// had when the index was saved, found by path or, for entries that were
+    /// renamed or moved since, by inode. Other entries get new ids.
+    pub fn scan(&self, root: &Path, root_name: String, include_hidden: bool) -> io::Result<Tree> {
+        let mut found = vec![];
+        walk(root, String::new(), include_hidden, &mut found)?;
+
+        let mut ids = vec![None; found.len()];
+        let mut claimed = HashSet::new();
+        let by_path: HashMap<_, _> = self
+            .entries
+            .iter()
+            .map(|(item, entry)| ((kind_of(*item), entry.path.as_str()), *item))
+            .collect();
+        for (entry, id) in found.iter().zip(&mut ids) {
+            if let Some(item) = by_path.get(&(entry.kind, entry.path().as_str())) {
+                claimed.insert(*item);
+                *id = Some(id_of(*item));
+            }
+        }
+        let by_inode: HashMap<_, _> = self
+            .entries
+            .iter()
+            .filter(|(item, _)| !claimed.contains(*item))
+            .filter_map(|(item, entry)| Some(((kind_of(*item), entry.inode?), *item)))
+            .collect();
+        for (entry, id) in found.iter().zip(&mut ids) {
+            let Some(inode) = entry.inode.filter(|_| id.is_none()) else {
+                continue;
+            };
+            if let Some(item) = by_inode.get(&(entry.kind, inode)) {
+                if claimed.insert(*item) {
+                    *id = Some(id_of(*item));
+                }
+            }
+        }
+
+        let mut tree = Tree::new();
+        tree.insert_collection_with_id(ROOT_COLLECTION, None, root_name);
+        // new ids must not clash with the ones handed back, even if the index
+        // was edited by hand
+        let (mut next_collection_id, mut next_file_id) =
+            (self.next_collection_id, self.next_file_id);
+        for item in self.entries.keys() {
+            match item {
+                ItemId::Collection(id) => next_collection_id = next_collection_id.max(id + 1),
+                ItemId::File(id) => next_file_id = next_file_id.max(id + 1),
+            }
+        }
+        tree.reserve_ids(next_collection_id, next_file_id);
+        let mut collections = HashMap::from([(String::new(), ROOT_COLLECTION)]);
+        for (entry, id) in found.into_iter().zip(ids) {
+            let parent = collections[&entry.parent];
+            let path = entry.path();
+            match (entry.kind, id) {
+                (EntryKind::Collection, Some(id)) => {
+                    tree.insert_collection_with_id(id, Some(parent), entry.name);
+                    collections.insert(path, id);
+                }
+                (EntryKind::Collection, None) => {
+                    let id = tree.insert_collection(Some(parent), entry.name);
+                    collections.insert(path, id);
+                }
+                (EntryKind::File, id) => {
+                    let id = match id {
+                        Some(id) => {
+                            tree.insert_file_with_id(id, parent, entry.name);
+                            id
+                        }
+                        None => tree.insert_file(parent, entry.name),
+                    };
+                    refresh_meta(&mut tree, id, &root.join(path));
+                }
+            }
+        }
+        Ok(tree)
+    }
+}
+
+/// Lists everything under `dir`, collections before their content
+fn walk(dir: &Path, path: String, include_hidden: bool, found: &mut Vec<Found>) -> io::Result<()> {
+    for (name, kind) in list_dir(dir, include_hidden)? {
+        let entry_path = dir.join(&name);
+        let entry = Found {
+            kind,
+            parent: path.clone(),
+            name,
+            inode: inode(&entry_path),
+        };
+        let relative = entry.path();
+        found.push(entry);
+        if kind == EntryKind::Collection {
+            walk(&entry_path, relative, include_hidden, found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Line of the ids file recording where `item` is, or that it is gone
+fn line(item: ItemId, entry: Option<&Entry>) -> String {
+    let (kind, id) = match item {
+        ItemId::Collection(id) => ("collection", id),
+        ItemId::File(id) => ("file", id),
+    };
+    match entry {
+        // can't be told apart from the next line, they get new ids instead
+        Some(entry) if !entry.path.contains('\n') => {
+            let inode = entry
+                .inode
+                .map_or_else(|| "-".to_string(), |inode| inode.to_string());
+            format!("{} {} {} {}\n", kind, id, inode, entry.path)
+        }
+        _ => format!("removed {} {}\n", kind, id),
+    }
+}
+
+fn kind_of(item: ItemId) -> EntryKind {
+    match item {
+        ItemId::Collection(_) => EntryKind::Collection,
+        ItemId::File(_) => EntryKind::File,
+    }
+}
+
+fn id_of(item: ItemId) -> u64 {
+    match item {
+        ItemId::Collection(id) | ItemId::File(id) => id,
+    }
+}
+
+#[cfg(unix)]
+pub(super) fn inode(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::symlink_metadata(path)
+        .ok()
+        .map(|metadata| metadata.ino())
+}
+
+/// Renamed entries are only recognized by their path elsewhere
+#[cfg(not(unix))]
+pub(super) fn inode(_path: &Path) -> Option<u64> {
+    None
+}
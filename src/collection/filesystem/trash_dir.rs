@@ -0,0 +1,205 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{file_meta, write_atomic, STATE_DIR};
+use crate::collection::{
+    trash::{Trash, TrashEntry},
+    tree::Tree,
+    ItemId,
+};
+use crate::oxygen::{trash_item, Collection};
+
+/// Directory in [`STATE_DIR`] keeping the deleted collections and files
+const TRASH_DIR: &str = "trash";
+/// File describing a trashed item: `deleted <time>`, `lineage <ids>` and
+/// `path <path>` lines followed by a `<kind> <id> <parent id> <name>` line for
+/// the item and everything in it, parents first. The item itself has `-` as
+/// its parent id.
+const ENTRY_FILE: &str = "entry";
+/// The trashed file or directory itself
+const CONTENT: &str = "content";
+
+/// Trash of a [`super::FilesystemStorage`]. Each trashed item has a directory
+/// named after it under `.oxygen/trash`, holding what was deleted and what is
+/// needed to put it back with the same ids.
+#[derive(Debug, Clone)]
+pub(super) struct TrashDir {
+    dir: PathBuf,
+}
+
+impl TrashDir {
+    /// Trash of the notes stored at `root`
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join(STATE_DIR).join(TRASH_DIR),
+        }
+    }
+
+    fn item_dir(&self, item: ItemId) -> PathBuf {
+        match item {
+            ItemId::Collection(id) => self.dir.join(format!("collection-{}", id)),
+            ItemId::File(id) => self.dir.join(format!("file-{}", id)),
+        }
+    }
+
+    /// Moves `view` (as given by [`Tree::to_item`]), stored at `path`, to the
+    /// trash
+    pub fn put(&self, view: &trash_item::Item, entry: &TrashEntry, path: &Path) -> io::Result<()> {
+        let lineage: Vec<_> = entry.lineage.iter().map(u64::to_string).collect();
+        let mut content = format!(
+            "deleted {}\nlineage {}\npath {}\n",
+            entry.deleted,
+            lineage.join(" "),
+            entry.path
+        );
+        let item = match view {
+            trash_item::Item::Collection(collection) => {
+                describe_collection(collection, None, &mut content);
+                ItemId::Collection(collection.id)
+            }
+            trash_item::Item::File(file) => {
+                content.push_str(&format!("file {} - {}\n", file.id, file.name));
+                ItemId::File(file.id)
+            }
+        };
+        let dir = self.item_dir(item);
+        fs::create_dir_all(&dir)?;
+        write_atomic(&dir.join(ENTRY_FILE), content.as_bytes())?;
+        fs::rename(path, dir.join(CONTENT)).inspect_err(|_| {
+            let _ = fs::remove_dir_all(&dir);
+        })
+    }
+
+    /// Moves `item` out of the trash to `path`
+    pub fn restore(&self, item: ItemId, path: &Path) -> io::Result<()> {
+        let dir = self.item_dir(item);
+        fs::rename(dir.join(CONTENT), path)?;
+        fs::remove_dir_all(dir)
+    }
+
+    /// Deletes `item` for good
+    pub fn purge(&self, item: ItemId) -> io::Result<()> {
+        match fs::remove_dir_all(self.item_dir(item)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Adds the items saved in the trash to `trash`
+    pub fn load(&self, trash: &mut Trash) -> io::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let dir = entry?.path();
+            let (item, entry, mut tree) = load_entry(&dir.join(ENTRY_FILE))?;
+            trash.insert(item, entry, &mut tree);
+        }
+        Ok(())
+    }
+}
+
+/// Adds a line for `collection` (in the collection `parent`) and everything in
+/// it to `content`
+fn describe_collection(collection: &Collection, parent: Option<u64>, content: &mut String) {
+    let parent = parent.map_or("-".to_string(), |parent| parent.to_string());
+    content.push_str(&format!(
+        "collection {} {} {}\n",
+        collection.id, parent, collection.name
+    ));
+    for file in &collection.files {
+        content.push_str(&format!(
+            "file {} {} {}\n",
+            file.id, collection.id, file.name
+        ));
+    }
+    for child in &collection.child_collections {
+        describe_collection(child, Some(collection.id), content);
+    }
+}
+
+/// Reads the entry file at `path`. Returns the trashed item along with a tree
+/// holding it, with the contents of its files read from the trash.
+fn load_entry(path: &Path) -> io::Result<(ItemId, TrashEntry, Tree)> {
+    let content = fs::read_to_string(path)?;
+    let malformed = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed trash entry in {}: {:?}", path.display(), line),
+        )
+    };
+    let mut entry = TrashEntry {
+        lineage: vec![],
+        path: String::new(),
+        deleted: 0,
+    };
+    let mut item = None;
+    let mut tree = Tree::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "deleted" => entry.deleted = value.parse().map_err(|_| malformed(line))?,
+            "lineage" => {
+                entry.lineage = value
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| malformed(line))?
+            }
+            "path" => entry.path = value.to_string(),
+            "collection" | "file" => {
+                let mut fields = value.splitn(3, ' ');
+                let (Some(id), Some(parent), Some(name)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(malformed(line));
+                };
+                let id = id.parse().map_err(|_| malformed(line))?;
+                let parent = match parent {
+                    "-" => None,
+                    parent => Some(parent.parse().map_err(|_| malformed(line))?),
+                };
+                let known = |parent| tree.collection(parent).is_some();
+                match (key, parent) {
+                    ("collection", None) if item.is_none() => {
+                        tree.insert_collection_with_id(id, None, name.to_string());
+                        item = Some(ItemId::Collection(id));
+                    }
+                    ("collection", Some(parent)) if known(parent) => {
+                        tree.insert_collection_with_id(id, Some(parent), name.to_string());
+                    }
+                    ("file", None) if item.is_none() => {
+                        // files need a collection to be in
+                        tree.insert_collection_with_id(0, None, String::new());
+                        tree.insert_file_with_id(id, 0, name.to_string());
+                        item = Some(ItemId::File(id));
+                    }
+                    ("file", Some(parent)) if known(parent) => {
+                        tree.insert_file_with_id(id, parent, name.to_string());
+                    }
+                    _ => return Err(malformed(line)),
+                }
+            }
+            _ => return Err(malformed(line)),
+        }
+    }
+    let item = item.ok_or_else(|| malformed(""))?;
+    let content = path.with_file_name(CONTENT);
+    let files: Vec<_> = tree.file_ids().collect();
+    for id in files {
+        let path = match item {
+            ItemId::File(_) => content.clone(),
+            // the trashed collection is the root of the tree
+            ItemId::Collection(_) => content.join(
+                tree.path_of(ItemId::File(id))
+                    .expect("file was just inserted"),
+            ),
+        };
+        tree.set_file_meta(id, file_meta(&path)?);
+    }
+    Ok((item, entry, tree))
+}
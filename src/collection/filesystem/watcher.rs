@@ -0,0 +1,521 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use notify::{event::EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::broadcast;
+
+use super::{
+    history::History,
+    index::{self, Index},
+    is_hidden, list_dir, refresh_meta, save_index, scan_dir, EntryKind, Stamp, Stamps,
+    ROOT_COLLECTION, STATE_DIR,
+};
+use crate::collection::{tree::Tree, Change, ItemId};
+use crate::oxygen::{change_event::Kind, ChangeEvent, Collection, File};
+
+/// Editors tend to save in several steps (ex: vim moves the original file out
+/// of the way before writing the new one), so events are only applied once the
+/// root has been quiet for a little while.
+const QUIET_PERIOD: Duration = Duration::from_millis(100);
+/// Longest events are held back while the root keeps changing
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Applies edits made on disk to the tree of a [`super::FilesystemStorage`]
+pub(super) struct Reconciler {
+    pub root: PathBuf,
+    pub include_hidden: bool,
+    pub tree: Arc<RwLock<Tree>>,
+    pub writes: Arc<Mutex<()>>,
+    pub stamps: Arc<Mutex<Stamps>>,
+    pub changes: broadcast::Sender<Change>,
+    pub index: Arc<Mutex<Index>>,
+    pub history: History,
+}
+
+/// Paths touched by a burst of events
+#[derive(Default)]
+struct Batch {
+    paths: BTreeSet<PathBuf>,
+    // some events were lost, only a full scan can tell what changed
+    rescan: bool,
+}
+
+impl Batch {
+    fn add(&mut self, event: notify::Result<notify::Event>) {
+        match event {
+            Ok(event) => {
+                if event.need_rescan() {
+                    self.rescan = true;
+                }
+                // reads don't change anything
+                if !matches!(event.kind, EventKind::Access(_)) {
+                    self.paths.extend(event.paths);
+                }
+            }
+            Err(err) => {
+                eprintln!("Failed to watch for changes: {}", err);
+                self.rescan = true;
+            }
+        }
+    }
+}
+
+/// Starts watching `reconciler.root`. Events are applied in a background
+/// thread for as long as the returned watcher is alive.
+pub(super) fn spawn(reconciler: Reconciler) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // the thread only stops once the watcher (and so this sender) is gone
+        let _ = tx.send(event);
+    })?;
+    watcher.watch(&reconciler.root, RecursiveMode::Recursive)?;
+    thread::Builder::new()
+        .name("oxygen-watcher".to_string())
+        .spawn(move || reconciler.run(rx))?;
+    Ok(watcher)
+}
+
+impl Reconciler {
+    fn run(self, events: mpsc::Receiver<notify::Result<notify::Event>>) {
+        while let Ok(event) = events.recv() {
+            let mut batch = Batch::default();
+            batch.add(event);
+            let started = Instant::now();
+            while started.elapsed() < MAX_DELAY {
+                match events.recv_timeout(QUIET_PERIOD) {
+                    Ok(event) => batch.add(event),
+                    Err(_) => break,
+                }
+            }
+            self.apply(batch);
+        }
+    }
+
+    fn apply(&self, batch: Batch) {
+        let _writing = self.writes.lock().expect("storage lock poisoned");
+        // the batch is worked out on a copy of the tree, so that readers aren't
+        // held up while the disk is read
+        let mut tree = self.tree.read().expect("storage lock poisoned").clone();
+        let mut stamps = self.stamps.lock().expect("storage lock poisoned");
+        let mut changes = vec![];
+        if batch.rescan {
+            self.reconcile(
+                &mut tree,
+                &mut stamps,
+                ROOT_COLLECTION,
+                &self.root,
+                true,
+                &mut changes,
+            );
+        }
+
+        // the directories holding the touched paths are compared with the tree,
+        // which also covers paths that vanished
+        let mut dirs = BTreeMap::new();
+        for path in &batch.paths {
+            let Some(dir) = path.parent() else {
+                continue;
+            };
+            if let Some((id, dir)) = self.tracked_ancestor(&tree, dir) {
+                dirs.insert(id, dir);
+            }
+        }
+        for (id, dir) in dirs {
+            // might have vanished along with one of its ancestors
+            if tree.collection(id).is_some() {
+                self.reconcile(&mut tree, &mut stamps, id, &dir, false, &mut changes);
+            }
+        }
+        let mut changes = self.keep_moved_ids(&mut tree, &mut stamps, changes);
+
+        // content changes don't show up in the directory listing
+        for path in &batch.paths {
+            let Some(id) = self.tracked_file(&tree, path) else {
+                continue;
+            };
+            let Ok(stamp) = Stamp::of(path) else {
+                continue;
+            };
+            if stamps.insert(id, stamp) != Some(stamp) {
+                refresh_meta(&mut tree, id, path);
+                let file = tree.to_file(id).expect("file is tracked");
+                let lineage = tree.collection_lineage(file.collection_id);
+                changes.push(file_change(Kind::FileModified, file, lineage));
+            }
+        }
+        drop(stamps);
+        *self.tree.write().expect("storage lock poisoned") = tree;
+        if !changes.is_empty() {
+            save_index(&self.root, &self.tree, &self.index);
+            let tree = self.tree.read().expect("storage lock poisoned");
+            for change in &changes {
+                self.update_history(&tree, change);
+            }
+        }
+
+        for change in changes {
+            // it is fine if nobody is watching
+            let _ = self.changes.send(change);
+        }
+    }
+
+    /// Brings the collection `id` (stored at `dir`) in line with the disk.
+    /// Sub collections are only looked at when `recursive` is set, new ones
+    /// are always scanned in full.
+    fn reconcile(
+        &self,
+        tree: &mut Tree,
+        stamps: &mut Stamps,
+        id: u64,
+        dir: &Path,
+        recursive: bool,
+        changes: &mut Vec<Change>,
+    ) {
+        let entries = match list_dir(dir, self.include_hidden) {
+            Ok(entries) => entries,
+            Err(err) => {
+                // a vanished directory is dealt with along with its parent
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("Failed to read {}: {}", dir.display(), err);
+                }
+                return;
+            }
+        };
+        let on_disk = |name: &str, kind: EntryKind| {
+            entries
+                .iter()
+                .any(|(entry_name, entry_kind)| entry_name == name && *entry_kind == kind)
+        };
+        let node = tree
+            .require_collection(id)
+            .expect("collection is tracked")
+            .clone();
+
+        for child in node.children {
+            let name = tree
+                .collection(child)
+                .expect("child is tracked")
+                .name
+                .clone();
+            if on_disk(&name, EntryKind::Collection) {
+                if recursive {
+                    self.reconcile(tree, stamps, child, &dir.join(&name), true, changes);
+                }
+                continue;
+            }
+            let collection = tree.to_collection(child).expect("child is tracked");
+            let lineage = tree.collection_lineage(child);
+            let (_, removed_files) = tree.remove_collection(child);
+            for file in removed_files {
+                stamps.remove(&file);
+            }
+            changes.push(collection_change(
+                Kind::CollectionDeleted,
+                collection,
+                id,
+                lineage,
+            ));
+        }
+        for file in node.files {
+            let name = &tree.file(file).expect("file is tracked").name;
+            if on_disk(name, EntryKind::File) {
+                continue;
+            }
+            let removed = tree.to_file(file).expect("file is tracked");
+            tree.remove_file(file);
+            stamps.remove(&file);
+            changes.push(file_change(
+                Kind::FileDeleted,
+                removed,
+                tree.collection_lineage(id),
+            ));
+        }
+
+        for (name, kind) in entries {
+            let path = dir.join(&name);
+            match kind {
+                EntryKind::Collection => {
+                    let tracked = tree
+                        .require_collection(id)
+                        .expect("collection is tracked")
+                        .children
+                        .iter()
+                        .any(|child| {
+                            tree.collection(*child).map(|c| c.name.as_str()) == Some(&name)
+                        });
+                    if tracked {
+                        continue;
+                    }
+                    let child = tree.insert_collection(Some(id), name);
+                    if let Err(err) = scan_dir(&path, child, self.include_hidden, tree) {
+                        eprintln!("Failed to read {}: {}", path.display(), err);
+                    }
+                    changes.push(collection_change(
+                        Kind::CollectionCreated,
+                        tree.to_collection(child)
+                            .expect("collection was just inserted"),
+                        id,
+                        tree.collection_lineage(child),
+                    ));
+                }
+                EntryKind::File => {
+                    if tree.find_file(id, &name).is_some() {
+                        continue;
+                    }
+                    let file = tree.insert_file(id, name);
+                    if let Ok(stamp) = Stamp::of(&path) {
+                        stamps.insert(file, stamp);
+                    }
+                    refresh_meta(tree, file, &path);
+                    changes.push(file_change(
+                        Kind::FileCreated,
+                        tree.to_file(file).expect("file was just inserted"),
+                        tree.collection_lineage(id),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Records the content of files created or modified on disk as revisions
+    /// without an author, and forgets the revisions of deleted files
+    fn update_history(&self, tree: &Tree, change: &Change) {
+        let event = &change.event;
+        let result = match (event.kind(), &event.file, &event.collection) {
+            (Kind::FileCreated | Kind::FileModified, Some(file), _) => {
+                match tree.path_of(ItemId::File(file.id)) {
+                    Ok(path) => self.history.catch_up(file.id, &self.root.join(path)),
+                    Err(_) => Ok(()),
+                }
+            }
+            (Kind::FileDeleted, Some(file), _) => self.history.remove(file.id),
+            (Kind::CollectionDeleted, _, Some(collection)) => {
+                let mut pending = vec![collection];
+                let mut result = Ok(());
+                while let Some(current) = pending.pop() {
+                    for file in &current.files {
+                        result = result.and(self.history.remove(file.id));
+                    }
+                    pending.extend(&current.child_collections);
+                }
+                result
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!(
+                "Failed to update the revisions of {}: {}",
+                self.root.display(),
+                err
+            );
+        }
+    }
+
+    /// Entries renamed or moved on disk show up as deleted from where they
+    /// were and created where they are now. Those are told apart from actual
+    /// deletions by their inode, and get their id back.
+    fn keep_moved_ids(
+        &self,
+        tree: &mut Tree,
+        stamps: &mut Stamps,
+        changes: Vec<Change>,
+    ) -> Vec<Change> {
+        let index = self.index.lock().expect("storage lock poisoned");
+        let mut deleted = HashMap::new();
+        for (position, change) in changes.iter().enumerate() {
+            let event = &change.event;
+            let (kind, item) = match (event.kind(), &event.file, &event.collection) {
+                (Kind::FileDeleted, Some(file), _) => (EntryKind::File, ItemId::File(file.id)),
+                (Kind::CollectionDeleted, _, Some(collection)) => {
+                    (EntryKind::Collection, ItemId::Collection(collection.id))
+                }
+                _ => continue,
+            };
+            if let Some(inode) = index.inode(item) {
+                deleted.insert((kind, inode), position);
+            }
+        }
+        drop(index);
+
+        let mut changes: Vec<_> = changes.into_iter().map(Some).collect();
+        for position in 0..changes.len() {
+            let Some(event) = changes[position].as_ref().map(|change| &change.event) else {
+                continue;
+            };
+            let (kind, item) = match (event.kind(), &event.file, &event.collection) {
+                (Kind::FileCreated, Some(file), _) => (EntryKind::File, ItemId::File(file.id)),
+                (Kind::CollectionCreated, _, Some(collection)) => {
+                    (EntryKind::Collection, ItemId::Collection(collection.id))
+                }
+                _ => continue,
+            };
+            let Some(inode) = tree
+                .path_of(item)
+                .ok()
+                .and_then(|path| index::inode(&self.root.join(path)))
+            else {
+                continue;
+            };
+            let Some(removed) = deleted
+                .remove(&(kind, inode))
+                .and_then(|deleted| changes[deleted].take())
+            else {
+                continue;
+            };
+            let created = changes[position].take().expect("change was just looked at");
+            changes[position] = Some(moved(tree, stamps, created, removed));
+        }
+        changes.into_iter().flatten().collect()
+    }
+
+    /// The deepest collection on the way from the root to `dir`, along with
+    /// its path. Hidden directories are left alone unless they are tracked, the
+    /// storage's own directory always is.
+    fn tracked_ancestor(&self, tree: &Tree, dir: &Path) -> Option<(u64, PathBuf)> {
+        let relative = dir.strip_prefix(&self.root).ok()?;
+        let mut id = ROOT_COLLECTION;
+        let mut path = self.root.clone();
+        for component in relative.components() {
+            let Component::Normal(name) = component else {
+                return None;
+            };
+            let name = name.to_str()?;
+            if name == STATE_DIR || (!self.include_hidden && is_hidden(name)) {
+                return None;
+            }
+            let child = tree
+                .collection(id)?
+                .children
+                .iter()
+                .copied()
+                .find(|child| tree.collection(*child).map(|c| c.name.as_str()) == Some(name));
+            match child {
+                Some(child) => {
+                    id = child;
+                    path.push(name);
+                }
+                None => break,
+            }
+        }
+        Some((id, path))
+    }
+
+    /// Id of the file stored at `path` if the storage tracks it
+    fn tracked_file(&self, tree: &Tree, path: &Path) -> Option<u64> {
+        let name = path.file_name()?.to_str()?;
+        let (id, dir) = self.tracked_ancestor(tree, path.parent()?)?;
+        if dir != path.parent()? {
+            return None;
+        }
+        tree.find_file(id, name)
+    }
+}
+
+/// Gives the entry of the `created` change the id it had in the `deleted` one,
+/// and turns both into a single change.
+fn moved(tree: &mut Tree, stamps: &mut Stamps, created: Change, deleted: Change) -> Change {
+    let mut collections = deleted.collections;
+    match (created.event.file, deleted.event.file) {
+        (Some(new), Some(old)) => {
+            renumber_file(tree, stamps, new.id, old.id);
+            let file = tree.to_file(old.id).expect("file was just renumbered");
+            collections.extend(tree.collection_lineage(file.collection_id));
+            Change {
+                event: ChangeEvent {
+                    kind: Kind::FileMoved as i32,
+                    collection_id: file.collection_id,
+                    previous_collection_id: old.collection_id,
+                    file: Some(file),
+                    ..Default::default()
+                },
+                collections,
+            }
+        }
+        _ => {
+            let new = created
+                .event
+                .collection
+                .expect("collection changes carry the collection");
+            let old = deleted
+                .event
+                .collection
+                .expect("collection changes carry the collection");
+            renumber_collection(tree, stamps, &new, &old);
+            collections.extend(tree.collection_lineage(old.id));
+            let parent = created.event.collection_id;
+            let previous_parent = deleted.event.collection_id;
+            let (kind, previous_collection_id) = if parent == previous_parent {
+                (Kind::CollectionRenamed, 0)
+            } else {
+                (Kind::CollectionMoved, previous_parent)
+            };
+            Change {
+                event: ChangeEvent {
+                    kind: kind as i32,
+                    collection: tree.to_collection(old.id),
+                    collection_id: parent,
+                    previous_collection_id,
+                    ..Default::default()
+                },
+                collections,
+            }
+        }
+    }
+}
+
+/// Gives the collection `new` the ids of `old`, down to the entries it still
+/// has under the same name
+fn renumber_collection(tree: &mut Tree, stamps: &mut Stamps, new: &Collection, old: &Collection) {
+    tree.renumber_collection(new.id, old.id);
+    for child in &new.child_collections {
+        if let Some(old_child) = old.child_collections.iter().find(|c| c.name == child.name) {
+            renumber_collection(tree, stamps, child, old_child);
+        }
+    }
+    for file in &new.files {
+        if let Some(old_file) = old.files.iter().find(|f| f.name == file.name) {
+            renumber_file(tree, stamps, file.id, old_file.id);
+        }
+    }
+}
+
+fn renumber_file(tree: &mut Tree, stamps: &mut Stamps, id: u64, new_id: u64) {
+    tree.renumber_file(id, new_id);
+    if let Some(stamp) = stamps.remove(&id) {
+        stamps.insert(new_id, stamp);
+    }
+}
+
+fn file_change(kind: Kind, file: File, collections: Vec<u64>) -> Change {
+    Change {
+        event: ChangeEvent {
+            kind: kind as i32,
+            collection_id: file.collection_id,
+            file: Some(file),
+            ..Default::default()
+        },
+        collections,
+    }
+}
+
+fn collection_change(
+    kind: Kind,
+    collection: Collection,
+    parent: u64,
+    collections: Vec<u64>,
+) -> Change {
+    Change {
+        event: ChangeEvent {
+            kind: kind as i32,
+            collection: Some(collection),
+            collection_id: parent,
+            ..Default::default()
+        },
+        collections,
+    }
+}
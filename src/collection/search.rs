@@ -0,0 +1,796 @@
+//! Full-text search over the names and contents of the files of a storage.
+//!
+//! [`Searchable`] wraps any [`Storage`] and keeps an in memory inverted index
+//! of its files. The index is built on the first search. From then on the
+//! writes going through the wrapper, and the changes the storage picks up on
+//! its own, mark files for reindexing before the next search.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+use tantivy::{
+    collector::TopDocs,
+    query::{BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, PhraseQuery, Query, TermQuery},
+    schema::{
+        Field, IndexRecordOption, Schema, TantivyDocument, TextFieldIndexing, TextOptions, Value,
+        INDEXED, STORED,
+    },
+    tokenizer::{AsciiFoldingFilter, LowerCaser, RemoveLongFilter, SimpleTokenizer, TextAnalyzer},
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyError, Term,
+};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use super::{Change, ItemId, Storage, StorageError, StorageResult, User};
+use crate::oxygen::{
+    change_event::Kind, trash_item, AclEntry, Collection, File, FileContent, Highlight, Revision,
+    SearchHit, TrashItem,
+};
+
+/// Name the analyzer of [`analyzer`] is registered under
+const TOKENIZER: &str = "notes";
+/// Memory the index writer buffers documents in, the least tantivy accepts
+const WRITER_MEMORY: usize = 15_000_000;
+/// Matches in names count this much more than matches in contents
+const NAME_BOOST: f32 = 2.0;
+/// Bytes of content shown before the first match of a snippet
+const SNIPPET_CONTEXT: usize = 40;
+/// Most bytes of content in a snippet
+const SNIPPET_LENGTH: usize = 200;
+
+impl From<TantivyError> for StorageError {
+    fn from(err: TantivyError) -> Self {
+        StorageError::Io(format!("search index: {}", err))
+    }
+}
+
+/// Splits text into the words that are indexed and searched for: runs of
+/// letters and digits, lower cased and without accents
+fn analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter)
+        .build()
+}
+
+/// Words of `text` along with their byte ranges in it
+fn words(analyzer: &mut TextAnalyzer, text: &str) -> Vec<(String, usize, usize)> {
+    let mut words = vec![];
+    let mut stream = analyzer.token_stream(text);
+    while let Some(token) = stream.next() {
+        words.push((token.text.clone(), token.offset_from, token.offset_to));
+    }
+    words
+}
+
+/// Part of a search query, all of which must match
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Clause {
+    Term(String),
+    /// Words that follow one another
+    Phrase(Vec<String>),
+    /// Beginning of a word
+    Prefix(String),
+}
+
+/// Parses `query`: words, `"quoted phrases"` and prefixes (`meet*`). Words
+/// that the analyzer splits in several (ex: `2026-10-01`) are phrases. Fails
+/// with [`StorageError::InvalidArgument`] if there is nothing to search for.
+fn parse_query(analyzer: &mut TextAnalyzer, query: &str) -> StorageResult<Vec<Clause>> {
+    let mut clauses = vec![];
+    let mut rest = query.trim_start();
+    while !rest.is_empty() {
+        let (part, quoted, remainder) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let (phrase, remainder) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (phrase, true, remainder)
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '"')
+                    .unwrap_or(rest.len());
+                (&rest[..end], false, &rest[end..])
+            }
+        };
+        rest = remainder.trim_start();
+        let (part, prefix) = match part.strip_suffix('*') {
+            Some(part) if !quoted => (part, true),
+            _ => (part, false),
+        };
+        let mut terms: Vec<_> = words(analyzer, part)
+            .into_iter()
+            .map(|(word, _, _)| word)
+            .collect();
+        let prefix = if prefix { terms.pop() } else { None };
+        match terms.len() {
+            0 => {}
+            1 => clauses.push(Clause::Term(terms.remove(0))),
+            _ => clauses.push(Clause::Phrase(terms)),
+        }
+        clauses.extend(prefix.map(Clause::Prefix));
+    }
+    if clauses.is_empty() {
+        return Err(StorageError::InvalidArgument(format!(
+            "search query {:?} has no words",
+            query
+        )));
+    }
+    Ok(clauses)
+}
+
+/// Part of `body` around the first match of `clauses`, along with the matches
+/// in it. Empty if nothing in `body` matches.
+fn snippet(
+    analyzer: &mut TextAnalyzer,
+    body: &str,
+    clauses: &[Clause],
+) -> (String, Vec<Highlight>) {
+    let words = words(analyzer, body);
+    let mut matched = vec![false; words.len()];
+    for (i, (word, _, _)) in words.iter().enumerate() {
+        for clause in clauses {
+            match clause {
+                Clause::Term(term) => matched[i] |= word == term,
+                Clause::Prefix(prefix) => matched[i] |= word.starts_with(prefix.as_str()),
+                Clause::Phrase(terms) => {
+                    let follows = words[i..]
+                        .iter()
+                        .map(|(word, _, _)| word)
+                        .take(terms.len())
+                        .eq(terms.iter());
+                    if follows {
+                        matched[i..i + terms.len()].fill(true);
+                    }
+                }
+            }
+        }
+    }
+    let mut ranges = words
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| *matched)
+        .map(|((_, from, to), _)| (*from, *to));
+    let Some((first, first_end)) = ranges.next() else {
+        return (String::new(), vec![]);
+    };
+    let floor = |mut at: usize| {
+        while !body.is_char_boundary(at) {
+            at -= 1;
+        }
+        at
+    };
+    let mut start = floor(first.saturating_sub(SNIPPET_CONTEXT));
+    let cut = start > 0 && !body[..start].ends_with(char::is_whitespace);
+    // don't start in the middle of a word
+    if let Some(space) = body[start..first].find(char::is_whitespace).filter(|_| cut) {
+        start += space;
+        start += body[start..].chars().next().map_or(0, char::len_utf8);
+    }
+    let end = floor((start + SNIPPET_LENGTH).min(body.len())).max(first_end);
+    let highlights = [(first, first_end)]
+        .into_iter()
+        .chain(ranges)
+        .filter(|(_, to)| *to <= end)
+        .map(|(from, to)| Highlight {
+            start: (from - start) as u32,
+            end: (to - start) as u32,
+        })
+        .collect();
+    (body[start..end].to_string(), highlights)
+}
+
+/// Fields of the documents of the index, one per file
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    id: Field,
+    name: Field,
+    body: Field,
+}
+
+/// Inverted index of the names and contents of files
+struct SearchIndex {
+    fields: Fields,
+    analyzer: TextAnalyzer,
+    writer: IndexWriter,
+    reader: IndexReader,
+}
+
+impl SearchIndex {
+    fn new() -> StorageResult<Self> {
+        let text = TextOptions::default()
+            .set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(TOKENIZER)
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            )
+            .set_stored();
+        let mut schema = Schema::builder();
+        let fields = Fields {
+            id: schema.add_u64_field("id", INDEXED | STORED),
+            name: schema.add_text_field("name", text.clone()),
+            body: schema.add_text_field("body", text),
+        };
+        let index = Index::create_in_ram(schema.build());
+        let analyzer = analyzer();
+        index.tokenizers().register(TOKENIZER, analyzer.clone());
+        Ok(Self {
+            fields,
+            analyzer,
+            writer: index.writer_with_num_threads(1, WRITER_MEMORY)?,
+            reader: index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::Manual)
+                .try_into()?,
+        })
+    }
+
+    /// Replaces what is indexed for the file `id` with `file` and `body`,
+    /// or removes it if `file` is `None`. Only searchable once committed.
+    fn put(&self, id: u64, file: Option<(&File, &str)>) -> StorageResult<()> {
+        self.writer
+            .delete_term(Term::from_field_u64(self.fields.id, id));
+        if let Some((file, body)) = file {
+            let mut document = TantivyDocument::default();
+            document.add_u64(self.fields.id, id);
+            document.add_text(self.fields.name, &file.name);
+            document.add_text(self.fields.body, body);
+            self.writer.add_document(document)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> StorageResult<()> {
+        self.writer.delete_all_documents()?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> StorageResult<()> {
+        self.writer.commit()?;
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    fn query(&self, clauses: &[Clause]) -> BooleanQuery {
+        let field_query = |field: Field, clause: &Clause| -> Box<dyn Query> {
+            let term = |word: &str| Term::from_field_text(field, word);
+            match clause {
+                Clause::Term(word) => Box::new(TermQuery::new(
+                    term(word),
+                    IndexRecordOption::WithFreqsAndPositions,
+                )),
+                Clause::Phrase(words) => Box::new(PhraseQuery::new(
+                    words.iter().map(|word| term(word)).collect(),
+                )),
+                Clause::Prefix(prefix) => {
+                    Box::new(FuzzyTermQuery::new_prefix(term(prefix), 0, true))
+                }
+            }
+        };
+        BooleanQuery::new(
+            clauses
+                .iter()
+                .map(|clause| {
+                    let name = BoostQuery::new(field_query(self.fields.name, clause), NAME_BOOST);
+                    let either = BooleanQuery::new(vec![
+                        (Occur::Should, Box::new(name) as Box<dyn Query>),
+                        (Occur::Should, field_query(self.fields.body, clause)),
+                    ]);
+                    (Occur::Must, Box::new(either) as Box<dyn Query>)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Files the index has to catch up with
+#[derive(Debug)]
+struct Pending {
+    /// The whole index has to be rebuilt
+    everything: bool,
+    files: HashSet<u64>,
+}
+
+/// [`Storage`] that can be searched, see the module documentation.
+///
+/// Files are indexed by name and, when it is text, content.
+pub struct Searchable<S> {
+    storage: S,
+    /// Built on the first search
+    index: Mutex<Option<SearchIndex>>,
+    pending: Mutex<Pending>,
+    /// Changes the storage picked up on its own since the last search
+    changes: Mutex<Option<broadcast::Receiver<Change>>>,
+}
+
+impl<S: Storage> Searchable<S> {
+    pub fn new(storage: S) -> Self {
+        let changes = storage.changes();
+        Self {
+            storage,
+            index: Mutex::new(None),
+            pending: Mutex::new(Pending {
+                everything: true,
+                files: HashSet::new(),
+            }),
+            changes: Mutex::new(changes),
+        }
+    }
+
+    /// Files whose name or content match `query`, best first, skipping the
+    /// ones `visible` rejects. Returns at most `limit` hits. See
+    /// [`parse_query`] for the syntax of queries.
+    pub fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        visible: impl Fn(&File) -> bool,
+    ) -> StorageResult<Vec<SearchHit>> {
+        let mut index = self.index.lock().expect("search index lock poisoned");
+        let index = match &mut *index {
+            Some(index) => index,
+            None => index.insert(SearchIndex::new()?),
+        };
+        let clauses = parse_query(&mut index.analyzer, query)?;
+        self.refresh(index)?;
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+        let query = index.query(&clauses);
+        let searcher = index.reader.searcher();
+        let mut hits = vec![];
+        let mut offset = 0;
+        loop {
+            let page = searcher.search(&query, &TopDocs::with_limit(limit).and_offset(offset))?;
+            for (score, address) in &page {
+                let document: TantivyDocument = searcher.doc(*address)?;
+                let text = |field| {
+                    document
+                        .get_first(field)
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                };
+                let Some(id) = document
+                    .get_first(index.fields.id)
+                    .and_then(|value| value.as_u64())
+                else {
+                    continue;
+                };
+                let file = match self.storage.get_file(id) {
+                    Ok(file) => file,
+                    // gone without the index knowing, ex: overwritten by a move
+                    Err(StorageError::NotFound(_)) => {
+                        self.mark(&[id]);
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                if !visible(&file) {
+                    continue;
+                }
+                let (snippet, highlights) =
+                    snippet(&mut index.analyzer, text(index.fields.body), &clauses);
+                hits.push(SearchHit {
+                    file: Some(file),
+                    score: *score,
+                    snippet,
+                    highlights,
+                });
+                if hits.len() == limit {
+                    return Ok(hits);
+                }
+            }
+            if page.len() < limit {
+                return Ok(hits);
+            }
+            offset += page.len();
+        }
+    }
+
+    fn pending(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().expect("search index lock poisoned")
+    }
+
+    /// Has the files `ids` reindexed before the next search
+    fn mark(&self, ids: &[u64]) {
+        self.pending().files.extend(ids);
+    }
+
+    /// Marks the files in `collection` for reindexing
+    fn mark_collection(&self, collection: &Collection) {
+        self.mark(&file_ids(collection));
+    }
+
+    /// Brings `index` up to date with the storage
+    fn refresh(&self, index: &mut SearchIndex) -> StorageResult<()> {
+        if let Some(changes) = &mut *self.changes.lock().expect("search index lock poisoned") {
+            loop {
+                let change = match changes.try_recv() {
+                    Ok(change) => change,
+                    Err(TryRecvError::Lagged(_)) => {
+                        self.pending().everything = true;
+                        continue;
+                    }
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                };
+                let event = change.event;
+                if let Some(file) = &event.file {
+                    self.mark(&[file.id]);
+                }
+                match event.kind() {
+                    Kind::CollectionDeleted => {
+                        if let Some(collection) = &event.collection {
+                            self.mark_collection(collection);
+                        }
+                    }
+                    // directories show up with their files in them
+                    Kind::CollectionCreated => self.pending().everything = true,
+                    _ => {}
+                }
+            }
+        }
+        let Pending { everything, files } = std::mem::replace(
+            &mut *self.pending(),
+            Pending {
+                everything: false,
+                files: HashSet::new(),
+            },
+        );
+        if everything {
+            index.clear()?;
+            for collection in self.storage.get_collection_all() {
+                for id in file_ids(&collection) {
+                    self.index_file(index, id)?;
+                }
+            }
+        } else if files.is_empty() {
+            return Ok(());
+        } else {
+            for id in files {
+                self.index_file(index, id)?;
+            }
+        }
+        index.commit()
+    }
+
+    /// Reindexes the file `id` as it is now
+    fn index_file(&self, index: &SearchIndex, id: u64) -> StorageResult<()> {
+        let Ok(file) = self.storage.get_file(id) else {
+            return index.put(id, None);
+        };
+        let body = match self.storage.get_file_content(id) {
+            Ok(content) => String::from_utf8(content.body).unwrap_or_default(),
+            Err(StorageError::NotFound(_)) => return index.put(id, None),
+            // still found by name
+            Err(_) => String::new(),
+        };
+        index.put(id, Some((&file, &body)))
+    }
+}
+
+/// Ids of the files in `collection` and all of its descendants
+fn file_ids(collection: &Collection) -> Vec<u64> {
+    let mut ids: Vec<_> = collection.files.iter().map(|file| file.id).collect();
+    for child in &collection.child_collections {
+        ids.extend(file_ids(child));
+    }
+    ids
+}
+
+impl<S: Storage> Storage for Searchable<S> {
+    fn get_collection_all(&self) -> Vec<Collection> {
+        self.storage.get_collection_all()
+    }
+
+    fn get_collection(&self, id: u64) -> StorageResult<Collection> {
+        self.storage.get_collection(id)
+    }
+
+    fn get_file(&self, id: u64) -> StorageResult<File> {
+        self.storage.get_file(id)
+    }
+
+    fn get_file_content(&self, id: u64) -> StorageResult<FileContent> {
+        self.storage.get_file_content(id)
+    }
+
+    fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>> {
+        self.storage.collection_lineage(id)
+    }
+
+    fn read_file_range(&self, id: u64, offset: u64, length: Option<u64>) -> StorageResult<Vec<u8>> {
+        self.storage.read_file_range(id, offset, length)
+    }
+
+    fn create_file(
+        &self,
+        collection_id: u64,
+        name: &str,
+        body: Vec<u8>,
+        author: &str,
+    ) -> StorageResult<File> {
+        let file = self
+            .storage
+            .create_file(collection_id, name, body, author)?;
+        self.mark(&[file.id]);
+        Ok(file)
+    }
+
+    fn update_file_content(
+        &self,
+        id: u64,
+        body: Vec<u8>,
+        author: &str,
+        if_match: Option<&str>,
+    ) -> StorageResult<File> {
+        let file = self
+            .storage
+            .update_file_content(id, body, author, if_match)?;
+        self.mark(&[id]);
+        Ok(file)
+    }
+
+    fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>> {
+        self.storage.list_revisions(id)
+    }
+
+    fn get_revision_content(&self, id: u64, revision_id: u64) -> StorageResult<FileContent> {
+        self.storage.get_revision_content(id, revision_id)
+    }
+
+    fn restore_revision(&self, id: u64, revision_id: u64, author: &str) -> StorageResult<File> {
+        let file = self.storage.restore_revision(id, revision_id, author)?;
+        self.mark(&[id]);
+        Ok(file)
+    }
+
+    fn delete_file(&self, id: u64, if_match: Option<&str>) -> StorageResult<File> {
+        let file = self.storage.delete_file(id, if_match)?;
+        self.mark(&[id]);
+        Ok(file)
+    }
+
+    fn move_file(
+        &self,
+        id: u64,
+        collection_id: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<(File, Collection)> {
+        let moved = self.storage.move_file(id, collection_id, name, overwrite)?;
+        self.mark(&[id]);
+        Ok(moved)
+    }
+
+    fn create_collection(&self, parent_id: u64, name: &str) -> StorageResult<Collection> {
+        self.storage.create_collection(parent_id, name)
+    }
+
+    fn rename_collection(&self, id: u64, name: &str) -> StorageResult<Collection> {
+        self.storage.rename_collection(id, name)
+    }
+
+    fn move_collection(&self, id: u64, parent_id: u64) -> StorageResult<Collection> {
+        self.storage.move_collection(id, parent_id)
+    }
+
+    fn delete_collection(&self, id: u64, recursive: bool) -> StorageResult<Collection> {
+        let collection = self.storage.delete_collection(id, recursive)?;
+        self.mark_collection(&collection);
+        Ok(collection)
+    }
+
+    fn list_trash(&self) -> StorageResult<Vec<TrashItem>> {
+        self.storage.list_trash()
+    }
+
+    fn restore_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item> {
+        let restored = self.storage.restore_trash(item, parent_id)?;
+        match &restored {
+            trash_item::Item::Collection(collection) => self.mark_collection(collection),
+            trash_item::Item::File(file) => self.mark(&[file.id]),
+        }
+        Ok(restored)
+    }
+
+    fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem> {
+        self.storage.purge_trash(item)
+    }
+
+    fn purge_trash_before(&self, cutoff: u64) -> StorageResult<Vec<TrashItem>> {
+        self.storage.purge_trash_before(cutoff)
+    }
+
+    fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+        self.storage.resolve_path(path)
+    }
+
+    fn get_path(&self, item: ItemId) -> StorageResult<String> {
+        self.storage.get_path(item)
+    }
+
+    fn get_user(&self, username: &str) -> StorageResult<User> {
+        self.storage.get_user(username)
+    }
+
+    fn create_user(&self, user: User) -> StorageResult<User> {
+        self.storage.create_user(user)
+    }
+
+    fn get_acls(&self) -> StorageResult<HashMap<u64, Vec<AclEntry>>> {
+        self.storage.get_acls()
+    }
+
+    fn set_acl(&self, id: u64, entries: Vec<AclEntry>) -> StorageResult<()> {
+        self.storage.set_acl(id, entries)
+    }
+
+    fn changes(&self) -> Option<broadcast::Receiver<Change>> {
+        self.storage.changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyzer, parse_query, snippet, Clause, Searchable};
+    use crate::collection::{HardCodedStorage, ItemId, Storage, StorageError};
+    use crate::oxygen::SearchHit;
+
+    /// Names of the files of `hits`, best first
+    fn names(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter()
+            .map(|hit| hit.file.as_ref().expect("hits have a file").name.as_str())
+            .collect()
+    }
+
+    fn search(storage: &Searchable<HardCodedStorage>, query: &str) -> Vec<String> {
+        let hits = storage
+            .search(query, 10, |_| true)
+            .unwrap_or_else(|err| panic!("failed to search for {:?}: {}", query, err));
+        names(&hits).into_iter().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn queries_are_parsed_into_clauses() {
+        let mut analyzer = analyzer();
+        assert_eq!(
+            parse_query(
+                &mut analyzer,
+                "Meeting \"Weekly   sync\" proj* 2026-10-01 día \"open"
+            ),
+            Ok(vec![
+                Clause::Term("meeting".to_string()),
+                Clause::Phrase(vec!["weekly".to_string(), "sync".to_string()]),
+                Clause::Prefix("proj".to_string()),
+                Clause::Phrase(vec!["2026".to_string(), "10".to_string(), "01".to_string()]),
+                Clause::Term("dia".to_string()),
+                Clause::Term("open".to_string()),
+            ])
+        );
+        assert!(matches!(
+            parse_query(&mut analyzer, "  \"\" * -"),
+            Err(StorageError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn snippets_highlight_every_match_around_the_first_one() {
+        let mut analyzer = analyzer();
+        let body = format!(
+            "{}the Weekly sync is moved, see the weekly plan and the projects",
+            "filler ".repeat(20)
+        );
+        let clauses = parse_query(&mut analyzer, "\"weekly sync\" proj*").unwrap();
+        let (fragment, highlights) = snippet(&mut analyzer, &body, &clauses);
+        assert!(fragment.starts_with("filler "));
+        assert!(fragment.ends_with("projects"));
+        let highlighted: Vec<_> = highlights
+            .iter()
+            .map(|highlight| &fragment[highlight.start as usize..highlight.end as usize])
+            .collect();
+        assert_eq!(highlighted, ["Weekly", "sync", "projects"]);
+
+        let (fragment, highlights) = snippet(&mut analyzer, "nothing here", &clauses);
+        assert_eq!(fragment, "");
+        assert!(highlights.is_empty());
+    }
+
+    #[test]
+    fn files_are_found_by_name_and_content() {
+        let storage = Searchable::new(HardCodedStorage::new());
+        // XXX: hardcoded storage, collection 4 is the root
+        let standup = storage
+            .create_file(
+                4,
+                "standup.md",
+                b"# Standup\nWe talked about the weekly sync and the projects.".to_vec(),
+                "",
+            )
+            .unwrap();
+        storage
+            .create_file(
+                4,
+                "notes.md",
+                b"Standup notes: the projector broke.".to_vec(),
+                "",
+            )
+            .unwrap();
+        storage
+            .create_file(4, "logo.png", vec![0xff, 0xfe, 0x00], "")
+            .unwrap();
+
+        assert_eq!(search(&storage, "standup"), ["standup.md", "notes.md"]);
+        assert_eq!(search(&storage, "\"weekly sync\""), ["standup.md"]);
+        assert_eq!(search(&storage, "\"sync weekly\""), Vec::<String>::new());
+        let mut prefixed = search(&storage, "proj*");
+        prefixed.sort();
+        assert_eq!(prefixed, ["notes.md", "standup.md"]);
+        assert_eq!(search(&storage, "STANDUP projector"), ["notes.md"]);
+        assert_eq!(search(&storage, "logo"), ["logo.png"]);
+        assert_eq!(search(&storage, "missing"), Vec::<String>::new());
+
+        let hits = storage.search("standup", 1, |_| true).unwrap();
+        assert_eq!(names(&hits), ["standup.md"]);
+        assert_eq!(
+            hits[0].snippet,
+            "# Standup\nWe talked about the weekly sync and the projects."
+        );
+        let hits = storage
+            .search("standup", 10, |file| file.id != standup.id)
+            .unwrap();
+        assert_eq!(names(&hits), ["notes.md"]);
+        assert!(hits[0].score > 0.0);
+        assert_eq!(storage.search("standup", 0, |_| true), Ok(vec![]));
+    }
+
+    #[test]
+    fn index_follows_the_writes() {
+        let storage = Searchable::new(HardCodedStorage::new());
+        let plan = storage
+            .create_file(4, "plan.md", b"# alpha".to_vec(), "")
+            .unwrap();
+        assert_eq!(search(&storage, "alpha"), ["plan.md"]);
+
+        storage
+            .update_file_content(plan.id, b"# beta".to_vec(), "", None)
+            .unwrap();
+        assert_eq!(search(&storage, "alpha"), Vec::<String>::new());
+        assert_eq!(search(&storage, "beta"), ["plan.md"]);
+        let first = storage.list_revisions(plan.id).unwrap()[0].id;
+        storage.restore_revision(plan.id, first, "").unwrap();
+        assert_eq!(search(&storage, "alpha"), ["plan.md"]);
+
+        storage.move_file(plan.id, 3, "roadmap.md", false).unwrap();
+        assert_eq!(search(&storage, "roadmap"), ["roadmap.md"]);
+        assert_eq!(search(&storage, "plan"), Vec::<String>::new());
+
+        let other = storage
+            .create_file(3, "other.md", b"# gamma".to_vec(), "")
+            .unwrap();
+        assert_eq!(search(&storage, "gamma"), ["other.md"]);
+        storage.move_file(plan.id, 3, "other.md", true).unwrap();
+        assert_eq!(search(&storage, "gamma"), Vec::<String>::new());
+        assert!(storage.get_file(other.id).is_err());
+        // the replaced file went to the trash
+        storage
+            .restore_trash(ItemId::File(other.id), Some(4))
+            .unwrap();
+        assert_eq!(search(&storage, "gamma"), ["other.md"]);
+        storage.delete_file(other.id, None).unwrap();
+
+        storage.delete_file(plan.id, None).unwrap();
+        assert_eq!(search(&storage, "alpha"), Vec::<String>::new());
+        storage.restore_trash(ItemId::File(plan.id), None).unwrap();
+        assert_eq!(search(&storage, "alpha"), ["other.md"]);
+
+        // XXX: hardcoded storage, collection 3 holds the moved file
+        storage.delete_collection(3, true).unwrap();
+        assert_eq!(search(&storage, "alpha"), Vec::<String>::new());
+        storage.restore_trash(ItemId::Collection(3), None).unwrap();
+        assert_eq!(search(&storage, "alpha"), ["other.md"]);
+    }
+}
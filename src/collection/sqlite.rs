@@ -0,0 +1,1234 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::SystemTime,
+};
+
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Transaction};
+
+use super::{
+    trash::{Trash, TrashEntry},
+    tree::{FileMeta, Tree},
+    unix_millis, validate_name, validate_username, ItemId, Storage, StorageError, StorageResult,
+    User,
+};
+use crate::oxygen::{
+    acl_entry::Principal, trash_item, AclEntry, Collection, File, FileContent, Revision, TrashItem,
+};
+
+/// Name of the root collection of new in memory databases
+const DEFAULT_ROOT_NAME: &str = "notes";
+/// Name of the root collection keeping the items of the trash, which no other
+/// collection can have
+const TRASH_COLLECTION: &str = "/trash";
+/// Schema changes, in the order they are applied. The schema version of a
+/// database is the number of migrations applied to it, kept as its
+/// `user_version`.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE collections (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        parent_id INTEGER REFERENCES collections (id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        UNIQUE (parent_id, name)
+    );
+    -- content of the files, shared by the files with the same content
+    CREATE TABLE blobs (
+        sha256 TEXT PRIMARY KEY,
+        body BLOB NOT NULL
+    );
+    CREATE TABLE files (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        collection_id INTEGER NOT NULL REFERENCES collections (id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        sha256 TEXT NOT NULL REFERENCES blobs (sha256),
+        size INTEGER NOT NULL,
+        created INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        UNIQUE (collection_id, name)
+    );
+    CREATE INDEX files_by_content ON files (sha256);
+    CREATE TABLE users (
+        username TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL
+    );
+",
+    "
+    -- access control lists of the collections, in the order they were set
+    CREATE TABLE acl_entries (
+        collection_id INTEGER NOT NULL REFERENCES collections (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        kind TEXT NOT NULL CHECK (kind IN ('client', 'user')),
+        principal TEXT NOT NULL,
+        permission INTEGER NOT NULL,
+        PRIMARY KEY (collection_id, position)
+    );
+",
+    "
+    CREATE TABLE revisions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        file_id INTEGER NOT NULL REFERENCES files (id) ON DELETE CASCADE,
+        sha256 TEXT NOT NULL REFERENCES blobs (sha256),
+        size INTEGER NOT NULL,
+        created INTEGER NOT NULL,
+        author TEXT NOT NULL
+    );
+    CREATE INDEX revisions_by_file ON revisions (file_id);
+    CREATE INDEX revisions_by_content ON revisions (sha256);
+    -- the history of existing files starts with their current content
+    INSERT INTO revisions (file_id, sha256, size, created, author)
+        SELECT id, sha256, size, modified, '' FROM files ORDER BY id;
+",
+    "
+    -- deleted collections and files, which are moved to the trash collection
+    -- and named after their id until they are restored or purged
+    CREATE TABLE trash (
+        collection_id INTEGER UNIQUE REFERENCES collections (id) ON DELETE CASCADE,
+        file_id INTEGER UNIQUE REFERENCES files (id) ON DELETE CASCADE,
+        -- name of the item when it was deleted
+        name TEXT NOT NULL,
+        -- ids of the collection the item was in and of its ancestors, from the
+        -- root collection down, separated by spaces
+        lineage TEXT NOT NULL,
+        path TEXT NOT NULL,
+        deleted INTEGER NOT NULL,
+        CHECK ((collection_id IS NULL) <> (file_id IS NULL))
+    );
+",
+];
+
+/// Storage keeping collections, files, users and access control lists in a
+/// single SQLite database.
+///
+/// Every write happens in a transaction, so changes touching several files
+/// (ex: deleting a collection) are either fully applied or not at all. Backing
+/// up the storage is a matter of copying the database. Databases made by older
+/// versions are migrated when they are opened.
+pub struct SqliteStorage {
+    // only locked for writing while holding `writes`, and only for as long as
+    // it takes to update the tree in memory once the database is, so that
+    // readers are never held up by the database
+    tree: RwLock<Tree>,
+    // held by writes from their checks until the tree matches the database.
+    // Nothing else changes the tree meanwhile, so the holder can keep reading it
+    // without the write lock.
+    writes: Mutex<()>,
+    // only locked while holding the tree lock
+    trash: Mutex<Trash>,
+    // never locked while holding the tree lock for writing
+    connection: Mutex<Connection>,
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err.sqlite_error_code() {
+            Some(ErrorCode::ConstraintViolation) => StorageError::Conflict(err.to_string()),
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => {
+                StorageError::Corrupt(err.to_string())
+            }
+            Some(ErrorCode::PermissionDenied | ErrorCode::ReadOnly) => {
+                StorageError::PermissionDenied(err.to_string())
+            }
+            _ if matches!(err, rusqlite::Error::QueryReturnedNoRows) => {
+                StorageError::NotFound(err.to_string())
+            }
+            _ => StorageError::Io(err.to_string()),
+        }
+    }
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it if needed. New databases get
+    /// a root collection named after the file.
+    pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let path = path.as_ref();
+        let root_name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or(DEFAULT_ROOT_NAME);
+        Self::with_connection(Connection::open(path)?, root_name)
+    }
+
+    /// Database that only lives as long as the storage
+    #[cfg(test)]
+    pub fn open_in_memory() -> StorageResult<Self> {
+        Self::with_connection(Connection::open_in_memory()?, DEFAULT_ROOT_NAME)
+    }
+
+    fn with_connection(mut connection: Connection, root_name: &str) -> StorageResult<Self> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+        let has_root: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM collections WHERE parent_id IS NULL AND name <> ?1)",
+            [TRASH_COLLECTION],
+            |row| row.get(0),
+        )?;
+        if !has_root {
+            connection.execute(
+                "INSERT INTO collections (parent_id, name) VALUES (NULL, ?1)",
+                [root_name],
+            )?;
+        }
+        let mut tree = load_tree(&connection)?;
+        let trash = load_trash(&connection, &mut tree)?;
+        Ok(Self {
+            tree: RwLock::new(tree),
+            writes: Mutex::new(()),
+            trash: Mutex::new(trash),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Tree> {
+        self.tree.read().expect("storage lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Tree> {
+        self.tree.write().expect("storage lock poisoned")
+    }
+
+    fn writing(&self) -> MutexGuard<'_, ()> {
+        self.writes.lock().expect("storage lock poisoned")
+    }
+
+    fn trash(&self) -> MutexGuard<'_, Trash> {
+        self.trash.lock().expect("storage lock poisoned")
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().expect("storage lock poisoned")
+    }
+
+    /// Runs `write` in a transaction that is only committed if it succeeds.
+    /// Must be called while holding `writes`, the tree is only updated once the
+    /// transaction is committed.
+    fn transaction<T>(
+        &self,
+        write: impl FnOnce(&Transaction) -> rusqlite::Result<T>,
+    ) -> StorageResult<T> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let result = write(&transaction)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+}
+
+/// Brings the schema of the database up to date
+fn migrate(connection: &mut Connection) -> StorageResult<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError::Corrupt(format!(
+            "database schema version {} is newer than this server's ({})",
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", applied + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// Reads the collections and files of the database into a tree
+fn load_tree(connection: &Connection) -> StorageResult<Tree> {
+    let mut children: HashMap<Option<u64>, Vec<(u64, String)>> = HashMap::new();
+    let mut statement =
+        connection.prepare("SELECT id, parent_id, name FROM collections ORDER BY id")?;
+    let mut rows = statement.query([])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        children
+            .entry(row.get(1)?)
+            .or_default()
+            .push((row.get(0)?, row.get(2)?));
+        count += 1;
+    }
+
+    let mut tree = Tree::new();
+    // moved collections can have a smaller id than their parent, so the tree
+    // is walked down from the roots for parents to be inserted first
+    let mut pending: Vec<_> = children
+        .remove(&None)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .map(|(id, name)| (None, id, name))
+        .collect();
+    while let Some((parent, id, name)) = pending.pop() {
+        tree.insert_collection_with_id(id, parent, name);
+        count -= 1;
+        let nested = children.remove(&Some(id)).unwrap_or_default();
+        pending.extend(
+            nested
+                .into_iter()
+                .rev()
+                .map(|(child, name)| (Some(id), child, name)),
+        );
+    }
+    if count != 0 {
+        return Err(StorageError::Corrupt(format!(
+            "{} collections are not attached to a root collection",
+            count
+        )));
+    }
+
+    let mut statement = connection.prepare(
+        "SELECT id, collection_id, name, size, created, modified, sha256 FROM files ORDER BY id",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let id = row.get(0)?;
+        let collection_id = row.get(1)?;
+        if tree.collection(collection_id).is_none() {
+            return Err(StorageError::Corrupt(format!(
+                "file with id: {} is in a missing collection",
+                id
+            )));
+        }
+        tree.insert_file_with_id(id, collection_id, row.get(2)?);
+        tree.set_file_meta(
+            id,
+            FileMeta {
+                size: row.get(3)?,
+                created: row.get(4)?,
+                modified: row.get(5)?,
+                sha256: row.get(6)?,
+            },
+        );
+    }
+    Ok(tree)
+}
+
+/// Moves the items of the trash collection from `tree` to the trash, where they
+/// get their names back
+fn load_trash(connection: &Connection, tree: &mut Tree) -> StorageResult<Trash> {
+    let mut trash = Trash::new();
+    let Some(holder) = trash_collection(connection)? else {
+        return Ok(trash);
+    };
+    let mut statement = connection
+        .prepare("SELECT collection_id, file_id, name, lineage, path, deleted FROM trash")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(2)?;
+        let lineage: String = row.get(3)?;
+        let lineage = lineage
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| StorageError::Corrupt(format!("malformed lineage {:?}", lineage)))?;
+        let item = match (row.get(0)?, row.get(1)?) {
+            (Some(id), _) => {
+                tree.rename_collection(id, name);
+                ItemId::Collection(id)
+            }
+            (None, Some(id)) => {
+                tree.move_file(id, holder, name);
+                ItemId::File(id)
+            }
+            (None, None) => unreachable!("trash rows hold a collection or a file"),
+        };
+        let entry = TrashEntry {
+            lineage,
+            path: row.get(4)?,
+            deleted: row.get(5)?,
+        };
+        trash.insert(item, entry, tree);
+    }
+    tree.remove_collection(holder);
+    Ok(trash)
+}
+
+/// Id of the collection keeping the items of the trash, if there is one yet
+fn trash_collection(connection: &Connection) -> rusqlite::Result<Option<u64>> {
+    connection
+        .query_row(
+            "SELECT id FROM collections WHERE parent_id IS NULL AND name = ?1",
+            [TRASH_COLLECTION],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+/// Moves `item`, named `name`, to the trash collection and records `entry`
+fn insert_trash(
+    transaction: &Transaction,
+    item: ItemId,
+    name: &str,
+    entry: &TrashEntry,
+) -> rusqlite::Result<()> {
+    let holder = match trash_collection(transaction)? {
+        Some(holder) => holder,
+        None => {
+            transaction.execute(
+                "INSERT INTO collections (parent_id, name) VALUES (NULL, ?1)",
+                [TRASH_COLLECTION],
+            )?;
+            transaction.last_insert_rowid() as u64
+        }
+    };
+    let lineage = entry
+        .lineage
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (collection_id, file_id) = match item {
+        ItemId::Collection(id) => {
+            transaction.execute(
+                "UPDATE collections SET parent_id = ?2, name = ?3 WHERE id = ?1",
+                params![id, holder, id.to_string()],
+            )?;
+            (Some(id), None)
+        }
+        ItemId::File(id) => {
+            transaction.execute(
+                "UPDATE files SET collection_id = ?2, name = ?3 WHERE id = ?1",
+                params![id, holder, id.to_string()],
+            )?;
+            (None, Some(id))
+        }
+    };
+    transaction.execute(
+        "INSERT INTO trash (collection_id, file_id, name, lineage, path, deleted)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            collection_id,
+            file_id,
+            name,
+            lineage,
+            entry.path,
+            entry.deleted
+        ],
+    )?;
+    Ok(())
+}
+
+/// Moves `item` from the trash collection to `parent` under the name `name`
+fn remove_trash(
+    transaction: &Transaction,
+    item: ItemId,
+    parent: Option<u64>,
+    name: &str,
+) -> rusqlite::Result<()> {
+    match item {
+        ItemId::Collection(id) => {
+            transaction.execute(
+                "UPDATE collections SET parent_id = ?2, name = ?3 WHERE id = ?1",
+                params![id, parent, name],
+            )?;
+            transaction.execute("DELETE FROM trash WHERE collection_id = ?1", [id])?;
+        }
+        ItemId::File(id) => {
+            transaction.execute(
+                "UPDATE files SET collection_id = ?2, name = ?3 WHERE id = ?1",
+                params![id, parent, name],
+            )?;
+            transaction.execute("DELETE FROM trash WHERE file_id = ?1", [id])?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores `body` unless a file already has the same content
+fn insert_blob(transaction: &Transaction, sha256: &str, body: &[u8]) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT OR IGNORE INTO blobs (sha256, body) VALUES (?1, ?2)",
+        params![sha256, body],
+    )?;
+    Ok(())
+}
+
+/// Records the content described by `meta` as a new revision of the file `id`,
+/// the content itself must already be stored
+fn insert_revision(
+    transaction: &Transaction,
+    id: u64,
+    meta: &FileMeta,
+    author: &str,
+) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT INTO revisions (file_id, sha256, size, created, author)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![id, meta.sha256, meta.size, meta.modified, author],
+    )?;
+    Ok(())
+}
+
+/// Contents of every revision of the given files
+fn revision_hashes(
+    transaction: &Transaction,
+    files: impl IntoIterator<Item = u64>,
+) -> rusqlite::Result<Vec<String>> {
+    let mut statement =
+        transaction.prepare("SELECT DISTINCT sha256 FROM revisions WHERE file_id = ?1")?;
+    let mut hashes = vec![];
+    for id in files {
+        for sha256 in statement.query_map([id], |row| row.get(0))? {
+            hashes.push(sha256?);
+        }
+    }
+    Ok(hashes)
+}
+
+/// Drops the given contents if no file or revision has them any more
+fn drop_unused_blobs(transaction: &Transaction, hashes: &[String]) -> rusqlite::Result<()> {
+    let mut statement = transaction.prepare(
+        "DELETE FROM blobs
+         WHERE sha256 = ?1
+           AND NOT EXISTS (SELECT 1 FROM files WHERE sha256 = ?1)
+           AND NOT EXISTS (SELECT 1 FROM revisions WHERE sha256 = ?1)",
+    )?;
+    for sha256 in hashes {
+        statement.execute([sha256])?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn get_collection_all(&self) -> Vec<Collection> {
+        self.read().to_collection_all()
+    }
+
+    fn get_collection(&self, id: u64) -> StorageResult<Collection> {
+        let tree = self.read();
+        tree.require_collection(id)?;
+        Ok(tree.to_collection(id).expect("collection exists"))
+    }
+
+    fn get_file(&self, id: u64) -> StorageResult<File> {
+        let tree = self.read();
+        tree.require_file(id)?;
+        Ok(tree.to_file(id).expect("file exists"))
+    }
+
+    fn get_file_content(&self, id: u64) -> StorageResult<FileContent> {
+        let sha256 = self.read().require_file(id)?.meta.sha256.clone();
+        let body = self.connection().query_row(
+            "SELECT body FROM blobs WHERE sha256 = ?1",
+            [sha256],
+            |row| row.get(0),
+        )?;
+        Ok(FileContent { body })
+    }
+
+    fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>> {
+        let tree = self.read();
+        tree.require_collection(id)?;
+        Ok(tree.collection_lineage(id))
+    }
+
+    fn read_file_range(&self, id: u64, offset: u64, length: Option<u64>) -> StorageResult<Vec<u8>> {
+        let sha256 = self.read().require_file(id)?.meta.sha256.clone();
+        // substr counts from 1, and takes everything left for a negative length
+        let start = i64::try_from(offset).unwrap_or(i64::MAX).saturating_add(1);
+        let length = length.map_or(-1, |length| i64::try_from(length).unwrap_or(i64::MAX));
+        let body = self.connection().query_row(
+            "SELECT CASE WHEN ?3 < 0 THEN substr(body, ?2) ELSE substr(body, ?2, ?3) END
+             FROM blobs WHERE sha256 = ?1",
+            params![sha256, start, length],
+            |row| row.get::<_, Option<Vec<u8>>>(0),
+        )?;
+        Ok(body.unwrap_or_default())
+    }
+
+    fn create_file(
+        &self,
+        collection_id: u64,
+        name: &str,
+        body: Vec<u8>,
+        author: &str,
+    ) -> StorageResult<File> {
+        validate_name(name)?;
+        let _writing = self.writing();
+        self.read().check_name_free(collection_id, name)?;
+        let now = unix_millis(SystemTime::now());
+        let meta = FileMeta::of(&body, now, now);
+        let id = self.transaction(|transaction| {
+            insert_blob(transaction, &meta.sha256, &body)?;
+            transaction.execute(
+                "INSERT INTO files (collection_id, name, sha256, size, created, modified)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    collection_id,
+                    name,
+                    meta.sha256,
+                    meta.size,
+                    meta.created,
+                    meta.modified
+                ],
+            )?;
+            let id = transaction.last_insert_rowid() as u64;
+            insert_revision(transaction, id, &meta, author)?;
+            Ok(id)
+        })?;
+        let mut tree = self.write();
+        tree.insert_file_with_id(id, collection_id, name.to_string());
+        tree.set_file_meta(id, meta);
+        Ok(tree.to_file(id).expect("file was just inserted"))
+    }
+
+    fn update_file_content(
+        &self,
+        id: u64,
+        body: Vec<u8>,
+        author: &str,
+        if_match: Option<&str>,
+    ) -> StorageResult<File> {
+        let _writing = self.writing();
+        let created = {
+            let tree = self.read();
+            tree.check_etag(id, if_match)?;
+            tree.require_file(id)?.meta.created
+        };
+        let meta = FileMeta::of(&body, created, unix_millis(SystemTime::now()));
+        // the previous content stays, the previous revision still has it
+        self.transaction(|transaction| {
+            insert_blob(transaction, &meta.sha256, &body)?;
+            transaction.execute(
+                "UPDATE files SET sha256 = ?2, size = ?3, modified = ?4 WHERE id = ?1",
+                params![id, meta.sha256, meta.size, meta.modified],
+            )?;
+            insert_revision(transaction, id, &meta, author)
+        })?;
+        let mut tree = self.write();
+        tree.set_file_meta(id, meta);
+        Ok(tree.to_file(id).expect("file exists"))
+    }
+
+    fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>> {
+        self.read().require_file(id)?;
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, created, author, sha256, size FROM revisions
+             WHERE file_id = ?1 ORDER BY id",
+        )?;
+        let revisions = statement
+            .query_map([id], |row| {
+                Ok(Revision {
+                    id: row.get(0)?,
+                    file_id: id,
+                    created: row.get(1)?,
+                    author: row.get(2)?,
+                    sha256: row.get(3)?,
+                    size: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(revisions)
+    }
+
+    fn get_revision_content(&self, id: u64, revision_id: u64) -> StorageResult<FileContent> {
+        self.read().require_file(id)?;
+        let body = self
+            .connection()
+            .query_row(
+                "SELECT body FROM revisions JOIN blobs USING (sha256)
+                 WHERE revisions.id = ?2 AND file_id = ?1",
+                [id, revision_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| {
+                StorageError::NotFound(format!("revision {} of file with id: {}", revision_id, id))
+            })?;
+        Ok(FileContent { body })
+    }
+
+    fn delete_file(&self, id: u64, if_match: Option<&str>) -> StorageResult<File> {
+        let _writing = self.writing();
+        let (file, entry) = {
+            let tree = self.read();
+            tree.check_etag(id, if_match)?;
+            let file = tree.to_file(id).expect("file exists");
+            let now = unix_millis(SystemTime::now());
+            (file, TrashEntry::of(&tree, ItemId::File(id), now)?)
+        };
+        self.transaction(|transaction| {
+            insert_trash(transaction, ItemId::File(id), &file.name, &entry)
+        })?;
+        let mut tree = self.write();
+        self.trash().insert(ItemId::File(id), entry, &mut tree);
+        Ok(file)
+    }
+
+    fn move_file(
+        &self,
+        id: u64,
+        collection_id: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<(File, Collection)> {
+        validate_name(name)?;
+        let _writing = self.writing();
+        let replaced = {
+            let tree = self.read();
+            match tree.check_file_move(id, collection_id, name, overwrite)? {
+                Some(replaced) => {
+                    let now = unix_millis(SystemTime::now());
+                    Some((
+                        replaced,
+                        TrashEntry::of(&tree, ItemId::File(replaced), now)?,
+                    ))
+                }
+                None => None,
+            }
+        };
+        self.transaction(|transaction| {
+            if let Some((replaced, entry)) = &replaced {
+                insert_trash(transaction, ItemId::File(*replaced), name, entry)?;
+            }
+            transaction.execute(
+                "UPDATE files SET collection_id = ?2, name = ?3 WHERE id = ?1",
+                params![id, collection_id, name],
+            )
+        })?;
+        let mut tree = self.write();
+        if let Some((replaced, entry)) = replaced {
+            self.trash()
+                .insert(ItemId::File(replaced), entry, &mut tree);
+        }
+        tree.move_file(id, collection_id, name.to_string());
+        Ok((
+            tree.to_file(id).expect("file exists"),
+            tree.to_collection(collection_id)
+                .expect("collection exists"),
+        ))
+    }
+
+    fn create_collection(&self, parent_id: u64, name: &str) -> StorageResult<Collection> {
+        validate_name(name)?;
+        let _writing = self.writing();
+        self.read().check_name_free(parent_id, name)?;
+        let id = self.transaction(|transaction| {
+            transaction.execute(
+                "INSERT INTO collections (parent_id, name) VALUES (?1, ?2)",
+                params![parent_id, name],
+            )?;
+            Ok(transaction.last_insert_rowid() as u64)
+        })?;
+        let mut tree = self.write();
+        tree.insert_collection_with_id(id, Some(parent_id), name.to_string());
+        Ok(tree
+            .to_collection(id)
+            .expect("collection was just inserted"))
+    }
+
+    fn rename_collection(&self, id: u64, name: &str) -> StorageResult<Collection> {
+        validate_name(name)?;
+        let _writing = self.writing();
+        {
+            let tree = self.read();
+            if let Some(parent) = tree.require_collection(id)?.parent {
+                tree.check_name_free(parent, name)?;
+            }
+        }
+        self.transaction(|transaction| {
+            transaction.execute(
+                "UPDATE collections SET name = ?2 WHERE id = ?1",
+                params![id, name],
+            )
+        })?;
+        let mut tree = self.write();
+        tree.rename_collection(id, name.to_string());
+        Ok(tree.to_collection(id).expect("collection exists"))
+    }
+
+    fn move_collection(&self, id: u64, parent_id: u64) -> StorageResult<Collection> {
+        let _writing = self.writing();
+        let moved = {
+            let tree = self.read();
+            tree.check_move(id, parent_id)?;
+            let node = tree.require_collection(id)?;
+            let moved = node.parent != Some(parent_id);
+            if moved {
+                tree.check_name_free(parent_id, &node.name)?;
+            }
+            moved
+        };
+        if moved {
+            self.transaction(|transaction| {
+                transaction.execute(
+                    "UPDATE collections SET parent_id = ?2 WHERE id = ?1",
+                    params![id, parent_id],
+                )
+            })?;
+            self.write().move_collection(id, parent_id);
+        }
+        Ok(self.read().to_collection(id).expect("collection exists"))
+    }
+
+    fn delete_collection(&self, id: u64, recursive: bool) -> StorageResult<Collection> {
+        let _writing = self.writing();
+        let item = ItemId::Collection(id);
+        let (collection, entry) = {
+            let tree = self.read();
+            let node = tree.require_collection(id)?;
+            if !recursive && !node.is_empty() {
+                return Err(StorageError::Conflict(format!(
+                    "collection with id: {} is not empty",
+                    id
+                )));
+            }
+            let collection = tree.to_collection(id).expect("collection exists");
+            let now = unix_millis(SystemTime::now());
+            (collection, TrashEntry::of(&tree, item, now)?)
+        };
+        self.transaction(|transaction| insert_trash(transaction, item, &collection.name, &entry))?;
+        let mut tree = self.write();
+        self.trash().insert(item, entry, &mut tree);
+        Ok(collection)
+    }
+
+    fn list_trash(&self) -> StorageResult<Vec<TrashItem>> {
+        let _tree = self.read();
+        Ok(self.trash().list())
+    }
+
+    fn restore_trash(
+        &self,
+        item: ItemId,
+        parent_id: Option<u64>,
+    ) -> StorageResult<trash_item::Item> {
+        let _writing = self.writing();
+        let (parent, name) = {
+            let tree = self.read();
+            let trash = self.trash();
+            let parent = trash.check_restore(&tree, item, parent_id)?;
+            (parent, trash.name(item)?.to_string())
+        };
+        self.transaction(|transaction| remove_trash(transaction, item, parent, &name))?;
+        let mut tree = self.write();
+        self.trash().restore(&mut tree, item, parent);
+        Ok(tree.to_item(item).expect("item was just restored"))
+    }
+
+    fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem> {
+        let _writing = self.writing();
+        let purged = {
+            let _tree = self.read();
+            self.trash().to_trash_item(item)?
+        };
+        let mut files = vec![];
+        let mut pending = vec![];
+        match &purged.item {
+            Some(trash_item::Item::Collection(collection)) => pending.push(collection),
+            Some(trash_item::Item::File(file)) => files.push(file.id),
+            None => {}
+        }
+        while let Some(current) = pending.pop() {
+            files.extend(current.files.iter().map(|file| file.id));
+            pending.extend(&current.child_collections);
+        }
+        self.transaction(|transaction| {
+            let hashes = revision_hashes(transaction, files)?;
+            // takes the descendants, their files, the revisions of the files and
+            // the trash entry along
+            match item {
+                ItemId::Collection(id) => {
+                    transaction.execute("DELETE FROM collections WHERE id = ?1", [id])?
+                }
+                ItemId::File(id) => transaction.execute("DELETE FROM files WHERE id = ?1", [id])?,
+            };
+            drop_unused_blobs(transaction, &hashes)
+        })?;
+        let _tree = self.write();
+        self.trash().purge(item)?;
+        Ok(purged)
+    }
+
+    fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+        self.read().resolve_path(path)
+    }
+
+    fn get_path(&self, item: ItemId) -> StorageResult<String> {
+        self.read().path_of(item)
+    }
+
+    fn get_user(&self, username: &str) -> StorageResult<User> {
+        self.connection()
+            .query_row(
+                "SELECT password_hash FROM users WHERE username = ?1",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?
+            .map(|password_hash| User {
+                username: username.to_string(),
+                password_hash,
+            })
+            .ok_or_else(|| StorageError::NotFound(format!("user: {}", username)))
+    }
+
+    fn create_user(&self, user: User) -> StorageResult<User> {
+        validate_username(&user.username)?;
+        let inserted = self.connection().execute(
+            "INSERT INTO users (username, password_hash) VALUES (?1, ?2)",
+            params![user.username, user.password_hash],
+        );
+        match inserted.map_err(StorageError::from) {
+            Ok(_) => Ok(user),
+            Err(StorageError::Conflict(_)) => Err(StorageError::Conflict(format!(
+                "user {} already exists",
+                user.username
+            ))),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn get_acls(&self) -> StorageResult<HashMap<u64, Vec<AclEntry>>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT collection_id, kind, principal, permission FROM acl_entries
+                ORDER BY collection_id, position",
+        )?;
+        let mut rows = statement.query([])?;
+        let mut acls: HashMap<u64, Vec<AclEntry>> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let kind: String = row.get(1)?;
+            let principal: String = row.get(2)?;
+            let principal = match kind.as_str() {
+                "client" => Principal::ClientUuid(principal),
+                _ => Principal::User(principal),
+            };
+            acls.entry(row.get(0)?).or_default().push(AclEntry {
+                principal: Some(principal),
+                permission: row.get(3)?,
+            });
+        }
+        Ok(acls)
+    }
+
+    fn set_acl(&self, id: u64, entries: Vec<AclEntry>) -> StorageResult<()> {
+        let _writing = self.writing();
+        self.read().require_collection(id)?;
+        let mut rows = vec![];
+        for entry in &entries {
+            let (kind, principal) = match &entry.principal {
+                Some(Principal::ClientUuid(uuid)) => ("client", uuid),
+                Some(Principal::User(user)) => ("user", user),
+                None => {
+                    return Err(StorageError::InvalidArgument(
+                        "ACL entries must name who they grant access to".to_string(),
+                    ))
+                }
+            };
+            rows.push((kind, principal, entry.permission));
+        }
+        self.transaction(|transaction| {
+            transaction.execute("DELETE FROM acl_entries WHERE collection_id = ?1", [id])?;
+            for (position, (kind, principal, permission)) in rows.into_iter().enumerate() {
+                transaction.execute(
+                    "INSERT INTO acl_entries (collection_id, position, kind, principal, permission)
+                        VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![id, position, kind, principal, permission],
+                )?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+
+    use rusqlite::Connection;
+
+    use super::{SqliteStorage, MIGRATIONS};
+    use crate::collection::{conformance, ItemId, Storage, StorageError, User};
+    use crate::oxygen::{acl_entry::Principal, AclEntry, Permission};
+
+    fn count(storage: &SqliteStorage, table: &str) -> u64 {
+        storage
+            .connection()
+            .query_row(&format!("SELECT count(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn sqlite_storage_conforms() {
+        conformance::check(&SqliteStorage::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn new_databases_have_a_root_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SqliteStorage::open(dir.path().join("notes.db")).unwrap();
+        let collections = storage.get_collection_all();
+        assert_eq!(collections.len(), 1);
+        assert_eq!(collections[0].name, "notes");
+        assert_eq!(
+            storage.resolve_path(""),
+            Ok(ItemId::Collection(collections[0].id))
+        );
+    }
+
+    #[test]
+    fn everything_is_kept_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.db");
+        let storage = SqliteStorage::open(&path).unwrap();
+        let root = storage.get_collection_all()[0].id;
+        let work = storage.create_collection(root, "work").unwrap();
+        let meetings = storage.create_collection(root, "meetings").unwrap();
+        let standup = storage
+            .create_file(meetings.id, "standup.md", b"# standup".to_vec(), "")
+            .unwrap();
+        // the moved collection now has a smaller id than its parent
+        storage.move_collection(work.id, meetings.id).unwrap();
+        let alice = User {
+            username: "alice".to_string(),
+            password_hash: "pbkdf2-sha256$1$00$00".to_string(),
+        };
+        storage.create_user(alice.clone()).unwrap();
+        let acl = vec![AclEntry {
+            principal: Some(Principal::User("alice".to_string())),
+            permission: Permission::Admin as i32,
+        }];
+        storage.set_acl(work.id, acl.clone()).unwrap();
+        let old = storage
+            .create_file(work.id, "old.md", b"# old".to_vec(), "")
+            .unwrap();
+        storage.delete_file(old.id, None).unwrap();
+        let collections = storage.get_collection_all();
+        let trash = storage.list_trash().unwrap();
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.get_collection_all(), collections);
+        assert_eq!(
+            storage.get_file_content(standup.id).unwrap().body,
+            b"# standup"
+        );
+        assert_eq!(storage.get_user("alice"), Ok(alice));
+        assert_eq!(storage.get_acls(), Ok(HashMap::from([(work.id, acl)])));
+        assert_eq!(storage.list_trash(), Ok(trash));
+        storage
+            .restore_trash(ItemId::File(old.id), None)
+            .expect("failed to restore file");
+        assert_eq!(storage.get_file_content(old.id).unwrap().body, b"# old");
+    }
+
+    #[test]
+    fn files_can_be_created_updated_moved_and_deleted() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let root = storage.get_collection_all()[0].id;
+        let work = storage.create_collection(root, "work").unwrap();
+        let file = storage
+            .create_file(root, "todo.md", b"# todo".to_vec(), "")
+            .unwrap();
+        assert_eq!(file.size, 6);
+        assert!(matches!(
+            storage.create_file(root, "todo.md", vec![], ""),
+            Err(StorageError::Conflict(_))
+        ));
+        assert!(matches!(
+            storage.create_file(42, "todo.md", vec![], ""),
+            Err(StorageError::NotFound(_))
+        ));
+
+        let updated = storage
+            .update_file_content(file.id, b"# done".to_vec(), "", None)
+            .unwrap();
+        assert_eq!(updated.created, file.created);
+        assert_ne!(updated.sha256, file.sha256);
+        assert_eq!(storage.read_file_range(file.id, 2, Some(2)).unwrap(), b"do");
+        assert_eq!(storage.read_file_range(file.id, 2, None).unwrap(), b"done");
+        assert!(storage
+            .read_file_range(file.id, 42, None)
+            .unwrap()
+            .is_empty());
+
+        let (moved, collection) = storage
+            .move_file(file.id, work.id, "done.md", false)
+            .unwrap();
+        assert_eq!(moved.id, file.id);
+        assert_eq!(collection.files, vec![moved.clone()]);
+        assert_eq!(
+            storage.get_path(ItemId::File(file.id)).unwrap(),
+            "work/done.md"
+        );
+
+        assert_eq!(storage.delete_file(file.id, None), Ok(moved));
+        assert!(matches!(
+            storage.get_file_content(file.id),
+            Err(StorageError::NotFound(_))
+        ));
+        // the trash keeps both revisions until the file is purged
+        assert_eq!(count(&storage, "blobs"), 2);
+        storage.purge_trash(ItemId::File(file.id)).unwrap();
+        assert_eq!(count(&storage, "blobs"), 0);
+    }
+
+    #[test]
+    fn identical_contents_are_stored_once() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let root = storage.get_collection_all()[0].id;
+        let a = storage
+            .create_file(root, "a.md", b"# same".to_vec(), "")
+            .unwrap();
+        let b = storage
+            .create_file(root, "b.md", b"# same".to_vec(), "")
+            .unwrap();
+        assert_eq!(count(&storage, "blobs"), 1);
+        storage
+            .update_file_content(a.id, b"# other".to_vec(), "", None)
+            .unwrap();
+        assert_eq!(count(&storage, "blobs"), 2);
+        storage.move_file(a.id, root, "b.md", true).unwrap();
+        assert_eq!(count(&storage, "blobs"), 2);
+        assert!(matches!(
+            storage.get_file(b.id),
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(storage.get_file_content(a.id).unwrap().body, b"# other");
+        // b went to the trash, and the first revision of a has its content
+        storage.purge_trash(ItemId::File(b.id)).unwrap();
+        assert_eq!(count(&storage, "blobs"), 2);
+        storage.delete_file(a.id, None).unwrap();
+        storage.purge_trash(ItemId::File(a.id)).unwrap();
+        assert_eq!(count(&storage, "blobs"), 0);
+    }
+
+    #[test]
+    fn collections_are_deleted_with_everything_in_them() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let root = storage.get_collection_all()[0].id;
+        let work = storage.create_collection(root, "work").unwrap();
+        let meetings = storage.create_collection(work.id, "meetings").unwrap();
+        storage
+            .create_file(meetings.id, "standup.md", b"# standup".to_vec(), "")
+            .unwrap();
+        assert!(matches!(
+            storage.delete_collection(work.id, false),
+            Err(StorageError::Conflict(_))
+        ));
+        assert!(matches!(
+            storage.move_collection(work.id, meetings.id),
+            Err(StorageError::InvalidArgument(_))
+        ));
+
+        let deleted = storage.delete_collection(work.id, true).unwrap();
+        assert_eq!(deleted.child_collections[0].id, meetings.id);
+        assert_eq!(storage.get_collection_all().len(), 1);
+        storage.purge_trash(ItemId::Collection(work.id)).unwrap();
+        // the root and the collection holding the trash
+        assert_eq!(count(&storage, "collections"), 2);
+        assert_eq!(count(&storage, "files"), 0);
+        assert_eq!(count(&storage, "blobs"), 0);
+        assert_eq!(storage.get_collection_all().len(), 1);
+    }
+
+    #[test]
+    fn users_are_unique() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let alice = User {
+            username: "alice".to_string(),
+            password_hash: "hash".to_string(),
+        };
+        storage.create_user(alice.clone()).unwrap();
+        assert!(matches!(
+            storage.create_user(alice),
+            Err(StorageError::Conflict(_))
+        ));
+        assert!(matches!(
+            storage.get_user("bob"),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn existing_files_start_their_history_when_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.db");
+        let connection = Connection::open(&path).unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO collections (parent_id, name) VALUES (NULL, 'notes');
+                 INSERT INTO blobs (sha256, body) VALUES ('abc', x'2320746f646f');
+                 INSERT INTO files (collection_id, name, sha256, size, created, modified)
+                     VALUES (1, 'todo.md', 'abc', 6, 1, 2);",
+            )
+            .unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        drop(connection);
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        let revisions = storage.list_revisions(1).unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(
+            (revisions[0].created, revisions[0].sha256.as_str()),
+            (2, "abc")
+        );
+        assert_eq!(revisions[0].author, "");
+        assert_eq!(
+            storage
+                .get_revision_content(1, revisions[0].id)
+                .unwrap()
+                .body,
+            b"# todo"
+        );
+    }
+
+    #[test]
+    fn slow_writes_do_not_hold_up_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.db");
+        let storage = Arc::new(SqliteStorage::open(&path).unwrap());
+        let root = storage.get_collection_all()[0].id;
+        let file = storage
+            .create_file(root, "plan.md", b"# plan".to_vec(), "")
+            .unwrap();
+        storage
+            .connection()
+            .busy_timeout(Duration::from_secs(5))
+            .unwrap();
+        // another process keeps the database busy, the write waits for it
+        let other = Connection::open(&path).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let writer = {
+            let storage = Arc::clone(&storage);
+            thread::spawn(move || {
+                storage.update_file_content(file.id, b"# updated".to_vec(), "", None)
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(storage.get_file(file.id), Ok(file.clone()));
+        assert_eq!(storage.collection_lineage(root), Ok(vec![root]));
+        assert_eq!(storage.resolve_path("plan.md"), Ok(ItemId::File(file.id)));
+        assert!(!writer.is_finished());
+        other.execute_batch("COMMIT").unwrap();
+        let updated = writer.join().unwrap().unwrap();
+        assert_eq!(storage.get_file(file.id), Ok(updated));
+    }
+
+    #[test]
+    fn databases_from_newer_versions_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.db");
+        drop(SqliteStorage::open(&path).unwrap());
+        let connection = Connection::open(&path).unwrap();
+        let version: usize = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        drop(connection);
+        assert!(matches!(
+            SqliteStorage::open(&path),
+            Err(StorageError::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use super::{tree::Tree, ItemId, StorageError, StorageResult};
+use crate::oxygen::{trash_item, TrashItem};
+
+/// Collection of [`Trash::tree`] holding the trashed files. Ids never get
+/// that large, so it can't clash with a trashed collection.
+const FILES: u64 = u64::MAX;
+
+/// Collections and files deleted from a [`Tree`], waiting to be restored or
+/// purged. Backends keep one next to their tree and move items between the
+/// two, so that ids and what is known about the contents survive the trip.
+#[derive(Debug)]
+pub struct Trash {
+    /// Trashed collections as roots and trashed files in [`FILES`], along with
+    /// everything in them
+    tree: Tree,
+    entries: HashMap<ItemId, TrashEntry>,
+}
+
+/// Where and when an item of the [`Trash`] was deleted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// Lineage of the collection the item was in, empty for root collections
+    pub lineage: Vec<u64>,
+    /// See [`Tree::path_of`]
+    pub path: String,
+    /// Milliseconds since the Unix epoch
+    pub deleted: u64,
+}
+
+impl TrashEntry {
+    /// Entry of `item` of `tree` deleted at `deleted`
+    pub fn of(tree: &Tree, item: ItemId, deleted: u64) -> StorageResult<Self> {
+        let parent = match item {
+            ItemId::Collection(id) => tree.require_collection(id)?.parent,
+            ItemId::File(id) => Some(tree.require_file(id)?.parent),
+        };
+        Ok(Self {
+            lineage: parent
+                .map(|parent| tree.collection_lineage(parent))
+                .unwrap_or_default(),
+            path: tree.path_of(item)?,
+            deleted,
+        })
+    }
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        let mut tree = Tree::new();
+        tree.insert_collection_with_id(FILES, None, String::new());
+        Self {
+            tree,
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl Trash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves `item`, with everything in it, from `tree` to the trash
+    pub fn put(&mut self, tree: &mut Tree, item: ItemId, deleted: u64) -> StorageResult<()> {
+        let entry = TrashEntry::of(tree, item, deleted)?;
+        self.insert(item, entry, tree);
+        Ok(())
+    }
+
+    /// Moves `item` from `from` to the trash as deleted according to `entry`.
+    /// Lets backends load the trash they keep.
+    pub fn insert(&mut self, item: ItemId, entry: TrashEntry, from: &mut Tree) {
+        let parent = match item {
+            ItemId::Collection(_) => None,
+            ItemId::File(_) => Some(FILES),
+        };
+        from.transplant(item, &mut self.tree, parent);
+        self.entries.insert(item, entry);
+    }
+
+    pub fn entry(&self, item: ItemId) -> StorageResult<&TrashEntry> {
+        self.entries
+            .get(&item)
+            .ok_or_else(|| StorageError::NotFound(format!("{} in the trash", item)))
+    }
+
+    /// Name `item` had when it was deleted
+    pub fn name(&self, item: ItemId) -> StorageResult<&str> {
+        self.entry(item)?;
+        Ok(match item {
+            ItemId::Collection(id) => &self.tree.collection(id).expect("item is trashed").name,
+            ItemId::File(id) => &self.tree.file(id).expect("item is trashed").name,
+        })
+    }
+
+    /// Collection `item` goes back to in `tree`: `parent` if given, otherwise
+    /// the one it was deleted from (`None` for root collections). Fails with
+    /// [`StorageError::NotFound`] if that collection is gone and with
+    /// [`StorageError::Conflict`] if something there has the same name.
+    pub fn check_restore(
+        &self,
+        tree: &Tree,
+        item: ItemId,
+        parent: Option<u64>,
+    ) -> StorageResult<Option<u64>> {
+        let parent = parent.or(self.entry(item)?.lineage.last().copied());
+        if let Some(parent) = parent {
+            tree.check_name_free(parent, self.name(item)?)?;
+        }
+        Ok(parent)
+    }
+
+    /// Moves `item` back to `tree` under `parent`, which must have been
+    /// checked with [`Trash::check_restore`]
+    pub fn restore(&mut self, tree: &mut Tree, item: ItemId, parent: Option<u64>) -> TrashEntry {
+        let entry = self.entries.remove(&item).expect("item is trashed");
+        self.tree.transplant(item, tree, parent);
+        entry
+    }
+
+    /// Forgets `item` for good. Returns the ids of the collections and files
+    /// purged with it.
+    pub fn purge(&mut self, item: ItemId) -> StorageResult<(Vec<u64>, Vec<u64>)> {
+        self.entry(item)?;
+        self.entries.remove(&item);
+        Ok(match item {
+            ItemId::Collection(id) => self.tree.remove_collection(id),
+            ItemId::File(id) => {
+                self.tree.remove_file(id);
+                (vec![], vec![id])
+            }
+        })
+    }
+
+    /// The gRPC view of `item`
+    pub fn to_trash_item(&self, item: ItemId) -> StorageResult<TrashItem> {
+        let entry = self.entry(item)?;
+        let mut view = self.tree.to_item(item).expect("item is trashed");
+        if let trash_item::Item::File(file) = &mut view {
+            // rather than the collection holding the files of the trash
+            file.collection_id = entry.lineage.last().copied().unwrap_or_default();
+        }
+        Ok(TrashItem {
+            item: Some(view),
+            lineage: entry.lineage.clone(),
+            path: entry.path.clone(),
+            deleted: entry.deleted,
+        })
+    }
+
+    /// Every item of the trash, oldest first
+    pub fn list(&self) -> Vec<TrashItem> {
+        let mut items: Vec<_> = self.entries.iter().collect();
+        items.sort_by_key(|(item, entry)| (entry.deleted, **item));
+        items
+            .into_iter()
+            .map(|(item, _)| self.to_trash_item(*item).expect("item is trashed"))
+            .collect()
+    }
+}
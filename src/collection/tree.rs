@@ -0,0 +1,601 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use sha2::{Digest, Sha256};
+
+use super::{file_type, ItemId, StorageError, StorageResult};
+use crate::oxygen::{trash_item, Collection, File, Revision};
+
+/// Flat, id indexed view of a collection hierarchy. Backends keep one of these
+/// around and build the (nested) gRPC messages from it on demand.
+#[derive(Debug, Clone, Default)]
+pub struct Tree {
+    collections: BTreeMap<u64, CollectionNode>,
+    files: BTreeMap<u64, FileNode>,
+    next_collection_id: u64,
+    next_file_id: u64,
+    // only kept once asked for, see `track_touched`
+    touched: Option<BTreeSet<ItemId>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CollectionNode {
+    pub name: String,
+    pub parent: Option<u64>,
+    pub children: Vec<u64>,
+    pub files: Vec<u64>,
+}
+
+impl CollectionNode {
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.files.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileNode {
+    pub name: String,
+    pub parent: u64,
+    pub meta: FileMeta,
+}
+
+/// What the backend knows about the content of a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMeta {
+    pub size: u64,
+    /// Milliseconds since the Unix epoch, 0 if unknown
+    pub created: u64,
+    pub modified: u64,
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+}
+
+impl FileMeta {
+    pub fn of(body: &[u8], created: u64, modified: u64) -> Self {
+        Self {
+            size: body.len() as u64,
+            created,
+            modified,
+            sha256: format!("{:x}", Sha256::digest(body)),
+        }
+    }
+
+    /// Version of the file, see [`File::etag`]. Identical contents are the same
+    /// version: writing over them doesn't lose anything.
+    pub fn etag(&self) -> String {
+        self.sha256.clone()
+    }
+
+    /// Revision `id` of the file `file_id` recording the content this describes
+    pub fn to_revision(&self, file_id: u64, id: u64, author: &str) -> Revision {
+        Revision {
+            id,
+            file_id,
+            created: self.modified,
+            author: author.to_string(),
+            sha256: self.sha256.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts keeping track of the items that are added, removed, renumbered,
+    /// moved or get new content, until [`Tree::take_touched`] hands them out
+    pub fn track_touched(&mut self) {
+        self.touched.get_or_insert_with(BTreeSet::new);
+    }
+
+    /// Items touched since the last call. Moving a collection only touches the
+    /// collection, not what is in it.
+    pub fn take_touched(&mut self) -> BTreeSet<ItemId> {
+        self.touched
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn touch(&mut self, item: ItemId) {
+        if let Some(touched) = &mut self.touched {
+            touched.insert(item);
+        }
+    }
+
+    /// Adds a new collection and returns its id. Ids are handed out in
+    /// insertion order and are never reused.
+    pub fn insert_collection(&mut self, parent: Option<u64>, name: String) -> u64 {
+        let id = self.next_collection_id;
+        self.insert_collection_with_id(id, parent, name);
+        id
+    }
+
+    /// Adds a collection with a known id. Ids handed out by
+    /// [`Tree::insert_collection`] afterwards will be greater than `id`.
+    pub fn insert_collection_with_id(&mut self, id: u64, parent: Option<u64>, name: String) {
+        assert!(
+            !self.collections.contains_key(&id),
+            "collection ids must be unique"
+        );
+        self.next_collection_id = self.next_collection_id.max(id.saturating_add(1));
+        self.touch(ItemId::Collection(id));
+        if let Some(parent_id) = parent {
+            self.collections
+                .get_mut(&parent_id)
+                .expect("parent collection must exist")
+                .children
+                .push(id);
+        }
+        self.collections.insert(
+            id,
+            CollectionNode {
+                name,
+                parent,
+                children: vec![],
+                files: vec![],
+            },
+        );
+    }
+
+    /// Adds a new file to `parent` and returns its id.
+    pub fn insert_file(&mut self, parent: u64, name: String) -> u64 {
+        let id = self.next_file_id;
+        self.insert_file_with_id(id, parent, name);
+        id
+    }
+
+    /// Adds a file with a known id to `parent`.
+    pub fn insert_file_with_id(&mut self, id: u64, parent: u64, name: String) {
+        assert!(!self.files.contains_key(&id), "file ids must be unique");
+        self.next_file_id = self.next_file_id.max(id.saturating_add(1));
+        self.touch(ItemId::File(id));
+        self.collections
+            .get_mut(&parent)
+            .expect("parent collection must exist")
+            .files
+            .push(id);
+        self.files.insert(
+            id,
+            FileNode {
+                name,
+                parent,
+                meta: FileMeta::default(),
+            },
+        );
+    }
+
+    /// Makes sure ids handed out from now on are at least the given ones, so
+    /// that ids of removed items aren't handed out again.
+    pub fn reserve_ids(&mut self, next_collection_id: u64, next_file_id: u64) {
+        self.next_collection_id = self.next_collection_id.max(next_collection_id);
+        self.next_file_id = self.next_file_id.max(next_file_id);
+    }
+
+    /// Ids the next inserted collection and file would get
+    pub fn next_ids(&self) -> (u64, u64) {
+        (self.next_collection_id, self.next_file_id)
+    }
+
+    /// Gives the collection `id` the (unused) id `new_id`
+    pub fn renumber_collection(&mut self, id: u64, new_id: u64) {
+        assert!(
+            !self.collections.contains_key(&new_id),
+            "collection ids must be unique"
+        );
+        let node = self.collections.remove(&id).expect("collection must exist");
+        self.next_collection_id = self.next_collection_id.max(new_id + 1);
+        self.touch(ItemId::Collection(id));
+        self.touch(ItemId::Collection(new_id));
+        if let Some(parent) = node
+            .parent
+            .and_then(|parent| self.collections.get_mut(&parent))
+        {
+            for child in parent.children.iter_mut().filter(|child| **child == id) {
+                *child = new_id;
+            }
+        }
+        for child in &node.children {
+            if let Some(child) = self.collections.get_mut(child) {
+                child.parent = Some(new_id);
+            }
+        }
+        for file in &node.files {
+            if let Some(file) = self.files.get_mut(file) {
+                file.parent = new_id;
+            }
+        }
+        self.collections.insert(new_id, node);
+    }
+
+    /// Gives the file `id` the (unused) id `new_id`
+    pub fn renumber_file(&mut self, id: u64, new_id: u64) {
+        assert!(!self.files.contains_key(&new_id), "file ids must be unique");
+        let node = self.files.remove(&id).expect("file must exist");
+        self.next_file_id = self.next_file_id.max(new_id + 1);
+        self.touch(ItemId::File(id));
+        self.touch(ItemId::File(new_id));
+        let parent = self
+            .collections
+            .get_mut(&node.parent)
+            .expect("parent collection must exist");
+        for file in parent.files.iter_mut().filter(|file| **file == id) {
+            *file = new_id;
+        }
+        self.files.insert(new_id, node);
+    }
+
+    pub fn set_file_meta(&mut self, id: u64, meta: FileMeta) {
+        self.files.get_mut(&id).expect("file must exist").meta = meta;
+        // new content is often written to a new file replacing the old one
+        self.touch(ItemId::File(id));
+    }
+
+    /// Moves the file `id` into the collection `parent` under the name `name`
+    pub fn move_file(&mut self, id: u64, parent: u64, name: String) {
+        self.touch(ItemId::File(id));
+        let file = self.files.get_mut(&id).expect("file must exist");
+        let old_parent = std::mem::replace(&mut file.parent, parent);
+        file.name = name;
+        if old_parent != parent {
+            if let Some(old_parent) = self.collections.get_mut(&old_parent) {
+                old_parent.files.retain(|file_id| *file_id != id);
+            }
+            self.collections
+                .get_mut(&parent)
+                .expect("parent collection must exist")
+                .files
+                .push(id);
+        }
+    }
+
+    pub fn remove_file(&mut self, id: u64) -> Option<FileNode> {
+        let file = self.files.remove(&id)?;
+        self.touch(ItemId::File(id));
+        if let Some(parent) = self.collections.get_mut(&file.parent) {
+            parent.files.retain(|file_id| *file_id != id);
+        }
+        Some(file)
+    }
+
+    pub fn rename_collection(&mut self, id: u64, name: String) {
+        self.touch(ItemId::Collection(id));
+        self.collections
+            .get_mut(&id)
+            .expect("collection must exist")
+            .name = name;
+    }
+
+    /// Moves the collection `id` (and everything in it) under `parent`. Callers
+    /// must make sure the move is valid with [`Tree::check_move`].
+    pub fn move_collection(&mut self, id: u64, parent: u64) {
+        self.touch(ItemId::Collection(id));
+        let old_parent = self
+            .collections
+            .get_mut(&id)
+            .expect("collection must exist")
+            .parent
+            .replace(parent);
+        if let Some(old_parent) = old_parent.and_then(|id| self.collections.get_mut(&id)) {
+            old_parent.children.retain(|child| *child != id);
+        }
+        self.collections
+            .get_mut(&parent)
+            .expect("parent collection must exist")
+            .children
+            .push(id);
+    }
+
+    /// Removes the collection `id` with all of its descendants. Returns the ids
+    /// of the removed collections and files.
+    pub fn remove_collection(&mut self, id: u64) -> (Vec<u64>, Vec<u64>) {
+        let mut removed_collections = vec![];
+        let mut removed_files = vec![];
+        let parent = self.collections.get(&id).and_then(|c| c.parent);
+        if let Some(parent) = parent.and_then(|id| self.collections.get_mut(&id)) {
+            parent.children.retain(|child| *child != id);
+        }
+        let mut pending = vec![id];
+        while let Some(current) = pending.pop() {
+            if let Some(node) = self.collections.remove(&current) {
+                for file in node.files {
+                    self.files.remove(&file);
+                    self.touch(ItemId::File(file));
+                    removed_files.push(file);
+                }
+                pending.extend(node.children);
+                self.touch(ItemId::Collection(current));
+                removed_collections.push(current);
+            }
+        }
+        (removed_collections, removed_files)
+    }
+
+    /// Moves the collection or file `item`, with everything in it, to the tree
+    /// `to` under `parent` (collections can be moved as roots). Ids, names and
+    /// what is known about the contents are kept.
+    pub fn transplant(&mut self, item: ItemId, to: &mut Tree, parent: Option<u64>) {
+        match item {
+            ItemId::File(id) => {
+                let file = self.remove_file(id).expect("file must exist");
+                to.insert_file_with_id(id, parent.expect("files must have a parent"), file.name);
+                to.set_file_meta(id, file.meta);
+            }
+            ItemId::Collection(id) => {
+                // parents go in before their children
+                let mut pending = vec![(id, parent)];
+                while let Some((current, parent)) = pending.pop() {
+                    let node = &self.collections[&current];
+                    to.insert_collection_with_id(current, parent, node.name.clone());
+                    for file in &node.files {
+                        let file_node = &self.files[file];
+                        to.insert_file_with_id(*file, current, file_node.name.clone());
+                        to.set_file_meta(*file, file_node.meta.clone());
+                    }
+                    pending.extend(
+                        node.children
+                            .iter()
+                            .rev()
+                            .map(|child| (*child, Some(current))),
+                    );
+                }
+                self.remove_collection(id);
+            }
+        }
+    }
+
+    pub fn collection(&self, id: u64) -> Option<&CollectionNode> {
+        self.collections.get(&id)
+    }
+
+    pub fn file(&self, id: u64) -> Option<&FileNode> {
+        self.files.get(&id)
+    }
+
+    pub fn collection_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.collections.keys().copied()
+    }
+
+    pub fn file_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.files.keys().copied()
+    }
+
+    pub fn require_collection(&self, id: u64) -> StorageResult<&CollectionNode> {
+        self.collection(id)
+            .ok_or_else(|| StorageError::NotFound(format!("collection with id: {}", id)))
+    }
+
+    pub fn require_file(&self, id: u64) -> StorageResult<&FileNode> {
+        self.file(id)
+            .ok_or_else(|| StorageError::NotFound(format!("file with id: {}", id)))
+    }
+
+    /// Id of the file named `name` directly under the collection `parent`
+    pub fn find_file(&self, parent: u64, name: &str) -> Option<u64> {
+        self.collections
+            .get(&parent)?
+            .files
+            .iter()
+            .copied()
+            .find(|id| self.files.get(id).map(|file| file.name.as_str()) == Some(name))
+    }
+
+    /// Checks that the file `id` can be moved into the collection `parent` as
+    /// `name`. Returns the id of the file that would be replaced by the move,
+    /// which is only allowed when `overwrite` is set.
+    pub fn check_file_move(
+        &self,
+        id: u64,
+        parent: u64,
+        name: &str,
+        overwrite: bool,
+    ) -> StorageResult<Option<u64>> {
+        self.require_file(id)?;
+        match self.find_file(parent, name) {
+            Some(existing) if existing == id => Ok(None),
+            Some(existing) if overwrite => Ok(Some(existing)),
+            _ => self.check_name_free(parent, name).map(|_| None),
+        }
+    }
+
+    /// Fails with [`StorageError::Stale`] unless the file `id` has the etag
+    /// `if_match`, if given
+    pub fn check_etag(&self, id: u64, if_match: Option<&str>) -> StorageResult<()> {
+        let meta = &self.require_file(id)?.meta;
+        match if_match {
+            Some(etag) if etag != meta.etag() => Err(StorageError::Stale(Box::new(
+                self.to_file(id).expect("file exists"),
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with [`StorageError::Conflict`] if the collection `parent` already
+    /// has a collection or a file named `name`.
+    pub fn check_name_free(&self, parent: u64, name: &str) -> StorageResult<()> {
+        let node = self.require_collection(parent)?;
+        let taken = node
+            .children
+            .iter()
+            .filter_map(|id| self.collections.get(id))
+            .any(|child| child.name == name)
+            || node
+                .files
+                .iter()
+                .filter_map(|id| self.files.get(id))
+                .any(|file| file.name == name);
+        if taken {
+            Err(StorageError::Conflict(format!(
+                "{:?} already exists in collection with id: {}",
+                name, parent
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the collection `id` can be moved under `parent` without
+    /// creating a cycle.
+    pub fn check_move(&self, id: u64, parent: u64) -> StorageResult<()> {
+        self.require_collection(id)?;
+        self.require_collection(parent)?;
+        if self.collection_lineage(parent).contains(&id) {
+            return Err(StorageError::InvalidArgument(format!(
+                "can't move collection with id: {} into itself or one of its descendants",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Ids of the collection `id` and all of its ancestors, starting from the
+    /// root.
+    pub fn collection_lineage(&self, id: u64) -> Vec<u64> {
+        let mut lineage = vec![];
+        let mut current = Some(id);
+        while let Some(current_id) = current {
+            lineage.push(current_id);
+            current = self.collections.get(&current_id).and_then(|c| c.parent);
+        }
+        lineage.reverse();
+        lineage
+    }
+
+    /// Collection or file at `path`: the names from a root collection down to
+    /// the item, separated by `/`. Root collections are the empty path.
+    /// Leading and trailing slashes are ignored.
+    pub fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+        let trimmed = path.trim_matches('/');
+        let components: Vec<_> = if trimmed.is_empty() {
+            vec![]
+        } else {
+            trimmed.split('/').collect()
+        };
+        if let Some(component) = components
+            .iter()
+            .find(|component| matches!(**component, "" | "." | ".."))
+        {
+            return Err(StorageError::InvalidArgument(format!(
+                "path {:?} has an invalid component {:?}",
+                path, component
+            )));
+        }
+        let mut candidates: Vec<_> = self
+            .collections
+            .iter()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| ItemId::Collection(*id))
+            .collect();
+        for name in components {
+            candidates = candidates
+                .into_iter()
+                .filter_map(|candidate| match candidate {
+                    ItemId::Collection(id) => self.collections.get(&id),
+                    // files don't have anything in them
+                    ItemId::File(_) => None,
+                })
+                .flat_map(|node| {
+                    let collections = node
+                        .children
+                        .iter()
+                        .filter(|id| self.collections.get(id).is_some_and(|c| c.name == name))
+                        .map(|id| ItemId::Collection(*id));
+                    let files = node
+                        .files
+                        .iter()
+                        .filter(|id| self.files.get(id).is_some_and(|f| f.name == name))
+                        .map(|id| ItemId::File(*id));
+                    collections.chain(files).collect::<Vec<_>>()
+                })
+                .collect();
+        }
+        match candidates.as_slice() {
+            [] => Err(StorageError::NotFound(format!("path: {:?}", path))),
+            [item] => Ok(*item),
+            items => Err(StorageError::InvalidArgument(format!(
+                "path {:?} is ambiguous, it matches {}",
+                path,
+                items
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+
+    /// Path of the collection or file `item`, see [`Tree::resolve_path`]
+    pub fn path_of(&self, item: ItemId) -> StorageResult<String> {
+        let (collection_id, file_name) = match item {
+            ItemId::Collection(id) => {
+                self.require_collection(id)?;
+                (id, None)
+            }
+            ItemId::File(id) => {
+                let file = self.require_file(id)?;
+                (file.parent, Some(file.name.as_str()))
+            }
+        };
+        let names: Vec<_> = self
+            .collection_lineage(collection_id)
+            .into_iter()
+            // root collections are the empty path
+            .skip(1)
+            .filter_map(|id| self.collections.get(&id))
+            .map(|node| node.name.as_str())
+            .chain(file_name)
+            .collect();
+        Ok(names.join("/"))
+    }
+
+    pub fn to_file(&self, id: u64) -> Option<File> {
+        self.files.get(&id).map(|file| File {
+            name: file.name.clone(),
+            id,
+            collection_id: file.parent,
+            size: file.meta.size,
+            created: file.meta.created,
+            modified: file.meta.modified,
+            sha256: file.meta.sha256.clone(),
+            file_type: file_type(&file.name) as i32,
+            etag: file.meta.etag(),
+        })
+    }
+
+    /// Builds the gRPC view of the collection `id` including all of its
+    /// descendants.
+    pub fn to_collection(&self, id: u64) -> Option<Collection> {
+        let node = self.collections.get(&id)?;
+        Some(Collection {
+            name: node.name.clone(),
+            id,
+            child_collections: node
+                .children
+                .iter()
+                .filter_map(|child| self.to_collection(*child))
+                .collect(),
+            files: node
+                .files
+                .iter()
+                .filter_map(|file| self.to_file(*file))
+                .collect(),
+        })
+    }
+
+    /// The gRPC view of the collection or file `item`
+    pub fn to_item(&self, item: ItemId) -> Option<trash_item::Item> {
+        match item {
+            ItemId::Collection(id) => self.to_collection(id).map(trash_item::Item::Collection),
+            ItemId::File(id) => self.to_file(id).map(trash_item::Item::File),
+        }
+    }
+
+    /// Every collection in the tree ordered by id.
+    pub fn to_collection_all(&self) -> Vec<Collection> {
+        self.collections
+            .keys()
+            .filter_map(|id| self.to_collection(*id))
+            .collect()
+    }
+}
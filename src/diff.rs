@@ -0,0 +1,146 @@
+use similar::{ChangeTag, TextDiff};
+
+use crate::oxygen::{diff_line::Kind, DiffHunk, DiffLine, DiffResponse};
+
+/// Lines of unchanged content shown around each change unless the client asks
+/// for something else
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// Line based differences between `old` and `new`, both as a unified diff (the
+/// format of `diff -u`, with `old_name` and `new_name` in its header) and as a
+/// list of hunks. Contents that aren't valid UTF-8 are compared after
+/// replacing the invalid sequences.
+pub fn diff(
+    old: &[u8],
+    new: &[u8],
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> DiffResponse {
+    let old = String::from_utf8_lossy(old);
+    let new = String::from_utf8_lossy(new);
+    let diff = TextDiff::from_lines(old.as_ref(), new.as_ref());
+    let unified_diff = diff
+        .unified_diff()
+        .context_radius(context)
+        .header(old_name, new_name)
+        .to_string();
+    let hunks = diff
+        .grouped_ops(context)
+        .into_iter()
+        .map(|ops| {
+            let (first, last) = (&ops[0], &ops[ops.len() - 1]);
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            let lines = ops
+                .iter()
+                .flat_map(|op| diff.iter_changes(op))
+                .map(|change| {
+                    let kind = match change.tag() {
+                        ChangeTag::Equal => Kind::Context,
+                        ChangeTag::Insert => Kind::Added,
+                        ChangeTag::Delete => Kind::Removed,
+                    };
+                    DiffLine {
+                        kind: kind.into(),
+                        text: change.value().trim_end_matches(['\n', '\r']).to_string(),
+                    }
+                })
+                .collect();
+            DiffHunk {
+                old_start: hunk_start(&old_range),
+                old_lines: old_range.len() as u64,
+                new_start: hunk_start(&new_range),
+                new_lines: new_range.len() as u64,
+                lines,
+            }
+        })
+        .collect();
+    DiffResponse {
+        unified_diff,
+        hunks,
+    }
+}
+
+/// First line of `range` counting from 1, as in unified diff headers. Empty
+/// ranges give the line they come after.
+fn hunk_start(range: &std::ops::Range<usize>) -> u64 {
+    if range.is_empty() {
+        range.start as u64
+    } else {
+        range.start as u64 + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use crate::oxygen::{diff_line::Kind, DiffHunk, DiffLine};
+
+    fn line(kind: Kind, text: &str) -> DiffLine {
+        DiffLine {
+            kind: kind.into(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn changed_lines_are_listed_with_their_context() {
+        let old = b"# standup\n- alice\n- bob\n- carol\n- dave\n- erin\n";
+        let new = b"# standup\n- alice\n- bobby\n- carol\n- dave\n- erin\n- frank\n";
+        let response = diff(old, new, "standup.md@1", "standup.md@2", 1);
+        assert_eq!(
+            response.unified_diff,
+            "--- standup.md@1\n\
+             +++ standup.md@2\n\
+             @@ -2,3 +2,3 @@\n \
+             - alice\n\
+             -- bob\n\
+             +- bobby\n \
+             - carol\n\
+             @@ -6 +6,2 @@\n \
+             - erin\n\
+             +- frank\n"
+        );
+        assert_eq!(
+            response.hunks,
+            [
+                DiffHunk {
+                    old_start: 2,
+                    old_lines: 3,
+                    new_start: 2,
+                    new_lines: 3,
+                    lines: vec![
+                        line(Kind::Context, "- alice"),
+                        line(Kind::Removed, "- bob"),
+                        line(Kind::Added, "- bobby"),
+                        line(Kind::Context, "- carol"),
+                    ],
+                },
+                DiffHunk {
+                    old_start: 6,
+                    old_lines: 1,
+                    new_start: 6,
+                    new_lines: 2,
+                    lines: vec![line(Kind::Context, "- erin"), line(Kind::Added, "- frank")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_contents_have_no_hunks() {
+        let response = diff(b"# same\n", b"# same\n", "a", "b", 3);
+        assert!(response.hunks.is_empty());
+        assert!(response.unified_diff.is_empty());
+    }
+
+    #[test]
+    fn empty_ranges_start_after_the_line_they_follow() {
+        let response = diff(b"", b"# new\n", "a", "b", 3);
+        let hunk = &response.hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_lines), (0, 0));
+        assert_eq!((hunk.new_start, hunk.new_lines), (1, 1));
+        assert!(response.unified_diff.contains("@@ -0,0 +1 @@"));
+    }
+}
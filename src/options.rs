@@ -0,0 +1,242 @@
+use std::{fs, io, net::SocketAddr, path::PathBuf, time::Duration};
+
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+pub const USAGE: &str = "\
+Usage: oxygen-server [options] [notes directory]
+
+Without a notes directory (or database) the server uses a small hard coded set
+of collections.
+
+Options:
+    --addr <address>     address to listen on (default: [::1]:50050)
+    --db <file>          SQLite database to keep the notes in, created if
+                         missing. Can't be used with a notes directory.
+    --tls-cert <file>    PEM certificate (chain) of the server, enables TLS
+    --tls-key <file>     PEM private key of the server certificate
+    --client-ca <file>   PEM certificate of the CA client certificates must be
+                         signed by, enables mutual TLS
+    --session-timeout <secs>
+                         seconds clients can stay idle before their session
+                         is dropped (default: 300)
+    --trash-retention <days>
+                         days deleted notes stay in the trash before they are
+                         purged (default: 30), 0 keeps them until they are
+                         purged by hand
+    --max-upload-size <mib>
+                         largest file clients can upload, in MiB (default:
+                         64)";
+
+const DEFAULT_ADDR: &str = "[::1]:50050";
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const MIB: usize = 1024 * 1024;
+
+/// Command line options of `oxygen-server`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub addr: SocketAddr,
+    pub root: Option<PathBuf>,
+    pub db: Option<PathBuf>,
+    pub tls: Option<TlsFiles>,
+    /// How long clients can stay idle, `None` for the server default
+    pub session_timeout: Option<Duration>,
+    /// How long deleted items stay in the trash, `None` for as long as they
+    /// aren't purged by hand
+    pub trash_retention: Option<Duration>,
+    /// Largest file clients can upload in bytes, `None` for the server default
+    pub max_upload_size: Option<usize>,
+}
+
+/// PEM files the TLS configuration is loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Only clients with a certificate signed by this CA can connect when set
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Options {
+    /// Parses the command line arguments (without the program name)
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut addr = None;
+        let mut root = None;
+        let mut db = None;
+        let mut cert = None;
+        let mut key = None;
+        let mut client_ca = None;
+        let mut session_timeout = None;
+        let mut trash_retention_days = DEFAULT_TRASH_RETENTION_DAYS;
+        let mut max_upload_size = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--addr" => {
+                    let value = value()?;
+                    let parsed = value
+                        .parse()
+                        .map_err(|err| format!("Invalid address {:?}: {}", value, err))?;
+                    addr = Some(parsed);
+                }
+                "--db" => db = Some(PathBuf::from(value()?)),
+                "--tls-cert" => cert = Some(PathBuf::from(value()?)),
+                "--tls-key" => key = Some(PathBuf::from(value()?)),
+                "--client-ca" => client_ca = Some(PathBuf::from(value()?)),
+                "--session-timeout" => {
+                    let value = value()?;
+                    let secs = value
+                        .parse()
+                        .ok()
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| format!("Invalid number of seconds {:?}", value))?;
+                    session_timeout = Some(Duration::from_secs(secs));
+                }
+                "--trash-retention" => {
+                    let value = value()?;
+                    trash_retention_days = value
+                        .parse()
+                        .map_err(|err| format!("Invalid number of days {:?}: {}", value, err))?;
+                }
+                "--max-upload-size" => {
+                    let value = value()?;
+                    let mib = value
+                        .parse()
+                        .ok()
+                        .filter(|mib| *mib > 0)
+                        .ok_or_else(|| format!("Invalid number of MiB {:?}", value))?;
+                    max_upload_size = Some(MIB.saturating_mul(mib));
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ if root.is_none() => root = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument {:?}", arg)),
+            }
+        }
+        if root.is_some() && db.is_some() {
+            return Err("--db can't be used with a notes directory".to_string());
+        }
+        let tls = match (cert, key, client_ca) {
+            (Some(cert), Some(key), client_ca) => Some(TlsFiles {
+                cert,
+                key,
+                client_ca,
+            }),
+            (None, None, None) => None,
+            (None, None, Some(_)) => return Err("--client-ca requires --tls-cert".to_string()),
+            _ => return Err("--tls-cert and --tls-key go together".to_string()),
+        };
+        Ok(Self {
+            addr: addr.unwrap_or_else(|| DEFAULT_ADDR.parse().expect("default address is valid")),
+            root,
+            db,
+            tls,
+            session_timeout,
+            trash_retention: (trash_retention_days > 0)
+                .then(|| DAY.saturating_mul(trash_retention_days.try_into().unwrap_or(u32::MAX))),
+            max_upload_size,
+        })
+    }
+}
+
+impl TlsFiles {
+    pub fn load(&self) -> io::Result<ServerTlsConfig> {
+        let identity = Identity::from_pem(fs::read(&self.cert)?, fs::read(&self.key)?);
+        let mut config = ServerTlsConfig::new().identity(identity);
+        if let Some(client_ca) = &self.client_ca {
+            config = config.client_ca_root(Certificate::from_pem(fs::read(client_ca)?));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{Options, TlsFiles, DAY, MIB};
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults_to_plaintext_on_localhost() {
+        let options = parse(&[]).expect("no arguments are valid");
+        assert_eq!(options.addr, "[::1]:50050".parse().unwrap());
+        assert_eq!(options.root, None);
+        assert_eq!(options.tls, None);
+        assert_eq!(options.session_timeout, None);
+        assert_eq!(options.trash_retention, Some(30 * DAY));
+        assert_eq!(options.max_upload_size, None);
+        let options = parse(&["notes"]).expect("a notes directory is valid");
+        assert_eq!(options.root, Some(PathBuf::from("notes")));
+        let options = parse(&["--db", "notes.db"]).expect("a database is valid");
+        assert_eq!(options.db, Some(PathBuf::from("notes.db")));
+    }
+
+    #[test]
+    fn parses_tls_options() {
+        let options = parse(&[
+            "--addr",
+            "0.0.0.0:50050",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+            "notes",
+            "--client-ca",
+            "ca.pem",
+        ])
+        .expect("options are valid");
+        assert_eq!(options.addr, "0.0.0.0:50050".parse().unwrap());
+        assert_eq!(options.root, Some(PathBuf::from("notes")));
+        assert_eq!(
+            options.tls,
+            Some(TlsFiles {
+                cert: PathBuf::from("cert.pem"),
+                key: PathBuf::from("key.pem"),
+                client_ca: Some(PathBuf::from("ca.pem")),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_session_timeout() {
+        let options = parse(&["--session-timeout", "90"]).expect("options are valid");
+        assert_eq!(options.session_timeout, Some(Duration::from_secs(90)));
+        assert!(parse(&["--session-timeout", "0"]).is_err());
+        assert!(parse(&["--session-timeout", "soon"]).is_err());
+    }
+
+    #[test]
+    fn parses_trash_retention() {
+        let options = parse(&["--trash-retention", "7"]).expect("options are valid");
+        assert_eq!(options.trash_retention, Some(7 * DAY));
+        let options = parse(&["--trash-retention", "0"]).expect("options are valid");
+        assert_eq!(options.trash_retention, None);
+    }
+
+    #[test]
+    fn parses_max_upload_size() {
+        let options = parse(&["--max-upload-size", "8"]).expect("options are valid");
+        assert_eq!(options.max_upload_size, Some(8 * MIB));
+        assert!(parse(&["--max-upload-size", "0"]).is_err());
+        assert!(parse(&["--max-upload-size", "big"]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(parse(&["--addr", "localhost"]).is_err());
+        assert!(parse(&["--addr"]).is_err());
+        assert!(parse(&["--tls-cert", "cert.pem"]).is_err());
+        assert!(parse(&["--client-ca", "ca.pem"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["--trash-retention", "-1"]).is_err());
+        assert!(parse(&["notes", "more notes"]).is_err());
+        assert!(parse(&["notes", "--db", "notes.db"]).is_err());
+    }
+}
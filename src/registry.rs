@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A registered client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// Random id telling this session apart from the other sessions of the
+    /// client, before and after it
+    pub id: String,
+    pub registered_at: SystemTime,
+    /// Last time the client made a request
+    pub last_seen: Instant,
+    /// Whatever the client told about itself when registering (ex: its name
+    /// and version)
+    pub metadata: HashMap<String, String>,
+    /// User the client logged in as
+    pub user: Option<String>,
+}
+
+/// Keeps track of the clients registered with the server, indexed by their
+/// uuid. Sessions of clients that don't make any request for `timeout` are
+/// evicted.
+#[derive(Debug)]
+pub struct ClientRegistry {
+    sessions: Mutex<HashMap<String, Session>>,
+    timeout: Duration,
+}
+
+impl ClientRegistry {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().expect("registry lock poisoned")
+    }
+
+    /// Starts a new session for `uuid`, returned along with the previous
+    /// session of the client if it was still live. Registering again replaces
+    /// the previous session.
+    pub fn register(
+        &self,
+        uuid: &str,
+        metadata: HashMap<String, String>,
+    ) -> (Session, Option<Session>) {
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut id).expect("failed to generate a session id");
+        let session = Session {
+            id: id.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            registered_at: SystemTime::now(),
+            last_seen: Instant::now(),
+            metadata,
+            user: None,
+        };
+        let previous = self.lock().insert(uuid.to_string(), session.clone());
+        let previous = previous.filter(|previous| !self.is_stale(previous, Instant::now()));
+        (session, previous)
+    }
+
+    /// Ends the session of `uuid`, returning it if there was one
+    pub fn unregister(&self, uuid: &str) -> Option<Session> {
+        let session = self.lock().remove(uuid)?;
+        (!self.is_stale(&session, Instant::now())).then_some(session)
+    }
+
+    /// Marks `uuid` as seen now, returning its session. Returns `None` if the
+    /// client doesn't have a session, it went stale, or it isn't the session
+    /// `id` (ex: the client registered again since).
+    pub fn touch(&self, uuid: &str, id: &str) -> Option<Session> {
+        let now = Instant::now();
+        let mut sessions = self.lock();
+        match sessions.get_mut(uuid) {
+            Some(session) if self.is_stale(session, now) => {
+                sessions.remove(uuid);
+                None
+            }
+            Some(session) if session.id == id => {
+                session.last_seen = now;
+                Some(session.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Ties the session of `uuid` to `user` until it ends. Returns `false` if
+    /// the client doesn't have a session.
+    pub fn login(&self, uuid: &str, user: &str) -> bool {
+        match self.lock().get_mut(uuid) {
+            Some(session) => {
+                session.user = Some(user.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops every stale session, returning the uuids of the evicted clients
+    pub fn evict_stale(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut evicted = vec![];
+        self.lock().retain(|uuid, session| {
+            let stale = self.is_stale(session, now);
+            if stale {
+                evicted.push(uuid.clone());
+            }
+            !stale
+        });
+        evicted
+    }
+
+    fn is_stale(&self, session: &Session, now: Instant) -> bool {
+        now.saturating_duration_since(session.last_seen) > self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, thread, time::Duration};
+
+    use super::ClientRegistry;
+
+    #[test]
+    fn only_registered_clients_are_known() {
+        let registry = ClientRegistry::new(Duration::from_secs(60));
+        assert!(registry.touch("a", "").is_none());
+        let metadata = HashMap::from([("name".to_string(), "test".to_string())]);
+        let (first, previous) = registry.register("a", HashMap::new());
+        assert!(previous.is_none());
+        let (second, replaced) = registry.register("a", metadata.clone());
+        let replaced = replaced.expect("expected the first session");
+        assert_eq!(replaced.id, first.id);
+        assert_ne!(second.id, first.id);
+        assert!(registry.touch("a", &first.id).is_none());
+        assert!(registry.touch("a", &second.id).is_some());
+        let session = registry.unregister("a").expect("expected a session");
+        assert_eq!(session.metadata, metadata);
+        assert!(session.last_seen >= replaced.last_seen);
+        assert!(registry.unregister("a").is_none());
+        assert!(registry.touch("a", &second.id).is_none());
+    }
+
+    #[test]
+    fn sessions_remember_who_logged_in() {
+        let registry = ClientRegistry::new(Duration::from_secs(60));
+        assert!(!registry.login("a", "alice"));
+        let (session, _) = registry.register("a", HashMap::new());
+        let touched = registry.touch("a", &session.id);
+        assert_eq!(touched.expect("expected a session").user, None);
+        assert!(registry.login("a", "alice"));
+        let touched = registry
+            .touch("a", &session.id)
+            .expect("expected a session");
+        assert_eq!(touched.user.as_deref(), Some("alice"));
+        // registering again starts from a clean session
+        let (session, _) = registry.register("a", HashMap::new());
+        let touched = registry.touch("a", &session.id);
+        assert_eq!(touched.expect("expected a session").user, None);
+    }
+
+    #[test]
+    fn stale_sessions_are_evicted() {
+        let registry = ClientRegistry::new(Duration::from_millis(50));
+        let (stale, _) = registry.register("stale", HashMap::new());
+        thread::sleep(Duration::from_millis(100));
+        let (fresh, _) = registry.register("fresh", HashMap::new());
+        assert_eq!(registry.evict_stale(), vec!["stale".to_string()]);
+        assert!(registry.touch("fresh", &fresh.id).is_some());
+        assert!(registry.touch("stale", &stale.id).is_none());
+    }
+}
@@ -1,65 +1,801 @@
-use collection::{HardCodedStorage, Storage};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use acl::AccessControl;
+use auth::{hash_password, verify_password, AuthInterceptor, AuthenticatedClient, Authenticator};
+use collection::{
+    async_storage::AsyncStorage, filesystem::FilesystemStorage, search::Searchable,
+    sqlite::SqliteStorage, trash_item_id, unix_millis, Change, HardCodedStorage, ItemId, Storage,
+    StorageError, User,
+};
+use options::{Options, TlsFiles};
 use oxygen::{
+    change_event::Kind,
+    diff_request, get_path_request,
     oxygen_server::{Oxygen, OxygenServer},
-    ClientId, CollectionRequest, CollectionResponse, FileContent, FileRequest, FileResponse,
-    RegResponse,
+    path_response, restore_trash_request, restore_trash_response, trash_item, trash_request,
+    AclResponse, ChangeEvent, ClientId, Collection, CollectionRequest, CollectionResponse,
+    CreateCollectionRequest, CreateFileRequest, CreateUserRequest, DeleteCollectionRequest,
+    DeleteFileRequest, DiffRequest, DiffResponse, DownloadFileRequest, File, FileChunk,
+    FileContent, FileRequest, FileResponse, GetPathRequest, HeartbeatResponse, LoginRequest,
+    MoveCollectionRequest, MoveFileRequest, MoveFileResponse, PathResponse, Permission,
+    RegResponse, RegisterRequest, RenameCollectionRequest, ResolvePathRequest, RestoreTrashRequest,
+    RestoreTrashResponse, RevisionRequest, RevisionsResponse, SearchRequest, SearchResponse,
+    SetAclRequest, TrashItem, TrashItemResponse, TrashRequest, TrashResponse, UnregisterResponse,
+    UpdateFileRequest, UploadFileChunk, UserResponse, WatchRequest,
+};
+use registry::ClientRegistry;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, Mutex,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    codegen::InterceptedService, transport::ServerTlsConfig, Request, Response, Status, Streaming,
 };
-use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
+mod acl;
+mod auth;
 mod collection;
+mod diff;
+mod options;
+mod registry;
 
+// streaming rpcs get an associated type named after the (camel case) rpc
+#[allow(non_camel_case_types)]
 pub mod oxygen {
     tonic::include_proto!("oxygen_lib");
 }
 
-pub struct OxygenService {
+/// Size of the chunks sent by `downloadFile` unless the client asks for
+/// something else
+const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024;
+/// Chunks are kept well below the default 4MB message limit of tonic
+const MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+/// Largest file `uploadFile` accepts unless the server is configured otherwise.
+/// Uploads are put together in memory before they are stored.
+const DEFAULT_MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+/// Hits returned by `search` unless the client asks for something else
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+/// Number of changes buffered for each watcher before it is considered too slow
+/// to keep up
+const CHANGE_BUFFER_SIZE: usize = 1024;
+/// Environment variable holding the secret new clients need to register
+const ADMIN_SECRET_VAR: &str = "OXYGEN_ADMIN_SECRET";
+/// Clients that don't make any request for this long have to register again
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Deleted items are purged once they have been in the trash for this long
+const DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How often the trash is checked for items past the retention period
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct OxygenService<S> {
     id: Uuid,
-    // TODO: think how we can have different types for this (probably we will do this as an enum)
-    storage: HardCodedStorage,
+    storage: AsyncStorage<Searchable<S>>,
+    chunk_size: u64,
+    max_upload_size: usize,
+    changes: broadcast::Sender<Change>,
+    registry: Arc<ClientRegistry>,
+    auth: Arc<Authenticator>,
+    acl: Arc<AccessControl>,
+    // held while storing an access control list and updating `acl` with it, so
+    // that concurrent changes end up in the same order in both
+    acl_writes: Mutex<()>,
+    trash_retention: Option<Duration>,
 }
 
-impl Default for OxygenService {
-    fn default() -> Self {
+impl<S: Storage + Send + Sync + 'static> OxygenService<S> {
+    /// Service serving the content of `storage`, with the access control lists
+    /// it keeps. Panics if the lists can't be loaded, rather than leaving every
+    /// collection open.
+    pub fn new(storage: S) -> Self {
+        let (changes, _) = broadcast::channel(CHANGE_BUFFER_SIZE);
+        let acls = storage
+            .get_acls()
+            .unwrap_or_else(|err| panic!("Failed to load the access control lists: {}", err));
         Self {
             id: uuid::Uuid::new_v4(),
-            storage: HardCodedStorage::new(),
+            storage: AsyncStorage::new(Searchable::new(storage)),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+            changes,
+            registry: Arc::new(ClientRegistry::new(DEFAULT_SESSION_TIMEOUT)),
+            auth: Arc::new(Authenticator::new(None)),
+            acl: Arc::new(AccessControl::with_lists(acls)),
+            acl_writes: Mutex::new(()),
+            trash_retention: Some(DEFAULT_TRASH_RETENTION),
+        }
+    }
+
+    /// Sets the default chunk size (in bytes) for streamed downloads
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// Sets the largest file (in bytes) accepted by streamed uploads
+    pub fn with_max_upload_size(mut self, max_upload_size: usize) -> Self {
+        self.max_upload_size = max_upload_size;
+        self
+    }
+
+    /// Sets how long clients can go without making any request before their
+    /// session is dropped
+    pub fn with_session_timeout(mut self, timeout: Duration) -> Self {
+        self.registry = Arc::new(ClientRegistry::new(timeout));
+        self
+    }
+
+    /// Sets how long deleted items stay in the trash before they are purged,
+    /// `None` keeps them until they are purged by hand
+    pub fn with_trash_retention(mut self, retention: Option<Duration>) -> Self {
+        self.trash_retention = retention;
+        self
+    }
+
+    /// Requires new clients to present `secret` to register
+    pub fn with_admin_secret(mut self, secret: &str) -> Self {
+        self.auth = Arc::new(Authenticator::new(Some(secret)));
+        self
+    }
+
+    /// Rejects requests unless they come from the client named in the request
+    /// (as proved by its token) and the client has a (live) session. Returns
+    /// the client, for the permission checks.
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    fn check_client(
+        &self,
+        caller: Option<AuthenticatedClient>,
+        client_id: &ClientId,
+    ) -> Result<AuthenticatedClient, Status> {
+        let message = match caller {
+            None => format!("Request from {:?} without session token", client_id.uuid),
+            Some(caller) if caller.uuid != client_id.uuid => {
+                let message = format!(
+                    "Request from {:?} with the session token of {:?}",
+                    client_id.uuid, caller.uuid
+                );
+                eprintln!("{}", message);
+                return Err(Status::new(tonic::Code::PermissionDenied, message));
+            }
+            Some(caller) => match self.registry.touch(&client_id.uuid, &caller.session) {
+                Some(session) => {
+                    return Ok(AuthenticatedClient {
+                        user: session.user,
+                        ..caller
+                    })
+                }
+                None => format!(
+                    "Client {:?} is not registered, or its session ended",
+                    client_id.uuid
+                ),
+            },
+        };
+        eprintln!("{}", message);
+        Err(Status::new(tonic::Code::Unauthenticated, message))
+    }
+
+    /// Rejects requests of `caller` unless it has (at least) the `needed`
+    /// permission on the collection `collection_id`
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    fn require(
+        &self,
+        caller: &AuthenticatedClient,
+        collection_id: u64,
+        needed: Permission,
+    ) -> Result<(), Status> {
+        let granted = match self.storage.collection_lineage(collection_id) {
+            Ok(lineage) => self.acl.permission(caller, &lineage),
+            Err(err) => {
+                eprintln!("Failed to get collection {}: {}", collection_id, err);
+                return Err(err.into());
+            }
+        };
+        if granted >= needed {
+            return Ok(());
+        }
+        let message = format!(
+            "Client {:?} needs {:?} permission on collection {}",
+            caller.uuid, needed, collection_id
+        );
+        eprintln!("{}", message);
+        Err(Status::new(tonic::Code::PermissionDenied, message))
+    }
+
+    /// Rejects requests of `caller` for the access control list of the
+    /// collection `collection_id` unless it has admin permission on it.
+    /// Collections without any list on the way up to their root are open to
+    /// every client, their first list is up to the server's admin (`is_admin`)
+    /// so that no client can take them over. Servers without an admin secret
+    /// have no admin, their collections stay open.
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    fn require_acl_admin(
+        &self,
+        caller: &AuthenticatedClient,
+        collection_id: u64,
+        is_admin: bool,
+    ) -> Result<(), Status> {
+        let restricted = match self.storage.collection_lineage(collection_id) {
+            Ok(lineage) => self.acl.restricted(&lineage),
+            Err(err) => {
+                eprintln!("Failed to get collection {}: {}", collection_id, err);
+                return Err(err.into());
+            }
+        };
+        if restricted {
+            return self.require(caller, collection_id, Permission::Admin);
+        }
+        if is_admin {
+            return Ok(());
+        }
+        let message = format!(
+            "Client {:?} needs the admin secret for the ACL of open collection {}",
+            caller.uuid, collection_id
+        );
+        eprintln!("{}", message);
+        Err(Status::new(tonic::Code::PermissionDenied, message))
+    }
+
+    /// Like [`Self::require`] for the collection the file `file_id` is in,
+    /// returns the file
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    fn require_file(
+        &self,
+        caller: &AuthenticatedClient,
+        file_id: u64,
+        needed: Permission,
+    ) -> Result<File, Status> {
+        let file = match self.storage.get_file(file_id) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("Failed to get file {}: {}", file_id, err);
+                return Err(err.into());
+            }
+        };
+        self.require(caller, file.collection_id, needed)?;
+        Ok(file)
+    }
+
+    /// Like [`Self::require`] for a collection or the collection a file is in
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    fn require_item(
+        &self,
+        caller: &AuthenticatedClient,
+        item: ItemId,
+        needed: Permission,
+    ) -> Result<(), Status> {
+        match item {
+            ItemId::Collection(id) => self.require(caller, id, needed),
+            ItemId::File(id) => self.require_file(caller, id, needed).map(|_| ()),
+        }
+    }
+
+    /// Like [`Self::require`] for `collection` and every collection in it
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    fn require_all(
+        &self,
+        caller: &AuthenticatedClient,
+        collection: &Collection,
+        needed: Permission,
+    ) -> Result<(), Status> {
+        self.require(caller, collection.id, needed)?;
+        for child in &collection.child_collections {
+            self.require_all(caller, child, needed)?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::require`] for the collection `item` of the trash was deleted
+    /// from (and the item itself if it is a collection). Returns the item.
+    // handlers pass tonic's status on as is
+    #[allow(clippy::result_large_err)]
+    async fn require_trashed(
+        &self,
+        caller: &AuthenticatedClient,
+        item: ItemId,
+        needed: Permission,
+    ) -> Result<TrashItem, Status> {
+        let trashed = match self.storage.list_trash().await {
+            Ok(trash) => trash
+                .into_iter()
+                .find(|trashed| trash_item_id(trashed) == Some(item)),
+            Err(err) => {
+                eprintln!("Failed to list the trash: {}", err);
+                return Err(err.into());
+            }
+        };
+        let Some(trashed) = trashed else {
+            let message = format!("{} is not in the trash", item);
+            eprintln!("{}", message);
+            return Err(Status::new(tonic::Code::NotFound, message));
+        };
+        if self.acl.permission(caller, &trashed_lineage(&trashed)) >= needed {
+            return Ok(trashed);
+        }
+        let message = format!(
+            "Client {:?} needs {:?} permission on {} in the trash",
+            caller.uuid, needed, item
+        );
+        eprintln!("{}", message);
+        Err(Status::new(tonic::Code::PermissionDenied, message))
+    }
+
+    /// `collection` without the collections in it that `caller` can't read
+    fn readable(&self, caller: &AuthenticatedClient, collection: Collection) -> Collection {
+        readable(&self.storage, &self.acl, caller, collection)
+            .expect("callers can read the collections they are sent")
+    }
+
+    fn session_timeout(&self) -> u64 {
+        self.registry.timeout().as_millis() as u64
+    }
+
+    fn lineage(&self, collection_id: u64) -> Vec<u64> {
+        self.storage
+            .collection_lineage(collection_id)
+            .unwrap_or_default()
+    }
+
+    fn publish(&self, event: ChangeEvent, mut collections: Vec<u64>) {
+        collections.sort_unstable();
+        collections.dedup();
+        // it is fine if nobody is watching
+        let _ = self.changes.send(Change { event, collections });
+    }
+
+    /// Lets watchers know that `file` has changed. `previous_collection_id` is
+    /// the collection the file was in before the change if it was moved.
+    fn publish_file_change(&self, kind: Kind, file: &File, previous_collection_id: Option<u64>) {
+        let mut collections = self.lineage(file.collection_id);
+        if let Some(previous_collection_id) = previous_collection_id {
+            collections.extend(self.lineage(previous_collection_id));
+        }
+        let event = ChangeEvent {
+            kind: kind as i32,
+            file: Some(file.clone()),
+            collection: None,
+            collection_id: file.collection_id,
+            previous_collection_id: previous_collection_id.unwrap_or_default(),
+        };
+        self.publish(event, collections);
+    }
+
+    /// Lets watchers know that `collection` has changed. `lineage` is the
+    /// lineage of the collection after the change (before for deletes) and
+    /// `previous_lineage` the lineage before the change if it was moved.
+    fn publish_collection_change(
+        &self,
+        kind: Kind,
+        collection: &Collection,
+        lineage: Vec<u64>,
+        previous_lineage: Vec<u64>,
+    ) {
+        // root collections aren't in any collection, so they are reported as
+        // changes in themselves
+        let parent = |lineage: &[u64]| lineage.iter().rev().nth(1).copied();
+        let event = ChangeEvent {
+            kind: kind as i32,
+            file: None,
+            collection: Some(collection.clone()),
+            collection_id: parent(&lineage).unwrap_or(collection.id),
+            previous_collection_id: parent(&previous_lineage).unwrap_or_default(),
+        };
+        let mut collections = lineage;
+        collections.extend(previous_lineage);
+        self.publish(event, collections);
+    }
+}
+
+impl<S: Storage + Send + Sync + 'static> OxygenService<S> {
+    /// gRPC service checking the session tokens of the requests
+    pub fn into_server(self) -> InterceptedService<OxygenServer<Self>, AuthInterceptor> {
+        let interceptor = AuthInterceptor::new(Arc::clone(&self.auth));
+        OxygenServer::with_interceptor(self, interceptor)
+    }
+}
+
+/// `collection` without the collections in it that `client` can't read, `None`
+/// if it can't read `collection` itself
+fn readable<S: Storage + Send + Sync + 'static>(
+    storage: &AsyncStorage<S>,
+    acl: &AccessControl,
+    client: &AuthenticatedClient,
+    mut collection: Collection,
+) -> Option<Collection> {
+    let lineage = storage.collection_lineage(collection.id).ok()?;
+    if acl.permission(client, &lineage) < Permission::Read {
+        return None;
+    }
+    collection.child_collections = std::mem::take(&mut collection.child_collections)
+        .into_iter()
+        .filter_map(|child| readable(storage, acl, client, child))
+        .collect();
+    Some(collection)
+}
+
+/// `change` as `client` gets to see it, `None` if it can't read any of the
+/// collections the change happened in
+fn readable_change<S: Storage + Send + Sync + 'static>(
+    storage: &AsyncStorage<S>,
+    acl: &AccessControl,
+    client: &AuthenticatedClient,
+    change: Change,
+) -> Option<ChangeEvent> {
+    let mut event = change.event;
+    let mut collection_ids = vec![match &event.collection {
+        Some(collection) => collection.id,
+        None => event.collection_id,
+    }];
+    if matches!(event.kind(), Kind::FileMoved | Kind::CollectionMoved) {
+        collection_ids.push(event.previous_collection_id);
+    }
+    let lineages: Vec<_> = collection_ids
+        .into_iter()
+        .filter_map(|id| storage.collection_lineage(id).ok())
+        .collect();
+    let can_read = if lineages.is_empty() {
+        // the collections are gone, go by where they were
+        acl.permission(client, &change.collections) >= Permission::Read
+    } else {
+        lineages
+            .iter()
+            .any(|lineage| acl.permission(client, lineage) >= Permission::Read)
+    };
+    if !can_read {
+        return None;
+    }
+    if let Some(collection) = event.collection.take() {
+        // deleted collections can't be checked any more
+        let pruned = readable(storage, acl, client, collection.clone());
+        event.collection = Some(pruned.unwrap_or(collection));
+    }
+    Some(event)
+}
+
+/// Like [`readable`] for a collection of the trash, which was deleted from the
+/// collection with the lineage `lineage`
+fn readable_trashed(
+    acl: &AccessControl,
+    client: &AuthenticatedClient,
+    lineage: &[u64],
+    mut collection: Collection,
+) -> Option<Collection> {
+    let lineage = [lineage, &[collection.id]].concat();
+    if acl.permission(client, &lineage) < Permission::Read {
+        return None;
+    }
+    collection.child_collections = std::mem::take(&mut collection.child_collections)
+        .into_iter()
+        .filter_map(|child| readable_trashed(acl, client, &lineage, child))
+        .collect();
+    Some(collection)
+}
+
+/// Lineage the permissions on `item` of the trash go by: the lineage of the
+/// collection it was deleted from, along with the item if it is a collection
+fn trashed_lineage(item: &TrashItem) -> Vec<u64> {
+    let mut lineage = item.lineage.clone();
+    if let Some(trash_item::Item::Collection(collection)) = &item.item {
+        lineage.push(collection.id);
+    }
+    lineage
+}
+
+/// Purges the items that have been in the trash for longer than `retention`,
+/// along with the access control lists of the purged collections
+async fn purge_expired_trash<S: Storage + Send + Sync + 'static>(
+    storage: &AsyncStorage<S>,
+    acl: &AccessControl,
+    retention: Duration,
+) {
+    let cutoff = unix_millis(SystemTime::now()).saturating_sub(retention.as_millis() as u64);
+    match storage.purge_trash_before(cutoff).await {
+        Ok(purged) => {
+            for item in purged {
+                if let Some(trash_item::Item::Collection(collection)) = &item.item {
+                    acl.remove(collection_ids(collection));
+                }
+                println!("Purged {:?} from the trash", item.path);
+            }
         }
+        Err(err) => eprintln!("Failed to purge the trash: {}", err),
+    }
+}
+
+fn path_response(path: String, item: ItemId) -> PathResponse {
+    let item = match item {
+        ItemId::Collection(id) => path_response::Item::CollectionId(id),
+        ItemId::File(id) => path_response::Item::FileId(id),
+    };
+    PathResponse {
+        path,
+        item: Some(item),
+    }
+}
+
+/// Ids of `collection` and every collection in it
+fn collection_ids(collection: &Collection) -> Vec<u64> {
+    let mut ids = vec![collection.id];
+    for child in &collection.child_collections {
+        ids.extend(collection_ids(child));
+    }
+    ids
+}
+
+/// Client proved to have sent `request` by the auth interceptor
+fn authenticated_client<T>(request: &Request<T>) -> Option<AuthenticatedClient> {
+    request.extensions().get::<AuthenticatedClient>().cloned()
+}
+
+/// Etag a write expects the file to have, requests leave it empty to write
+/// whatever the version
+fn expected_etag(if_match: &str) -> Option<&str> {
+    (!if_match.is_empty()).then_some(if_match)
+}
+
+/// Runs `f` (ex: deliberately slow password hashing) off the runtime threads
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, Status> {
+    tokio::task::spawn_blocking(f).await.map_err(|err| {
+        eprintln!("Blocking task failed: {}", err);
+        Status::new(tonic::Code::Internal, "Blocking task failed")
+    })
+}
+
+async fn recv_change(
+    changes: &mut Option<broadcast::Receiver<Change>>,
+) -> Result<Change, RecvError> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+impl Default for OxygenService<HardCodedStorage> {
+    fn default() -> Self {
+        Self::new(HardCodedStorage::new())
     }
 }
+
 #[tonic::async_trait]
-impl Oxygen for OxygenService {
-    async fn register(&self, request: Request<ClientId>) -> Result<Response<RegResponse>, Status> {
-        // TODO: keep track of registered client and state of clients
+impl<S: Storage + Send + Sync + 'static> Oxygen for OxygenService<S> {
+    type downloadFileStream = ReceiverStream<Result<FileChunk, Status>>;
+    type watchStream = ReceiverStream<Result<ChangeEvent, Status>>;
+
+    async fn register(
+        &self,
+        request: Request<RegisterRequest>,
+    ) -> Result<Response<RegResponse>, Status> {
+        let caller = authenticated_client(&request);
+        // anyone may register when the server doesn't have an admin secret
+        let is_admin = !self.auth.has_admin_secret() || self.auth.is_admin(request.metadata());
+        match request.into_inner() {
+            RegisterRequest {
+                client_id: Some(client_id),
+                metadata,
+            } => {
+                // TODO: setup logging
+                println!(
+                    "Get register request from: {:?} with metadata: {:?}",
+                    &client_id.uuid, metadata
+                );
+                // clients can renew their own live session, new ones (and
+                // those whose session ended) need the admin secret
+                let renewing = caller.is_some_and(|caller| {
+                    caller.uuid == client_id.uuid
+                        && self.registry.touch(&caller.uuid, &caller.session).is_some()
+                });
+                if !is_admin && !renewing {
+                    let message = format!(
+                        "Client {:?} can't register without the admin secret",
+                        client_id.uuid
+                    );
+                    eprintln!("{}", message);
+                    return Err(Status::new(tonic::Code::Unauthenticated, message));
+                }
+                let (session, previous) = self.registry.register(&client_id.uuid, metadata);
+                if previous.is_some() {
+                    println!("Replaced existing session of: {:?}", &client_id.uuid);
+                }
+                let reply = RegResponse {
+                    token: self.auth.issue_token(&client_id.uuid, &session.id),
+                    client_id: client_id.uuid,
+                    server_id: self.id.to_string(),
+                    session_timeout: self.session_timeout(),
+                };
+                Ok(Response::new(reply))
+            }
+            RegisterRequest {
+                client_id: None, ..
+            } => {
+                let message = "Got register request without client Id".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn unregister(
+        &self,
+        request: Request<ClientId>,
+    ) -> Result<Response<UnregisterResponse>, Status> {
+        let caller = authenticated_client(&request);
         let client_id = request.into_inner();
-        // TODO: setup logging
-        println!("Get register request from: {:?}", &client_id.uuid);
+        println!("Get unregister request from: {:?}", &client_id.uuid);
+        self.check_client(caller, &client_id)?;
+        match self.registry.unregister(&client_id.uuid) {
+            Some(_) => Ok(Response::new(UnregisterResponse {})),
+            None => {
+                let message = format!("Client {:?} is not registered", client_id.uuid);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::Unauthenticated, message))
+            }
+        }
+    }
 
-        let reply = RegResponse {
-            client_id: client_id.uuid,
-            server_id: self.id.to_string(),
-        };
-        Ok(Response::new(reply))
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let caller = authenticated_client(&request);
+        let is_admin = self.auth.is_admin(request.metadata());
+        match request.into_inner() {
+            CreateUserRequest {
+                client_id: Some(client_id),
+                username,
+                password,
+            } => {
+                println!(
+                    "Create user request from: {:?} for user: {:?}",
+                    &client_id.uuid, username
+                );
+                self.check_client(caller, &client_id)?;
+                if !is_admin {
+                    let message = format!(
+                        "Client {:?} can't create users without the admin secret",
+                        client_id.uuid
+                    );
+                    eprintln!("{}", message);
+                    return Err(Status::new(tonic::Code::PermissionDenied, message));
+                }
+                if password.is_empty() {
+                    let message = format!(
+                        "Got create user request for {:?} without password",
+                        username
+                    );
+                    eprintln!("{}", message);
+                    return Err(Status::new(tonic::Code::InvalidArgument, message));
+                }
+                let user = User {
+                    username: username.clone(),
+                    password_hash: run_blocking(move || hash_password(&password)).await?,
+                };
+                match self.storage.create_user(user).await {
+                    Ok(user) => Ok(Response::new(UserResponse {
+                        username: user.username,
+                    })),
+                    Err(err) => {
+                        eprintln!("Failed to create user {:?}: {}", username, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            CreateUserRequest {
+                client_id: None,
+                username,
+                ..
+            } => {
+                let message = format!(
+                    "Got create user request for {:?} without client Id",
+                    username
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn login(
+        &self,
+        request: Request<LoginRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            LoginRequest {
+                client_id: Some(client_id),
+                username,
+                password,
+            } => {
+                println!(
+                    "Login request from: {:?} as user: {:?}",
+                    &client_id.uuid, username
+                );
+                self.check_client(caller, &client_id)?;
+                let valid = match self.storage.get_user(&username).await {
+                    Ok(user) => {
+                        run_blocking(move || verify_password(&password, &user.password_hash))
+                            .await?
+                    }
+                    Err(StorageError::NotFound(_)) => false,
+                    Err(err) => {
+                        eprintln!("Failed to get user {:?}: {}", username, err);
+                        return Err(err.into());
+                    }
+                };
+                if !valid {
+                    let message = format!(
+                        "Client {:?} failed to log in: invalid username or password",
+                        client_id.uuid
+                    );
+                    eprintln!("{}", message);
+                    return Err(Status::new(tonic::Code::Unauthenticated, message));
+                }
+                if !self.registry.login(&client_id.uuid, &username) {
+                    let message = format!("Client {:?} is not registered", client_id.uuid);
+                    eprintln!("{}", message);
+                    return Err(Status::new(tonic::Code::Unauthenticated, message));
+                }
+                Ok(Response::new(UserResponse { username }))
+            }
+            LoginRequest {
+                client_id: None,
+                username,
+                ..
+            } => {
+                let message = format!("Got login request as {:?} without client Id", username);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn heartbeat(
+        &self,
+        request: Request<ClientId>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let caller = authenticated_client(&request);
+        let client_id = request.into_inner();
+        self.check_client(caller, &client_id)?;
+        Ok(Response::new(HeartbeatResponse {
+            session_timeout: self.session_timeout(),
+        }))
     }
 
     async fn get_all_collections(
         &self,
         request: Request<ClientId>,
     ) -> Result<Response<CollectionResponse>, Status> {
-        // TODO: keep track of registered client and state of clients
+        let caller = authenticated_client(&request);
         let client_id = request.into_inner();
         // TODO: setup logging
         println!("Get all collection request from: {:?}", &client_id.uuid);
+        let caller = self.check_client(caller, &client_id)?;
 
-        Ok(Response::new(CollectionResponse {
-            collections: self.storage.get_collection_all(),
-        }))
+        let collections = self
+            .storage
+            .get_collection_all()
+            .into_iter()
+            .filter_map(|collection| readable(&self.storage, &self.acl, &caller, collection))
+            .collect();
+        Ok(Response::new(CollectionResponse { collections }))
     }
 
     async fn get_collection(
         &self,
         request: Request<CollectionRequest>,
     ) -> Result<Response<CollectionResponse>, Status> {
+        let caller = authenticated_client(&request);
         match request.into_inner() {
             CollectionRequest {
                 client_id: Some(client_id),
@@ -69,14 +805,16 @@ impl Oxygen for OxygenService {
                     "Get collection request from: {:?} for collection: {:?}",
                     &client_id.uuid, collection_id
                 );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require(&caller, collection_id, Permission::Read)?;
                 match self.storage.get_collection(collection_id) {
-                    Ok(collections) => Ok(Response::new(CollectionResponse {
-                        collections: vec![collections],
+                    Ok(collection) => Ok(Response::new(CollectionResponse {
+                        collections: vec![self.readable(&caller, collection)],
                     })),
-                    Err(()) => Err(Status::new(
-                        tonic::Code::InvalidArgument,
-                        format!("Failed to find collection with id: {}", collection_id),
-                    )),
+                    Err(err) => {
+                        eprintln!("Failed to get collection {}: {}", collection_id, err);
+                        Err(err.into())
+                    }
                 }
             }
             CollectionRequest {
@@ -97,6 +835,7 @@ impl Oxygen for OxygenService {
         &self,
         request: Request<FileRequest>,
     ) -> Result<Response<FileResponse>, Status> {
+        let caller = authenticated_client(&request);
         match request.into_inner() {
             FileRequest {
                 client_id: Some(client_id),
@@ -106,13 +845,9 @@ impl Oxygen for OxygenService {
                     "Get file request from: {:?} for file: {:?}",
                     &client_id.uuid, file_id
                 );
-                match self.storage.get_file(file_id) {
-                    Ok(file) => Ok(Response::new(FileResponse { file: Some(file) })),
-                    Err(()) => Err(Status::new(
-                        tonic::Code::InvalidArgument,
-                        format!("Failed to find file with id: {}", file_id),
-                    )),
-                }
+                let caller = self.check_client(caller, &client_id)?;
+                let file = self.require_file(&caller, file_id, Permission::Read)?;
+                Ok(Response::new(FileResponse { file: Some(file) }))
             }
             FileRequest {
                 client_id: None,
@@ -129,6 +864,7 @@ impl Oxygen for OxygenService {
         &self,
         request: Request<FileRequest>,
     ) -> Result<Response<FileContent>, Status> {
+        let caller = authenticated_client(&request);
         match request.into_inner() {
             FileRequest {
                 client_id: Some(client_id),
@@ -138,12 +874,14 @@ impl Oxygen for OxygenService {
                     "Get file content request from: {:?} for file: {:?}",
                     &client_id.uuid, file_id
                 );
-                match self.storage.get_file_content(file_id) {
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Read)?;
+                match self.storage.get_file_content(file_id).await {
                     Ok(content) => Ok(Response::new(content)),
-                    Err(()) => Err(Status::new(
-                        tonic::Code::InvalidArgument,
-                        format!("Failed to find file with id: {}", file_id),
-                    )),
+                    Err(err) => {
+                        eprintln!("Failed to get content of file {}: {}", file_id, err);
+                        Err(err.into())
+                    }
                 }
             }
             FileRequest {
@@ -156,75 +894,3350 @@ impl Oxygen for OxygenService {
             }
         }
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:50050".parse()?;
-    let oxygen_service = OxygenService::default();
-    tonic::transport::Server::builder()
-        .add_service(OxygenServer::new(oxygen_service))
-        .serve(addr)
-        .await?;
-    Ok(())
-}
+    async fn download_file(
+        &self,
+        request: Request<DownloadFileRequest>,
+    ) -> Result<Response<Self::downloadFileStream>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            DownloadFileRequest {
+                client_id: Some(client_id),
+                file_id,
+                offset,
+                length,
+                chunk_size,
+            } => {
+                println!(
+                    "Download file request from: {:?} for file: {:?} (offset: {}, length: {})",
+                    &client_id.uuid, file_id, offset, length
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                // fail before starting the stream if the file doesn't exist
+                self.require_file(&caller, file_id, Permission::Read)?;
+                let chunk_size = match chunk_size {
+                    0 => self.chunk_size,
+                    chunk_size => u64::from(chunk_size).min(MAX_CHUNK_SIZE),
+                };
+                let end = (length > 0).then(|| offset.saturating_add(length));
+                let storage = self.storage.clone();
+                let (tx, rx) = mpsc::channel(4);
+                tokio::spawn(async move {
+                    let mut offset = offset;
+                    loop {
+                        let to_read = end.map_or(chunk_size, |end| (end - offset).min(chunk_size));
+                        if to_read == 0 {
+                            break;
+                        }
+                        let chunk = match storage
+                            .read_file_range(file_id, offset, Some(to_read))
+                            .await
+                        {
+                            Ok(data) if data.is_empty() => break,
+                            Ok(data) => FileChunk { offset, data },
+                            Err(err) => {
+                                eprintln!("Failed to download file {}: {}", file_id, err);
+                                let _ = tx.send(Err(err.into())).await;
+                                break;
+                            }
+                        };
+                        offset += chunk.data.len() as u64;
+                        if tx.send(Ok(chunk)).await.is_err() {
+                            // client is gone
+                            break;
+                        }
+                    }
+                });
+                Ok(Response::new(ReceiverStream::new(rx)))
+            }
+            DownloadFileRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got download file request for {} without client Id",
+                    file_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
 
-#[cfg(test)]
-mod tests {
+    async fn upload_file(
+        &self,
+        request: Request<Streaming<UploadFileChunk>>,
+    ) -> Result<Response<FileResponse>, Status> {
+        let caller = authenticated_client(&request);
+        let mut chunks = request.into_inner();
+        match chunks.message().await? {
+            Some(UploadFileChunk {
+                client_id: Some(client_id),
+                file_id,
+                data,
+                if_match,
+            }) => {
+                println!(
+                    "Upload file request from: {:?} for file: {:?}",
+                    &client_id.uuid, file_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Write)?;
+                let mut body = data;
+                loop {
+                    if body.len() > self.max_upload_size {
+                        let message = format!(
+                            "Upload of file {} is larger than {} bytes",
+                            file_id, self.max_upload_size
+                        );
+                        eprintln!("{}", message);
+                        return Err(Status::new(tonic::Code::ResourceExhausted, message));
+                    }
+                    match chunks.message().await? {
+                        Some(chunk) => body.extend(chunk.data),
+                        None => break,
+                    }
+                }
+                match self
+                    .storage
+                    .update_file_content(file_id, body, &caller.uuid, expected_etag(&if_match))
+                    .await
+                {
+                    Ok(file) => {
+                        self.publish_file_change(Kind::FileModified, &file, None);
+                        Ok(Response::new(FileResponse { file: Some(file) }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to upload file {}: {}", file_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            Some(UploadFileChunk {
+                client_id: None,
+                file_id,
+                ..
+            }) => {
+                let message = format!("Got upload file request for {} without client Id", file_id);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+            None => {
+                let message = "Got upload file request without any chunks".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
 
-    use crate::oxygen::{oxygen_client::OxygenClient, ClientId, CollectionRequest, FileRequest};
+    async fn create_file(
+        &self,
+        request: Request<CreateFileRequest>,
+    ) -> Result<Response<FileResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            CreateFileRequest {
+                client_id: Some(client_id),
+                collection_id,
+                name,
+                body,
+            } => {
+                println!(
+                    "Create file request from: {:?} for file: {:?} in collection: {:?}",
+                    &client_id.uuid, name, collection_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require(&caller, collection_id, Permission::Write)?;
+                match self
+                    .storage
+                    .create_file(collection_id, &name, body, &caller.uuid)
+                    .await
+                {
+                    Ok(file) => {
+                        self.publish_file_change(Kind::FileCreated, &file, None);
+                        Ok(Response::new(FileResponse { file: Some(file) }))
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to create file {:?} in collection {}: {}",
+                            name, collection_id, err
+                        );
+                        Err(err.into())
+                    }
+                }
+            }
+            CreateFileRequest {
+                client_id: None,
+                name,
+                ..
+            } => {
+                let message = format!("Got create file request for {:?} without client Id", name);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn update_file_content(
+        &self,
+        request: Request<UpdateFileRequest>,
+    ) -> Result<Response<FileResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            UpdateFileRequest {
+                client_id: Some(client_id),
+                file_id,
+                body,
+                if_match,
+            } => {
+                println!(
+                    "Update file content request from: {:?} for file: {:?}",
+                    &client_id.uuid, file_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Write)?;
+                match self
+                    .storage
+                    .update_file_content(file_id, body, &caller.uuid, expected_etag(&if_match))
+                    .await
+                {
+                    Ok(file) => {
+                        self.publish_file_change(Kind::FileModified, &file, None);
+                        Ok(Response::new(FileResponse { file: Some(file) }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to update content of file {}: {}", file_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            UpdateFileRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got update file content request for {} without client Id",
+                    file_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn delete_file(
+        &self,
+        request: Request<DeleteFileRequest>,
+    ) -> Result<Response<FileResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            DeleteFileRequest {
+                client_id: Some(client_id),
+                file_id,
+                if_match,
+            } => {
+                println!(
+                    "Delete file request from: {:?} for file: {:?}",
+                    &client_id.uuid, file_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Write)?;
+                match self
+                    .storage
+                    .delete_file(file_id, expected_etag(&if_match))
+                    .await
+                {
+                    Ok(file) => {
+                        self.publish_file_change(Kind::FileDeleted, &file, None);
+                        Ok(Response::new(FileResponse { file: Some(file) }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to delete file {}: {}", file_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            DeleteFileRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!("Got delete file request for {} without client Id", file_id);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn list_revisions(
+        &self,
+        request: Request<FileRequest>,
+    ) -> Result<Response<RevisionsResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            FileRequest {
+                client_id: Some(client_id),
+                file_id,
+            } => {
+                println!(
+                    "List revisions request from: {:?} for file: {:?}",
+                    &client_id.uuid, file_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Read)?;
+                match self.storage.list_revisions(file_id).await {
+                    Ok(revisions) => Ok(Response::new(RevisionsResponse { revisions })),
+                    Err(err) => {
+                        eprintln!("Failed to list revisions of file {}: {}", file_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            FileRequest {
+                client_id: None,
+                file_id,
+            } => {
+                let message = format!(
+                    "Got list revisions request for {} without client Id",
+                    file_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn get_revision_content(
+        &self,
+        request: Request<RevisionRequest>,
+    ) -> Result<Response<FileContent>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            RevisionRequest {
+                client_id: Some(client_id),
+                file_id,
+                revision_id,
+            } => {
+                println!(
+                    "Get revision content request from: {:?} for revision: {:?} of file: {:?}",
+                    &client_id.uuid, revision_id, file_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Read)?;
+                match self
+                    .storage
+                    .get_revision_content(file_id, revision_id)
+                    .await
+                {
+                    Ok(content) => Ok(Response::new(content)),
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to get revision {} of file {}: {}",
+                            revision_id, file_id, err
+                        );
+                        Err(err.into())
+                    }
+                }
+            }
+            RevisionRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got get revision content request for {} without client Id",
+                    file_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn restore_revision(
+        &self,
+        request: Request<RevisionRequest>,
+    ) -> Result<Response<FileResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            RevisionRequest {
+                client_id: Some(client_id),
+                file_id,
+                revision_id,
+            } => {
+                println!(
+                    "Restore revision request from: {:?} for revision: {:?} of file: {:?}",
+                    &client_id.uuid, revision_id, file_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_file(&caller, file_id, Permission::Write)?;
+                match self
+                    .storage
+                    .restore_revision(file_id, revision_id, &caller.uuid)
+                    .await
+                {
+                    Ok(file) => {
+                        self.publish_file_change(Kind::FileModified, &file, None);
+                        Ok(Response::new(FileResponse { file: Some(file) }))
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to restore revision {} of file {}: {}",
+                            revision_id, file_id, err
+                        );
+                        Err(err.into())
+                    }
+                }
+            }
+            RevisionRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got restore revision request for {} without client Id",
+                    file_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn diff_file(
+        &self,
+        request: Request<DiffRequest>,
+    ) -> Result<Response<DiffResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            DiffRequest {
+                client_id: Some(client_id),
+                file_id,
+                from_revision_id,
+                to,
+                context,
+            } => {
+                println!(
+                    "Diff file request from: {:?} for file: {:?} from revision: {:?} to: {:?}",
+                    &client_id.uuid, file_id, from_revision_id, to
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                let file = self.require_file(&caller, file_id, Permission::Read)?;
+                let contents = async {
+                    let old = self
+                        .storage
+                        .get_revision_content(file_id, from_revision_id)
+                        .await?;
+                    let (new, new_name) = match to {
+                        Some(diff_request::To::ToRevisionId(revision_id)) => (
+                            self.storage
+                                .get_revision_content(file_id, revision_id)
+                                .await?,
+                            format!("{}@{}", file.name, revision_id),
+                        ),
+                        None => (
+                            self.storage.get_file_content(file_id).await?,
+                            file.name.clone(),
+                        ),
+                    };
+                    Ok::<_, StorageError>((old.body, new.body, new_name))
+                };
+                match contents.await {
+                    Ok((old, new, new_name)) => {
+                        let old_name = format!("{}@{}", file.name, from_revision_id);
+                        let context = match context {
+                            0 => diff::DEFAULT_CONTEXT,
+                            context => context as usize,
+                        };
+                        let response = run_blocking(move || {
+                            diff::diff(&old, &new, &old_name, &new_name, context)
+                        })
+                        .await?;
+                        Ok(Response::new(response))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to diff file {}: {}", file_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            DiffRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!("Got diff file request for {} without client Id", file_id);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn move_file(
+        &self,
+        request: Request<MoveFileRequest>,
+    ) -> Result<Response<MoveFileResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            MoveFileRequest {
+                client_id: Some(client_id),
+                file_id,
+                collection_id,
+                name,
+                overwrite,
+            } => {
+                println!(
+                    "Move file request from: {:?} for file: {:?} to: {:?} in collection: {:?}",
+                    &client_id.uuid, file_id, name, collection_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                let current = self.require_file(&caller, file_id, Permission::Write)?;
+                self.require(&caller, collection_id, Permission::Write)?;
+                let name = if name.is_empty() { current.name } else { name };
+                match self
+                    .storage
+                    .move_file(file_id, collection_id, &name, overwrite)
+                    .await
+                {
+                    Ok((file, collection)) => {
+                        self.publish_file_change(
+                            Kind::FileMoved,
+                            &file,
+                            Some(current.collection_id),
+                        );
+                        Ok(Response::new(MoveFileResponse {
+                            file: Some(file),
+                            collection: Some(self.readable(&caller, collection)),
+                        }))
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to move file {} to collection {}: {}",
+                            file_id, collection_id, err
+                        );
+                        Err(err.into())
+                    }
+                }
+            }
+            MoveFileRequest {
+                client_id: None,
+                file_id,
+                ..
+            } => {
+                let message = format!("Got move file request for {} without client Id", file_id);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn create_collection(
+        &self,
+        request: Request<CreateCollectionRequest>,
+    ) -> Result<Response<CollectionResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            CreateCollectionRequest {
+                client_id: Some(client_id),
+                parent_id,
+                name,
+            } => {
+                println!(
+                    "Create collection request from: {:?} for collection: {:?} in collection: {:?}",
+                    &client_id.uuid, name, parent_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require(&caller, parent_id, Permission::Write)?;
+                match self.storage.create_collection(parent_id, &name).await {
+                    Ok(collection) => {
+                        self.publish_collection_change(
+                            Kind::CollectionCreated,
+                            &collection,
+                            self.lineage(collection.id),
+                            vec![],
+                        );
+                        Ok(Response::new(CollectionResponse {
+                            collections: vec![collection],
+                        }))
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to create collection {:?} in collection {}: {}",
+                            name, parent_id, err
+                        );
+                        Err(err.into())
+                    }
+                }
+            }
+            CreateCollectionRequest {
+                client_id: None,
+                name,
+                ..
+            } => {
+                let message = format!(
+                    "Got create collection request for {:?} without client Id",
+                    name
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn rename_collection(
+        &self,
+        request: Request<RenameCollectionRequest>,
+    ) -> Result<Response<CollectionResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            RenameCollectionRequest {
+                client_id: Some(client_id),
+                collection_id,
+                name,
+            } => {
+                println!(
+                    "Rename collection request from: {:?} for collection: {:?} to: {:?}",
+                    &client_id.uuid, collection_id, name
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require(&caller, collection_id, Permission::Write)?;
+                match self.storage.rename_collection(collection_id, &name).await {
+                    Ok(collection) => {
+                        self.publish_collection_change(
+                            Kind::CollectionRenamed,
+                            &collection,
+                            self.lineage(collection.id),
+                            vec![],
+                        );
+                        Ok(Response::new(CollectionResponse {
+                            collections: vec![self.readable(&caller, collection)],
+                        }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to rename collection {}: {}", collection_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            RenameCollectionRequest {
+                client_id: None,
+                collection_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got rename collection request for {} without client Id",
+                    collection_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn move_collection(
+        &self,
+        request: Request<MoveCollectionRequest>,
+    ) -> Result<Response<CollectionResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            MoveCollectionRequest {
+                client_id: Some(client_id),
+                collection_id,
+                parent_id,
+            } => {
+                println!(
+                    "Move collection request from: {:?} for collection: {:?} to: {:?}",
+                    &client_id.uuid, collection_id, parent_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                // moving changes what the collections in it inherit
+                self.require_all(
+                    &caller,
+                    &self.storage.get_collection(collection_id)?,
+                    Permission::Write,
+                )?;
+                self.require(&caller, parent_id, Permission::Write)?;
+                let previous_lineage = self.lineage(collection_id);
+                match self.storage.move_collection(collection_id, parent_id).await {
+                    Ok(collection) => {
+                        self.publish_collection_change(
+                            Kind::CollectionMoved,
+                            &collection,
+                            self.lineage(collection.id),
+                            previous_lineage,
+                        );
+                        Ok(Response::new(CollectionResponse {
+                            collections: vec![self.readable(&caller, collection)],
+                        }))
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "Failed to move collection {} to {}: {}",
+                            collection_id, parent_id, err
+                        );
+                        Err(err.into())
+                    }
+                }
+            }
+            MoveCollectionRequest {
+                client_id: None,
+                collection_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got move collection request for {} without client Id",
+                    collection_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn delete_collection(
+        &self,
+        request: Request<DeleteCollectionRequest>,
+    ) -> Result<Response<CollectionResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            DeleteCollectionRequest {
+                client_id: Some(client_id),
+                collection_id,
+                recursive,
+            } => {
+                println!(
+                    "Delete collection request from: {:?} for collection: {:?} (recursive: {})",
+                    &client_id.uuid, collection_id, recursive
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_all(
+                    &caller,
+                    &self.storage.get_collection(collection_id)?,
+                    Permission::Write,
+                )?;
+                let lineage = self.lineage(collection_id);
+                match self
+                    .storage
+                    .delete_collection(collection_id, recursive)
+                    .await
+                {
+                    Ok(collection) => {
+                        // the access control lists go once the collection is
+                        // purged from the trash
+                        self.publish_collection_change(
+                            Kind::CollectionDeleted,
+                            &collection,
+                            lineage,
+                            vec![],
+                        );
+                        Ok(Response::new(CollectionResponse {
+                            collections: vec![collection],
+                        }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to delete collection {}: {}", collection_id, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            DeleteCollectionRequest {
+                client_id: None,
+                collection_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got delete collection request for {} without client Id",
+                    collection_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn list_trash(
+        &self,
+        request: Request<ClientId>,
+    ) -> Result<Response<TrashResponse>, Status> {
+        let caller = authenticated_client(&request);
+        let client_id = request.into_inner();
+        println!("List trash request from: {:?}", &client_id.uuid);
+        let caller = self.check_client(caller, &client_id)?;
+        let trash = match self.storage.list_trash().await {
+            Ok(trash) => trash,
+            Err(err) => {
+                eprintln!("Failed to list the trash: {}", err);
+                return Err(err.into());
+            }
+        };
+        let items = trash
+            .into_iter()
+            .filter_map(|mut trashed| {
+                match trashed.item.take()? {
+                    trash_item::Item::Collection(collection) => {
+                        let collection =
+                            readable_trashed(&self.acl, &caller, &trashed.lineage, collection)?;
+                        trashed.item = Some(trash_item::Item::Collection(collection));
+                    }
+                    trash_item::Item::File(file) => {
+                        if self.acl.permission(&caller, &trashed.lineage) < Permission::Read {
+                            return None;
+                        }
+                        trashed.item = Some(trash_item::Item::File(file));
+                    }
+                }
+                Some(trashed)
+            })
+            .collect();
+        Ok(Response::new(TrashResponse { items }))
+    }
+
+    async fn restore_trash(
+        &self,
+        request: Request<RestoreTrashRequest>,
+    ) -> Result<Response<RestoreTrashResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            RestoreTrashRequest {
+                client_id: Some(client_id),
+                item: Some(item),
+                to,
+            } => {
+                let item = match item {
+                    restore_trash_request::Item::CollectionId(id) => ItemId::Collection(id),
+                    restore_trash_request::Item::FileId(id) => ItemId::File(id),
+                };
+                let parent_id = to.map(|restore_trash_request::To::ParentId(id)| id);
+                println!(
+                    "Restore trash request from: {:?} for {} (to: {:?})",
+                    &client_id.uuid, item, parent_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                let trashed = self
+                    .require_trashed(&caller, item, Permission::Write)
+                    .await?;
+                if let Some(parent) = parent_id.or(trashed.lineage.last().copied()) {
+                    self.require(&caller, parent, Permission::Write)?;
+                }
+                match self.storage.restore_trash(item, parent_id).await {
+                    Ok(trash_item::Item::File(file)) => {
+                        self.publish_file_change(Kind::FileCreated, &file, None);
+                        Ok(Response::new(RestoreTrashResponse {
+                            item: Some(restore_trash_response::Item::File(file)),
+                        }))
+                    }
+                    Ok(trash_item::Item::Collection(collection)) => {
+                        self.publish_collection_change(
+                            Kind::CollectionCreated,
+                            &collection,
+                            self.lineage(collection.id),
+                            vec![],
+                        );
+                        Ok(Response::new(RestoreTrashResponse {
+                            item: Some(restore_trash_response::Item::Collection(collection)),
+                        }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to restore {} from the trash: {}", item, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            RestoreTrashRequest {
+                client_id: Some(client_id),
+                item: None,
+                ..
+            } => {
+                let message = format!(
+                    "Got restore trash request from {:?} without collection or file id",
+                    client_id.uuid
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+            RestoreTrashRequest {
+                client_id: None, ..
+            } => {
+                let message = "Got restore trash request without client Id".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn purge_trash(
+        &self,
+        request: Request<TrashRequest>,
+    ) -> Result<Response<TrashItemResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            TrashRequest {
+                client_id: Some(client_id),
+                item: Some(item),
+            } => {
+                let item = match item {
+                    trash_request::Item::CollectionId(id) => ItemId::Collection(id),
+                    trash_request::Item::FileId(id) => ItemId::File(id),
+                };
+                println!(
+                    "Purge trash request from: {:?} for {}",
+                    &client_id.uuid, item
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_trashed(&caller, item, Permission::Write)
+                    .await?;
+                match self.storage.purge_trash(item).await {
+                    Ok(purged) => {
+                        if let Some(trash_item::Item::Collection(collection)) = &purged.item {
+                            self.acl.remove(collection_ids(collection));
+                        }
+                        Ok(Response::new(TrashItemResponse { item: Some(purged) }))
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to purge {} from the trash: {}", item, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            TrashRequest {
+                client_id: Some(client_id),
+                item: None,
+            } => {
+                let message = format!(
+                    "Got purge trash request from {:?} without collection or file id",
+                    client_id.uuid
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+            TrashRequest {
+                client_id: None, ..
+            } => {
+                let message = "Got purge trash request without client Id".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            SearchRequest {
+                client_id: Some(client_id),
+                query,
+                limit,
+            } => {
+                println!("Search request from: {:?} for {:?}", &client_id.uuid, query);
+                let caller = self.check_client(caller, &client_id)?;
+                let limit = match limit {
+                    0 => DEFAULT_SEARCH_LIMIT,
+                    limit => (limit as usize).min(MAX_SEARCH_LIMIT),
+                };
+                let storage = self.storage.clone();
+                let acl = Arc::clone(&self.acl);
+                let visible = move |file: &File| {
+                    storage
+                        .collection_lineage(file.collection_id)
+                        .is_ok_and(|lineage| acl.permission(&caller, &lineage) >= Permission::Read)
+                };
+                match self.storage.search(query.clone(), limit, visible).await {
+                    Ok(hits) => Ok(Response::new(SearchResponse { hits })),
+                    Err(err) => {
+                        eprintln!("Failed to search for {:?}: {}", query, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            SearchRequest {
+                client_id: None, ..
+            } => {
+                let message = "Got search request without client Id".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn resolve_path(
+        &self,
+        request: Request<ResolvePathRequest>,
+    ) -> Result<Response<PathResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            ResolvePathRequest {
+                client_id: Some(client_id),
+                path,
+            } => {
+                println!(
+                    "Resolve path request from: {:?} for path: {:?}",
+                    &client_id.uuid, path
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                let item = match self.storage.resolve_path(&path) {
+                    Ok(item) => item,
+                    Err(err) => {
+                        eprintln!("Failed to resolve path {:?}: {}", path, err);
+                        return Err(err.into());
+                    }
+                };
+                self.require_item(&caller, item, Permission::Read)?;
+                // sends the path back the way getPath would
+                let path = self.storage.get_path(item)?;
+                Ok(Response::new(path_response(path, item)))
+            }
+            ResolvePathRequest {
+                client_id: None,
+                path,
+            } => {
+                let message = format!("Got resolve path request for {:?} without client Id", path);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn get_path(
+        &self,
+        request: Request<GetPathRequest>,
+    ) -> Result<Response<PathResponse>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            GetPathRequest {
+                client_id: Some(client_id),
+                item: Some(item),
+            } => {
+                let item = match item {
+                    get_path_request::Item::CollectionId(id) => ItemId::Collection(id),
+                    get_path_request::Item::FileId(id) => ItemId::File(id),
+                };
+                println!("Get path request from: {:?} for {}", &client_id.uuid, item);
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_item(&caller, item, Permission::Read)?;
+                match self.storage.get_path(item) {
+                    Ok(path) => Ok(Response::new(path_response(path, item))),
+                    Err(err) => {
+                        eprintln!("Failed to get path of {}: {}", item, err);
+                        Err(err.into())
+                    }
+                }
+            }
+            GetPathRequest {
+                client_id: Some(client_id),
+                item: None,
+            } => {
+                let message = format!(
+                    "Got get path request from {:?} without collection or file id",
+                    client_id.uuid
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+            GetPathRequest {
+                client_id: None, ..
+            } => {
+                let message = "Got get path request without client Id".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::watchStream>, Status> {
+        let caller = authenticated_client(&request);
+        match request.into_inner() {
+            WatchRequest {
+                client_id: Some(client_id),
+                collection_ids,
+            } => {
+                println!(
+                    "Watch request from: {:?} for collections: {:?}",
+                    &client_id.uuid, collection_ids
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                for collection_id in &collection_ids {
+                    self.require(&caller, *collection_id, Permission::Read)?;
+                }
+                let storage = self.storage.clone();
+                let acl = Arc::clone(&self.acl);
+                let mut service_changes = self.changes.subscribe();
+                let mut storage_changes = self.storage.changes();
+                let (tx, rx) = mpsc::channel(16);
+                tokio::spawn(async move {
+                    loop {
+                        let change = tokio::select! {
+                            change = service_changes.recv() => change,
+                            change = recv_change(&mut storage_changes) => match change {
+                                Err(RecvError::Closed) => {
+                                    storage_changes = None;
+                                    continue;
+                                }
+                                change => change,
+                            },
+                            // client is gone
+                            _ = tx.closed() => break,
+                        };
+                        let change = match change {
+                            Ok(change) => change,
+                            Err(RecvError::Lagged(missed)) => {
+                                let message = format!(
+                                    "Missed {} changes, get the collections again and restart watching",
+                                    missed
+                                );
+                                eprintln!("Watcher {:?} fell behind: {}", &client_id.uuid, message);
+                                let _ = tx
+                                    .send(Err(Status::new(tonic::Code::Aborted, message)))
+                                    .await;
+                                break;
+                            }
+                            Err(RecvError::Closed) => break,
+                        };
+                        let in_scope = collection_ids.is_empty()
+                            || change
+                                .collections
+                                .iter()
+                                .any(|id| collection_ids.contains(id));
+                        if !in_scope {
+                            continue;
+                        }
+                        let Some(event) = readable_change(&storage, &acl, &caller, change) else {
+                            continue;
+                        };
+                        if tx.send(Ok(event)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok(Response::new(ReceiverStream::new(rx)))
+            }
+            WatchRequest {
+                client_id: None, ..
+            } => {
+                let message = "Got watch request without client Id".to_string();
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn get_acl(
+        &self,
+        request: Request<CollectionRequest>,
+    ) -> Result<Response<AclResponse>, Status> {
+        let caller = authenticated_client(&request);
+        let is_admin = self.auth.is_admin(request.metadata());
+        match request.into_inner() {
+            CollectionRequest {
+                client_id: Some(client_id),
+                collection_id,
+            } => {
+                println!(
+                    "Get ACL request from: {:?} for collection: {:?}",
+                    &client_id.uuid, collection_id
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                self.require_acl_admin(&caller, collection_id, is_admin)?;
+                Ok(Response::new(AclResponse {
+                    collection_id,
+                    entries: self.acl.get(collection_id),
+                }))
+            }
+            CollectionRequest {
+                client_id: None,
+                collection_id,
+            } => {
+                let message = format!("Got ACL request for {} without client Id", collection_id);
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+
+    async fn set_acl(
+        &self,
+        request: Request<SetAclRequest>,
+    ) -> Result<Response<AclResponse>, Status> {
+        let caller = authenticated_client(&request);
+        let is_admin = self.auth.is_admin(request.metadata());
+        match request.into_inner() {
+            SetAclRequest {
+                client_id: Some(client_id),
+                collection_id,
+                entries,
+            } => {
+                println!(
+                    "Set ACL request from: {:?} for collection: {:?} with entries: {:?}",
+                    &client_id.uuid, collection_id, entries
+                );
+                let caller = self.check_client(caller, &client_id)?;
+                let _writing = self.acl_writes.lock().await;
+                self.require_acl_admin(&caller, collection_id, is_admin)?;
+                if let Err(message) = acl::validate(&entries) {
+                    eprintln!(
+                        "Failed to set ACL of collection {}: {}",
+                        collection_id, message
+                    );
+                    return Err(Status::new(tonic::Code::InvalidArgument, message));
+                }
+                if let Err(err) = self.storage.set_acl(collection_id, entries.clone()).await {
+                    eprintln!("Failed to set ACL of collection {}: {}", collection_id, err);
+                    return Err(err.into());
+                }
+                self.acl
+                    .set(collection_id, entries)
+                    .expect("entries were validated");
+                Ok(Response::new(AclResponse {
+                    collection_id,
+                    entries: self.acl.get(collection_id),
+                }))
+            }
+            SetAclRequest {
+                client_id: None,
+                collection_id,
+                ..
+            } => {
+                let message = format!(
+                    "Got set ACL request for {} without client Id",
+                    collection_id
+                );
+                eprintln!("{}", message);
+                Err(Status::new(tonic::Code::InvalidArgument, message))
+            }
+        }
+    }
+}
+
+async fn serve<S: Storage + Send + Sync + 'static>(
+    storage: S,
+    options: &Options,
+    admin_secret: Option<String>,
+    tls: Option<ServerTlsConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut oxygen_service = match admin_secret {
+        Some(secret) => OxygenService::new(storage).with_admin_secret(&secret),
+        None => {
+            println!("No admin secret is set, any client can register and nobody is an admin");
+            OxygenService::new(storage)
+        }
+    }
+    .with_trash_retention(options.trash_retention);
+    if let Some(timeout) = options.session_timeout {
+        oxygen_service = oxygen_service.with_session_timeout(timeout);
+    }
+    if let Some(max_upload_size) = options.max_upload_size {
+        oxygen_service = oxygen_service.with_max_upload_size(max_upload_size);
+    }
+    let registry = Arc::clone(&oxygen_service.registry);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(registry.timeout());
+        loop {
+            interval.tick().await;
+            for uuid in registry.evict_stale() {
+                println!("Evicted stale session of: {:?}", uuid);
+            }
+        }
+    });
+    if let Some(retention) = oxygen_service.trash_retention {
+        let storage = oxygen_service.storage.clone();
+        let acl = Arc::clone(&oxygen_service.acl);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                crate::purge_expired_trash(&storage, &acl, retention).await;
+            }
+        });
+    }
+    let mut builder = tonic::transport::Server::builder();
+    match tls {
+        Some(tls) => builder = builder.tls_config(tls)?,
+        None => println!("TLS is not configured, serving plaintext"),
+    }
+    builder
+        .add_service(oxygen_service.into_server())
+        .serve(options.addr)
+        .await?;
+    Ok(())
+}
+
+/// See [`options::USAGE`]
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", options::USAGE);
+        return Ok(());
+    }
+    let mut options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, options::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let admin_secret = std::env::var(ADMIN_SECRET_VAR).ok();
+    let tls = options.tls.as_ref().map(TlsFiles::load).transpose()?;
+    match (options.root.take(), options.db.take()) {
+        (Some(root), _) => {
+            let storage = FilesystemStorage::new(root)?.watch()?;
+            serve(storage, &options, admin_secret, tls).await
+        }
+        (None, Some(db)) => {
+            let storage = SqliteStorage::open(db)?;
+            serve(storage, &options, admin_secret, tls).await
+        }
+        (None, None) => {
+            let storage = HardCodedStorage::new();
+            serve(storage, &options, admin_secret, tls).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use prost::Message;
+    use tonic::{
+        codegen::InterceptedService,
+        metadata::{Ascii, MetadataValue},
+        service::Interceptor,
+        transport::{Certificate, Channel, ClientTlsConfig, Identity},
+        Status,
+    };
+
+    use crate::acl::AccessControl;
+    use crate::auth::{AuthenticatedClient, ADMIN_SECRET_METADATA_KEY, TOKEN_METADATA_KEY};
+    use crate::collection::{
+        async_storage::AsyncStorage, HardCodedStorage, ItemId, Storage, StorageError,
+        StorageResult, User,
+    };
+    use crate::options::TlsFiles;
+    use crate::oxygen::{
+        acl_entry::Principal, change_event::Kind, diff_line, diff_request, get_path_request,
+        oxygen_client::OxygenClient, path_response, restore_trash_request, restore_trash_response,
+        trash_item, trash_request, AclEntry, ClientId, Collection, CollectionRequest,
+        CreateCollectionRequest, CreateFileRequest, CreateUserRequest, DeleteCollectionRequest,
+        DeleteFileRequest, DiffRequest, DownloadFileRequest, File, FileContent, FileRequest,
+        GetPathRequest, LoginRequest, MoveCollectionRequest, MoveFileRequest, Permission,
+        RegisterRequest, RenameCollectionRequest, ResolvePathRequest, RestoreTrashRequest,
+        Revision, RevisionRequest, SearchRequest, SetAclRequest, TrashItem, TrashRequest,
+        UpdateFileRequest, UploadFileChunk, WatchRequest,
+    };
+
+    /// Connects to the server listening on `port` and registers a new client
+    /// with it. Requests made with the returned client carry its session token.
+    async fn registered_client(
+        port: u16,
+    ) -> (OxygenClient<InterceptedService<Channel, Bearer>>, String) {
+        let channel = Channel::from_shared(format!("http://[::1]:{}", port))
+            .expect("Hardcoded URI must be valid")
+            .connect()
+            .await
+            .expect("failed to create client");
+        let uuid = uuid::Uuid::new_v4().to_string();
+        let token = OxygenClient::new(channel.clone())
+            .register(as_admin(RegisterRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                ..Default::default()
+            }))
+            .await
+            .expect("failed to register with server")
+            .into_inner()
+            .token;
+        let client = OxygenClient::with_interceptor(channel, Bearer::new(&token));
+        (client, uuid)
+    }
+
+    /// Request carrying the admin secret of the test servers that have one
+    fn as_admin<T>(message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert(
+            ADMIN_SECRET_METADATA_KEY,
+            "s3cret".parse().expect("secret must be valid metadata"),
+        );
+        request
+    }
+
+    /// Sends a session token along with every request
+    struct Bearer(MetadataValue<Ascii>);
+
+    impl Bearer {
+        fn new(token: &str) -> Self {
+            let token = format!("Bearer {}", token)
+                .parse()
+                .expect("tokens must be valid metadata");
+            Self(token)
+        }
+    }
+
+    impl Interceptor for Bearer {
+        fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+            request
+                .metadata_mut()
+                .insert(TOKEN_METADATA_KEY, self.0.clone());
+            Ok(request)
+        }
+    }
+
+    /// Storage with a single collection holding a single file
+    struct MockStorage;
+
+    impl Storage for MockStorage {
+        fn get_collection_all(&self) -> Vec<Collection> {
+            vec![self.get_collection(7).unwrap()]
+        }
+
+        fn get_collection(&self, id: u64) -> StorageResult<Collection> {
+            match id {
+                7 => Ok(Collection {
+                    name: "mock".to_string(),
+                    id,
+                    child_collections: vec![],
+                    files: vec![self.get_file(42)?],
+                }),
+                _ => Err(StorageError::NotFound(format!(
+                    "collection with id: {}",
+                    id
+                ))),
+            }
+        }
+
+        fn get_file(&self, id: u64) -> StorageResult<File> {
+            let name = match id {
+                42 => "mock.md",
+                // not listed in the collection, only there to fail reading its
+                // content
+                13 => "corrupt.md",
+                _ => return Err(StorageError::NotFound(format!("file with id: {}", id))),
+            };
+            Ok(File {
+                name: name.to_string(),
+                id,
+                collection_id: 7,
+                ..Default::default()
+            })
+        }
+
+        fn get_file_content(&self, id: u64) -> StorageResult<FileContent> {
+            match id {
+                // content that can't be read back
+                13 => Err(StorageError::Corrupt("file with id: 13".to_string())),
+                _ => self.get_file(id).map(|_| FileContent {
+                    body: b"mock content".to_vec(),
+                }),
+            }
+        }
+
+        fn collection_lineage(&self, id: u64) -> StorageResult<Vec<u64>> {
+            self.get_collection(id)
+                .map(|collection| vec![collection.id])
+        }
+
+        fn create_file(&self, _: u64, _: &str, _: Vec<u8>, _: &str) -> StorageResult<File> {
+            read_only()
+        }
+
+        fn update_file_content(
+            &self,
+            _: u64,
+            _: Vec<u8>,
+            _: &str,
+            _: Option<&str>,
+        ) -> StorageResult<File> {
+            read_only()
+        }
+
+        fn list_revisions(&self, id: u64) -> StorageResult<Vec<Revision>> {
+            self.get_file(id).map(|_| vec![])
+        }
+
+        fn get_revision_content(&self, id: u64, revision: u64) -> StorageResult<FileContent> {
+            self.get_file(id)?;
+            Err(StorageError::NotFound(format!(
+                "revision {} of file with id: {}",
+                revision, id
+            )))
+        }
+
+        fn delete_file(&self, _: u64, _: Option<&str>) -> StorageResult<File> {
+            read_only()
+        }
+
+        fn move_file(&self, _: u64, _: u64, _: &str, _: bool) -> StorageResult<(File, Collection)> {
+            read_only()
+        }
+
+        fn create_collection(&self, _: u64, _: &str) -> StorageResult<Collection> {
+            read_only()
+        }
+
+        fn rename_collection(&self, _: u64, _: &str) -> StorageResult<Collection> {
+            read_only()
+        }
+
+        fn move_collection(&self, _: u64, _: u64) -> StorageResult<Collection> {
+            read_only()
+        }
+
+        fn delete_collection(&self, _: u64, _: bool) -> StorageResult<Collection> {
+            read_only()
+        }
+
+        fn list_trash(&self) -> StorageResult<Vec<TrashItem>> {
+            Ok(vec![])
+        }
+
+        fn restore_trash(&self, item: ItemId, _: Option<u64>) -> StorageResult<trash_item::Item> {
+            Err(StorageError::NotFound(format!("{} in the trash", item)))
+        }
+
+        fn purge_trash(&self, item: ItemId) -> StorageResult<TrashItem> {
+            Err(StorageError::NotFound(format!("{} in the trash", item)))
+        }
+
+        fn resolve_path(&self, path: &str) -> StorageResult<ItemId> {
+            match path {
+                "" => Ok(ItemId::Collection(7)),
+                "mock.md" => Ok(ItemId::File(42)),
+                _ => Err(StorageError::NotFound(format!("path: {:?}", path))),
+            }
+        }
+
+        fn get_path(&self, item: ItemId) -> StorageResult<String> {
+            match item {
+                ItemId::Collection(7) => Ok(String::new()),
+                ItemId::File(42) => Ok("mock.md".to_string()),
+                _ => Err(StorageError::NotFound(item.to_string())),
+            }
+        }
+
+        fn get_user(&self, username: &str) -> StorageResult<User> {
+            Err(StorageError::NotFound(format!("user: {}", username)))
+        }
+
+        fn create_user(&self, _: User) -> StorageResult<User> {
+            read_only()
+        }
+
+        fn get_acls(&self) -> StorageResult<HashMap<u64, Vec<AclEntry>>> {
+            Ok(HashMap::new())
+        }
+
+        fn set_acl(&self, _: u64, _: Vec<AclEntry>) -> StorageResult<()> {
+            read_only()
+        }
+    }
+
+    fn read_only<T>() -> StorageResult<T> {
+        Err(StorageError::PermissionDenied(
+            "mock storage is read only".to_string(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn can_initialize_server() {
+        let port = 50051;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_register_with_server() {
+        let port = 50052;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let server_id = oxygen_service.id;
+        let registry = Arc::clone(&oxygen_service.registry);
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
+                .await
+                .expect("failed to create client");
+            let uuid = uuid::Uuid::new_v4().to_string();
+            let metadata = HashMap::from([("name".to_string(), "test".to_string())]);
+            let reg_request = tonic::Request::new(RegisterRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                metadata: metadata.clone(),
+            });
+            let res = client
+                .register(reg_request)
+                .await
+                .expect("failed to get response from server")
+                .into_inner();
+            assert_eq!(res.client_id, uuid);
+            assert_eq!(res.server_id, server_id.to_string());
+            assert_eq!(res.session_timeout, 5 * 60 * 1000);
+            assert!(res.token.starts_with(&uuid));
+            let session = registry.unregister(&uuid).expect("expected a session");
+            assert_eq!(session.metadata, metadata);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_get_all_collections() {
+        let port = 50053;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            let collection_res = client
+                .get_all_collections(tonic::Request::new(crate::oxygen::ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to get get all collections")
+                .into_inner();
+            // XXX: hardcoded collection
+            assert!(collection_res.collections.len() == 5);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+    #[tokio::test]
+    async fn client_can_get_collection_by_id() {
+        let port = 50054;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            for id in 0..5 {
+                let collection_request = CollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: id,
+                };
+                let _ = client
+                    .get_collection(tonic::Request::new(collection_request))
+                    .await
+                    .expect("failed to get get collection");
+            }
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn server_handle_invalid_collection_ids() {
+        let port = 50055;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            for id in [100, 10000000] {
+                let collection_request = CollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: id,
+                };
+                let _ = client
+                    .get_collection(tonic::Request::new(collection_request))
+                    .await
+                    .expect_err("server should fail to get invalid collection ids");
+            }
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_get_file_by_id() {
+        let port = 50056;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            for id in 0..4 {
+                let file_request = FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: id,
+                };
+                let _ = client
+                    .get_file(tonic::Request::new(file_request))
+                    .await
+                    .expect("failed to get file by id");
+            }
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_get_file_content_by_id() {
+        let port = 50057;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            for id in 0..4 {
+                let file_request = FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: id,
+                };
+                let content = client
+                    .get_file_content(tonic::Request::new(file_request.clone()))
+                    .await
+                    .expect("failed to get file content")
+                    .into_inner();
+                let actual =
+                    std::str::from_utf8(&content.body).expect("expect body to be valid utf-8");
+                // XXX: hardcoded content
+                let file = client
+                    .get_file(tonic::Request::new(file_request))
+                    .await
+                    .expect("failed to get file details")
+                    .into_inner()
+                    .file
+                    .expect("unexpected");
+                let expected = format!("# {} content", file.name);
+                assert_eq!(actual, expected);
+            }
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn server_handle_invalid_file_ids() {
+        let port = 50058;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            for id in [100, 10000000] {
+                let file_request = FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: id,
+                };
+                let _ = client
+                    .get_file(tonic::Request::new(file_request))
+                    .await
+                    .expect_err("server should fail to get invalid file ids");
+            }
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn server_can_use_any_storage() {
+        let port = 50059;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::new(MockStorage);
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            let collection_res = client
+                .get_all_collections(tonic::Request::new(crate::oxygen::ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to get get all collections")
+                .into_inner();
+            assert_eq!(collection_res.collections.len(), 1);
+            assert_eq!(collection_res.collections[0].files[0].id, 42);
+            let content = client
+                .get_file_content(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId { uuid }),
+                    file_id: 42,
+                }))
+                .await
+                .expect("failed to get file content")
+                .into_inner();
+            assert_eq!(content.body, b"mock content");
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn server_reports_storage_errors_with_matching_status() {
+        let port = 50060;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::new(MockStorage);
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            let status = client
+                .get_collection(tonic::Request::new(CollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 100,
+                }))
+                .await
+                .expect_err("server should fail to get invalid collection ids");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+            let status = client
+                .get_file_content(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId { uuid }),
+                    file_id: 13,
+                }))
+                .await
+                .expect_err("server should fail to get corrupt file content");
+            assert_eq!(status.code(), tonic::Code::DataLoss);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_create_update_and_delete_files() {
+        let port = 50061;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            let file = client
+                .create_file(tonic::Request::new(CreateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                    name: "new note.md".to_string(),
+                    body: b"# new note".to_vec(),
+                }))
+                .await
+                .expect("failed to create file")
+                .into_inner()
+                .file
+                .expect("expected created file");
+            let collection = client
+                .get_collection(tonic::Request::new(CollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                }))
+                .await
+                .expect("failed to get collection")
+                .into_inner();
+            assert!(collection.collections[0].files.contains(&file));
+
+            let status = client
+                .create_file(tonic::Request::new(CreateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                    name: "new note.md".to_string(),
+                    body: vec![],
+                }))
+                .await
+                .expect_err("server should not overwrite existing files");
+            assert_eq!(status.code(), tonic::Code::Aborted);
+
+            let file_request = FileRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                file_id: file.id,
+            };
+            let updated = client
+                .update_file_content(tonic::Request::new(UpdateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: file.id,
+                    body: b"# updated note".to_vec(),
+                    if_match: String::new(),
+                }))
+                .await
+                .expect("failed to update file content")
+                .into_inner()
+                .file
+                .expect("expected updated file");
+            assert_eq!(updated.size, 14);
+            assert_ne!(updated.sha256, file.sha256);
+            let content = client
+                .get_file_content(tonic::Request::new(file_request.clone()))
+                .await
+                .expect("failed to get file content")
+                .into_inner();
+            assert_eq!(content.body, b"# updated note");
+
+            let deleted = client
+                .delete_file(tonic::Request::new(DeleteFileRequest {
+                    client_id: file_request.client_id.clone(),
+                    file_id: file.id,
+                    if_match: updated.etag.clone(),
+                }))
+                .await
+                .expect("failed to delete file")
+                .into_inner()
+                .file;
+            assert_eq!(deleted, Some(updated));
+            let status = client
+                .get_file(tonic::Request::new(file_request))
+                .await
+                .expect_err("deleted file should not exist");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_manage_collections() {
+        let port = 50062;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            let created = client
+                .create_collection(tonic::Request::new(CreateCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    parent_id: 4,
+                    name: "meetings".to_string(),
+                }))
+                .await
+                .expect("failed to create collection")
+                .into_inner()
+                .collections
+                .remove(0);
+            let renamed = client
+                .rename_collection(tonic::Request::new(RenameCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: created.id,
+                    name: "meetings 2026".to_string(),
+                }))
+                .await
+                .expect("failed to rename collection")
+                .into_inner()
+                .collections
+                .remove(0);
+            assert_eq!(renamed.id, created.id);
+            assert_eq!(renamed.name, "meetings 2026");
+
+            let status = client
+                .move_collection(tonic::Request::new(MoveCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 4,
+                    parent_id: created.id,
+                }))
+                .await
+                .expect_err("server should not move a collection into its descendant");
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            let moved = client
+                .move_collection(tonic::Request::new(MoveCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                    parent_id: created.id,
+                }))
+                .await
+                .expect("failed to move collection")
+                .into_inner()
+                .collections
+                .remove(0);
+            assert_eq!(moved.id, 2);
+
+            let status = client
+                .delete_collection(tonic::Request::new(DeleteCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: created.id,
+                    recursive: false,
+                }))
+                .await
+                .expect_err("server should not delete non empty collections");
+            assert_eq!(status.code(), tonic::Code::Aborted);
+            let deleted = client
+                .delete_collection(tonic::Request::new(DeleteCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: created.id,
+                    recursive: true,
+                }))
+                .await
+                .expect("failed to delete collection")
+                .into_inner()
+                .collections
+                .remove(0);
+            assert_eq!(deleted.child_collections, vec![moved]);
+            let status = client
+                .get_collection(tonic::Request::new(CollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                }))
+                .await
+                .expect_err("deleted collection should not exist");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_move_files() {
+        let port = 50063;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            // f_1.md from collection 4 to collection 2, keeping its name
+            let moved = client
+                .move_file(tonic::Request::new(MoveFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 3,
+                    collection_id: 2,
+                    name: String::new(),
+                    overwrite: false,
+                }))
+                .await
+                .expect("failed to move file")
+                .into_inner();
+            let file = moved.file.expect("expected moved file");
+            assert_eq!(file.id, 3);
+            assert_eq!(file.name, "f_1.md");
+            assert_eq!(moved.collection.expect("expected collection").id, 2);
+
+            // rename f_1.md to f_4.md which already exists in collection 2
+            let status = client
+                .move_file(tonic::Request::new(MoveFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 3,
+                    collection_id: 2,
+                    name: "f_4.md".to_string(),
+                    overwrite: false,
+                }))
+                .await
+                .expect_err("server should not overwrite files unless asked to");
+            assert_eq!(status.code(), tonic::Code::Aborted);
+            let moved = client
+                .move_file(tonic::Request::new(MoveFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 3,
+                    collection_id: 2,
+                    name: "f_4.md".to_string(),
+                    overwrite: true,
+                }))
+                .await
+                .expect("failed to overwrite file")
+                .into_inner();
+            let file = moved.file.expect("expected moved file");
+            assert_eq!(file.id, 3);
+            assert_eq!(file.name, "f_4.md");
+            let content = client
+                .get_file_content(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId { uuid }),
+                    file_id: 3,
+                }))
+                .await
+                .expect("failed to get file content")
+                .into_inner();
+            assert_eq!(content.body, b"# f_1.md content");
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_download_files_in_chunks() {
+        let port = 50064;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default().with_chunk_size(4);
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded content
+            let download = |offset, length, chunk_size| DownloadFileRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                file_id: 3,
+                offset,
+                length,
+                chunk_size,
+            };
+            let mut chunks = client
+                .download_file(tonic::Request::new(download(0, 0, 0)))
+                .await
+                .expect("failed to download file")
+                .into_inner();
+            let mut body = vec![];
+            while let Some(chunk) = chunks.message().await.expect("failed to get chunk") {
+                assert_eq!(chunk.offset, body.len() as u64);
+                assert!(chunk.data.len() <= 4);
+                body.extend(chunk.data);
+            }
+            assert_eq!(body, b"# f_1.md content");
+
+            let mut chunks = client
+                .download_file(tonic::Request::new(download(2, 6, 5)))
+                .await
+                .expect("failed to download file range")
+                .into_inner();
+            let mut body = vec![];
+            while let Some(chunk) = chunks.message().await.expect("failed to get chunk") {
+                body.extend(chunk.data);
+            }
+            assert_eq!(body, b"f_1.md");
+
+            let status = client
+                .download_file(tonic::Request::new(DownloadFileRequest {
+                    file_id: 100,
+                    ..download(0, 0, 0)
+                }))
+                .await
+                .expect_err("server should fail to download invalid file ids");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_upload_files_in_chunks() {
+        let port = 50065;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // larger than the default message limit of tonic
+            let body: Vec<u8> = (0..6 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+            let mut chunks: Vec<UploadFileChunk> = body
+                .chunks(1024 * 1024)
+                .map(|data| UploadFileChunk {
+                    client_id: None,
+                    file_id: 0,
+                    data: data.to_vec(),
+                    if_match: String::new(),
+                })
+                .collect();
+            chunks[0].client_id = Some(ClientId {
+                uuid: uuid.to_owned(),
+            });
+            chunks[0].file_id = 2;
+            let _ = client
+                .upload_file(tokio_stream::iter(chunks))
+                .await
+                .expect("failed to upload file");
+
+            let mut chunks = client
+                .download_file(tonic::Request::new(DownloadFileRequest {
+                    client_id: Some(ClientId { uuid }),
+                    file_id: 2,
+                    offset: 0,
+                    length: 0,
+                    chunk_size: 0,
+                }))
+                .await
+                .expect("failed to download file")
+                .into_inner();
+            let mut downloaded = vec![];
+            while let Some(chunk) = chunks.message().await.expect("failed to get chunk") {
+                downloaded.extend(chunk.data);
+            }
+            assert!(downloaded == body);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn uploads_larger_than_the_limit_are_rejected() {
+        let port = 50083;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default().with_max_upload_size(8);
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            let upload = |body: &[u8]| {
+                let mut chunks: Vec<UploadFileChunk> = body
+                    .chunks(4)
+                    .map(|data| UploadFileChunk {
+                        client_id: None,
+                        file_id: 0,
+                        data: data.to_vec(),
+                        if_match: String::new(),
+                    })
+                    .collect();
+                chunks[0].client_id = Some(ClientId {
+                    uuid: uuid.to_owned(),
+                });
+                chunks[0].file_id = 2;
+                tokio_stream::iter(chunks)
+            };
+            let _ = client
+                .upload_file(upload(b"8 bytes!"))
+                .await
+                .expect("uploads up to the limit are accepted");
+            let status = client
+                .upload_file(upload(b"9 bytes!!"))
+                .await
+                .expect_err("uploads over the limit are rejected");
+            assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+            let content = client
+                .get_file_content(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId { uuid }),
+                    file_id: 2,
+                }))
+                .await
+                .expect("failed to get file content")
+                .into_inner();
+            assert_eq!(content.body, b"8 bytes!");
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_watch_changes() {
+        let port = 50066;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            let watch = |collection_ids| WatchRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                collection_ids,
+            };
+            let mut all_changes = client
+                .watch(tonic::Request::new(watch(vec![])))
+                .await
+                .expect("failed to watch all collections")
+                .into_inner();
+            // XXX: hardcoded storage
+            let mut collection_2_changes = client
+                .watch(tonic::Request::new(watch(vec![2])))
+                .await
+                .expect("failed to watch collection 2")
+                .into_inner();
+
+            // collection 1 is not inside collection 2
+            let outside = client
+                .create_file(tonic::Request::new(CreateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 1,
+                    name: "outside.md".to_string(),
+                    body: vec![],
+                }))
+                .await
+                .expect("failed to create file")
+                .into_inner()
+                .file
+                .expect("expected created file");
+            let event = all_changes
+                .message()
+                .await
+                .expect("failed to get change")
+                .expect("expected a change");
+            assert_eq!(event.kind(), Kind::FileCreated);
+            assert_eq!(event.file, Some(outside.clone()));
+            assert_eq!(event.collection_id, 1);
+
+            // moving it into collection 2 is a change in collection 2
+            let _ = client
+                .move_file(tonic::Request::new(MoveFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: outside.id,
+                    collection_id: 2,
+                    name: String::new(),
+                    overwrite: false,
+                }))
+                .await
+                .expect("failed to move file");
+            for changes in [&mut all_changes, &mut collection_2_changes] {
+                let event = changes
+                    .message()
+                    .await
+                    .expect("failed to get change")
+                    .expect("expected a change");
+                assert_eq!(event.kind(), Kind::FileMoved);
+                assert_eq!(event.collection_id, 2);
+                assert_eq!(event.previous_collection_id, 1);
+            }
+
+            let _ = client
+                .delete_collection(tonic::Request::new(DeleteCollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                    recursive: true,
+                }))
+                .await
+                .expect("failed to delete collection");
+            for changes in [&mut all_changes, &mut collection_2_changes] {
+                let event = changes
+                    .message()
+                    .await
+                    .expect("failed to get change")
+                    .expect("expected a change");
+                assert_eq!(event.kind(), Kind::CollectionDeleted);
+                assert_eq!(event.collection.expect("expected collection").id, 2);
+                assert_eq!(event.collection_id, 3);
+            }
+
+            let status = client
+                .watch(tonic::Request::new(watch(vec![100])))
+                .await
+                .expect_err("server should fail to watch invalid collection ids");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn unregistered_clients_are_rejected() {
+        let port = 50067;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
+                .await
+                .expect("failed to create client");
+            let uuid = uuid::Uuid::new_v4().to_string();
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject unregistered clients");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let status = client
+                .get_file(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                }))
+                .await
+                .expect_err("server should reject unregistered clients");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+            let (mut client, uuid) = registered_client(port).await;
+            let res = client
+                .heartbeat(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to send heartbeat")
+                .into_inner();
+            assert_eq!(res.session_timeout, 5 * 60 * 1000);
+            let _ = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("registered clients should be accepted");
+
+            let _ = client
+                .unregister(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to unregister");
+            let status = client
+                .heartbeat(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject unregistered clients");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let status = client
+                .unregister(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject unregistered clients");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
 
     #[tokio::test]
-    async fn can_initialize_server() {
-        let port = 50051;
+    async fn idle_sessions_expire() {
+        let port = 50068;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service =
+            crate::OxygenService::default().with_session_timeout(Duration::from_millis(200));
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            // heartbeats keep the session alive
+            for _ in 0..3 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let res = client
+                    .heartbeat(tonic::Request::new(ClientId {
+                        uuid: uuid.to_owned(),
+                    }))
+                    .await
+                    .expect("failed to send heartbeat")
+                    .into_inner();
+                assert_eq!(res.session_timeout, 200);
+            }
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject expired sessions");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn requests_must_carry_the_token_of_the_client() {
+        let port = 50069;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, _) = registered_client(port).await;
+            let (_, other_uuid) = registered_client(port).await;
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: other_uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject requests made for other clients");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+            // registered, but without its token
+            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
+                .await
+                .expect("failed to create client");
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: other_uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject requests without token");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+            let channel = Channel::from_shared(format!("http://[::1]:{}", port))
+                .expect("Hardcoded URI must be valid")
+                .connect()
+                .await
+                .expect("failed to create client");
+            let forged = format!("{}.{}", other_uuid, "00".repeat(32));
+            let mut client = OxygenClient::with_interceptor(channel, Bearer::new(&forged));
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: other_uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject forged tokens");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn new_clients_need_the_admin_secret() {
+        let port = 50070;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let channel = Channel::from_shared(format!("http://[::1]:{}", port))
+                .expect("Hardcoded URI must be valid")
+                .connect()
+                .await
+                .expect("failed to create client");
+            let mut client = OxygenClient::new(channel.clone());
+            let uuid = uuid::Uuid::new_v4().to_string();
+            let register = |secret: Option<&str>| {
+                let mut request = tonic::Request::new(RegisterRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    ..Default::default()
+                });
+                if let Some(secret) = secret {
+                    request.metadata_mut().insert(
+                        ADMIN_SECRET_METADATA_KEY,
+                        secret.parse().expect("secret must be valid metadata"),
+                    );
+                }
+                request
+            };
+            let status = client
+                .register(register(None))
+                .await
+                .expect_err("server should reject clients without the secret");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let status = client
+                .register(register(Some("guess")))
+                .await
+                .expect_err("server should reject clients with the wrong secret");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let token = client
+                .register(register(Some("s3cret")))
+                .await
+                .expect("failed to register with the secret")
+                .into_inner()
+                .token;
+
+            // registered clients can renew their session with their token,
+            // which ends the session of the old token
+            let mut client = OxygenClient::with_interceptor(channel.clone(), Bearer::new(&token));
+            let renewed = client
+                .register(register(None))
+                .await
+                .expect("failed to renew session")
+                .into_inner()
+                .token;
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject tokens of replaced sessions");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let mut client = OxygenClient::with_interceptor(channel, Bearer::new(&renewed));
+            let _ = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to get all collections");
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn ended_sessions_revoke_their_tokens() {
+        let port = 50081;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let channel = Channel::from_shared(format!("http://[::1]:{}", port))
+                .expect("Hardcoded URI must be valid")
+                .connect()
+                .await
+                .expect("failed to create client");
+            let uuid = uuid::Uuid::new_v4().to_string();
+            let register = || {
+                tonic::Request::new(RegisterRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    ..Default::default()
+                })
+            };
+            let mut request = register();
+            request.metadata_mut().insert(
+                ADMIN_SECRET_METADATA_KEY,
+                "s3cret".parse().expect("secret must be valid metadata"),
+            );
+            let token = OxygenClient::new(channel.clone())
+                .register(request)
+                .await
+                .expect("failed to register with the secret")
+                .into_inner()
+                .token;
+            let mut client = OxygenClient::with_interceptor(channel, Bearer::new(&token));
+            let _ = client
+                .unregister(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to unregister");
+
+            let status = client
+                .register(register())
+                .await
+                .expect_err("server should not renew ended sessions");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            let status = client
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: uuid.to_owned(),
+                }))
+                .await
+                .expect_err("server should reject tokens of ended sessions");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    /// PEM certificate and key signed by `ca`
+    fn signed_pem(ca: &rcgen::Certificate, names: &[&str]) -> (String, String) {
+        let names = names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let cert = rcgen::Certificate::from_params(rcgen::CertificateParams::new(names))
+            .expect("failed to generate certificate");
+        let pem = cert
+            .serialize_pem_with_signer(ca)
+            .expect("failed to sign certificate");
+        (pem, cert.serialize_private_key_pem())
+    }
+
+    #[tokio::test]
+    async fn server_can_require_client_certificates() {
+        let port = 50071;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let mut ca_params = rcgen::CertificateParams::new(vec![]);
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).expect("failed to generate CA");
+        let ca_pem = ca.serialize_pem().expect("failed to serialize CA");
+        let (server_cert, server_key) = signed_pem(&ca, &["localhost"]);
+        let (client_cert, client_key) = signed_pem(&ca, &["client"]);
+
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        for (name, pem) in [
+            ("server.pem", &server_cert),
+            ("server.key", &server_key),
+            ("ca.pem", &ca_pem),
+        ] {
+            std::fs::write(dir.path().join(name), pem).expect("failed to write PEM file");
+        }
+        let tls = TlsFiles {
+            cert: dir.path().join("server.pem"),
+            key: dir.path().join("server.key"),
+            client_ca: Some(dir.path().join("ca.pem")),
+        }
+        .load()
+        .expect("failed to load TLS configuration");
+
+        let oxygen_service = crate::OxygenService::default();
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .tls_config(tls)
+                .expect("TLS configuration must be valid")
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let register = || {
+                tonic::Request::new(RegisterRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid::Uuid::new_v4().to_string(),
+                    }),
+                    ..Default::default()
+                })
+            };
+            let endpoint = |identity: Option<Identity>| {
+                let mut tls = ClientTlsConfig::new()
+                    .ca_certificate(Certificate::from_pem(&ca_pem))
+                    .domain_name("localhost");
+                if let Some(identity) = identity {
+                    tls = tls.identity(identity);
+                }
+                Channel::from_shared(format!("https://[::1]:{}", port))
+                    .expect("Hardcoded URI must be valid")
+                    .tls_config(tls)
+                    .expect("client TLS configuration must be valid")
+            };
+
+            let channel = endpoint(Some(Identity::from_pem(&client_cert, &client_key)))
+                .connect()
+                .await
+                .expect("failed to connect with a client certificate");
+            let _ = OxygenClient::new(channel)
+                .register(register())
+                .await
+                .expect("failed to register over TLS");
+
+            // the handshake fails either while connecting or on the first
+            // request depending on the TLS version
+            if let Ok(channel) = endpoint(None).connect().await {
+                let _ = OxygenClient::new(channel)
+                    .register(register())
+                    .await
+                    .expect_err("server should reject clients without certificate");
+            }
+            if let Ok(mut client) = OxygenClient::connect(format!("http://[::1]:{}", port)).await {
+                let _ = client
+                    .register(register())
+                    .await
+                    .expect_err("server should reject plaintext clients");
+            }
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    /// Ids of every collection in `collections`, at any depth
+    fn all_collection_ids(collections: &[Collection]) -> Vec<u64> {
+        collections
+            .iter()
+            .flat_map(|collection| {
+                let mut ids = all_collection_ids(&collection.child_collections);
+                ids.push(collection.id);
+                ids
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn collections_can_be_restricted_to_some_clients() {
+        let port = 50072;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut owner, owner_uuid) = registered_client(port).await;
+            let (mut other, other_uuid) = registered_client(port).await;
+            let grant = |uuid: &str, permission: Permission| AclEntry {
+                principal: Some(Principal::ClientUuid(uuid.to_owned())),
+                permission: permission as i32,
+            };
+            let set_acl = |uuid: &str, entries| SetAclRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                // XXX: hardcoded storage, collection 2 holds files 1 and 2
+                collection_id: 2,
+                entries,
+            };
+            let file_request = |uuid: &str| FileRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                file_id: 1,
+            };
+            owner
+                .set_acl(as_admin(set_acl(
+                    &owner_uuid,
+                    vec![grant(&owner_uuid, Permission::Admin)],
+                )))
+                .await
+                .expect("the admin can restrict open collections");
+            let status = other
+                .set_acl(tonic::Request::new(set_acl(
+                    &other_uuid,
+                    vec![grant(&other_uuid, Permission::Admin)],
+                )))
+                .await
+                .expect_err("only admins can change the ACL");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+            // the collection is hidden from the other client
+            let collections = other
+                .get_all_collections(tonic::Request::new(ClientId {
+                    uuid: other_uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to get all collections")
+                .into_inner()
+                .collections;
+            let ids = all_collection_ids(&collections);
+            assert!(ids.contains(&3));
+            assert!(!ids.contains(&2));
+            let status = other
+                .get_collection(tonic::Request::new(CollectionRequest {
+                    client_id: Some(ClientId {
+                        uuid: other_uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                }))
+                .await
+                .expect_err("restricted collections can't be read");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            let status = other
+                .get_file_content(tonic::Request::new(file_request(&other_uuid)))
+                .await
+                .expect_err("files of restricted collections can't be read");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            owner
+                .get_file_content(tonic::Request::new(file_request(&owner_uuid)))
+                .await
+                .expect("the owner can read its files");
+
+            // and so are the changes made in it
+            let mut changes = other
+                .watch(tonic::Request::new(WatchRequest {
+                    client_id: Some(ClientId {
+                        uuid: other_uuid.to_owned(),
+                    }),
+                    collection_ids: vec![],
+                }))
+                .await
+                .expect("failed to watch all collections")
+                .into_inner();
+            for collection_id in [2, 1] {
+                owner
+                    .create_file(tonic::Request::new(CreateFileRequest {
+                        client_id: Some(ClientId {
+                            uuid: owner_uuid.to_owned(),
+                        }),
+                        collection_id,
+                        name: "new.md".to_string(),
+                        body: vec![],
+                    }))
+                    .await
+                    .expect("failed to create file");
+            }
+            let event = changes
+                .message()
+                .await
+                .expect("failed to get change")
+                .expect("expected a change");
+            assert_eq!(event.kind(), Kind::FileCreated);
+            assert_eq!(event.collection_id, 1);
+
+            // read access doesn't allow changes
+            let acl = owner
+                .set_acl(tonic::Request::new(set_acl(
+                    &owner_uuid,
+                    vec![
+                        grant(&owner_uuid, Permission::Admin),
+                        grant(&other_uuid, Permission::Read),
+                    ],
+                )))
+                .await
+                .expect("failed to set ACL")
+                .into_inner();
+            assert_eq!(acl.entries.len(), 2);
+            other
+                .get_file_content(tonic::Request::new(file_request(&other_uuid)))
+                .await
+                .expect("granted clients can read files");
+            let status = other
+                .update_file_content(tonic::Request::new(UpdateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: other_uuid.to_owned(),
+                    }),
+                    file_id: 1,
+                    body: b"changed".to_vec(),
+                    if_match: String::new(),
+                }))
+                .await
+                .expect_err("readers can't change files");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn clients_of_a_user_share_its_permissions() {
+        let port = 50073;
+        let addr = format!("[::1]:{}", port)
+            .parse()
+            .expect("Hardcoded IP address must be valid");
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
+        let join_handle = tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(oxygen_service.into_server())
+                .serve(addr)
+                .await
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut laptop, laptop_uuid) = registered_client(port).await;
+            let (mut phone, phone_uuid) = registered_client(port).await;
+            let (mut stranger, stranger_uuid) = registered_client(port).await;
+            let login = |uuid: &str, password: &str| LoginRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                username: "alice".to_string(),
+                password: password.to_string(),
+            };
+            let status = laptop
+                .login(tonic::Request::new(login(&laptop_uuid, "secret")))
+                .await
+                .expect_err("users must exist to log in");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            laptop
+                .create_user(as_admin(CreateUserRequest {
+                    client_id: Some(ClientId {
+                        uuid: laptop_uuid.to_owned(),
+                    }),
+                    username: "alice".to_string(),
+                    password: "secret".to_string(),
+                }))
+                .await
+                .expect("failed to create user");
+            let status = stranger
+                .login(tonic::Request::new(login(&stranger_uuid, "guess")))
+                .await
+                .expect_err("logging in needs the password");
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            for (client, uuid) in [(&mut laptop, &laptop_uuid), (&mut phone, &phone_uuid)] {
+                let user = client
+                    .login(tonic::Request::new(login(uuid, "secret")))
+                    .await
+                    .expect("failed to log in")
+                    .into_inner();
+                assert_eq!(user.username, "alice");
+            }
+
+            // XXX: hardcoded storage, collection 2 holds files 1 and 2
+            laptop
+                .set_acl(as_admin(SetAclRequest {
+                    client_id: Some(ClientId {
+                        uuid: laptop_uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                    entries: vec![AclEntry {
+                        principal: Some(Principal::User("alice".to_string())),
+                        permission: Permission::Admin as i32,
+                    }],
+                }))
+                .await
+                .expect("failed to set ACL");
+            let file_request = |uuid: &str| FileRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                file_id: 1,
+            };
+            phone
+                .get_file(tonic::Request::new(file_request(&phone_uuid)))
+                .await
+                .expect("every client of the user gets its permissions");
+            let status = stranger
+                .get_file(tonic::Request::new(file_request(&stranger_uuid)))
+                .await
+                .expect_err("other clients don't");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        })
+        .await
+        .expect("failed to run client");
+        join_handle.abort()
+    }
+
+    #[tokio::test]
+    async fn client_can_address_items_by_path() {
+        let port = 50074;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
         let oxygen_service = crate::OxygenService::default();
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
-                .expect("failed to start the server");
-        });
+                .expect("failed to start the server");
+        });
+        tokio::spawn(async move {
+            let (mut client, uuid) = registered_client(port).await;
+            let resolve = |path: &str| ResolvePathRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                path: path.to_string(),
+            };
+            // XXX: hardcoded storage
+            let resolved = client
+                .resolve_path(tonic::Request::new(resolve(
+                    "/collection 3/collection 2/f 3.md",
+                )))
+                .await
+                .expect("failed to resolve path")
+                .into_inner();
+            assert_eq!(resolved.item, Some(path_response::Item::FileId(1)));
+            assert_eq!(resolved.path, "collection 3/collection 2/f 3.md");
+            let status = client
+                .resolve_path(tonic::Request::new(resolve("collection 3/missing.md")))
+                .await
+                .expect_err("missing items can't be resolved");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+
+            let path = client
+                .get_path(tonic::Request::new(GetPathRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    item: Some(get_path_request::Item::CollectionId(2)),
+                }))
+                .await
+                .expect("failed to get path")
+                .into_inner();
+            assert_eq!(path.path, "collection 3/collection 2");
+            assert_eq!(path.item, Some(path_response::Item::CollectionId(2)));
+            let status = client
+                .get_path(tonic::Request::new(GetPathRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    item: None,
+                }))
+                .await
+                .expect_err("paths are for collections or files");
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        })
+        .await
+        .expect("failed to run client");
         join_handle.abort()
     }
 
     #[tokio::test]
-    async fn client_can_register_with_server() {
-        let port = 50052;
+    async fn client_can_list_fetch_and_restore_revisions() {
+        let port = 50075;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
         let oxygen_service = crate::OxygenService::default();
-        let server_id = oxygen_service.id;
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            client
+                .update_file_content(tonic::Request::new(UpdateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                    body: b"# edited".to_vec(),
+                    if_match: String::new(),
+                }))
                 .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let reg_request = tonic::Request::new(crate::oxygen::ClientId {
-                uuid: uuid.to_owned(),
-            });
-            let res = client
-                .register(reg_request)
+                .expect("failed to update file content");
+            let revisions = client
+                .list_revisions(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                }))
                 .await
-                .expect("failed to get response from server")
+                .expect("failed to list revisions")
+                .into_inner()
+                .revisions;
+            let authors: Vec<_> = revisions.iter().map(|r| r.author.as_str()).collect();
+            // the hard coded content isn't written by anybody
+            assert_eq!(authors, ["", uuid.as_str()]);
+            assert!(revisions.iter().all(|revision| revision.file_id == 0));
+            assert!(revisions[0].id < revisions[1].id);
+
+            let revision_request = |revision: &Revision| RevisionRequest {
+                client_id: Some(ClientId {
+                    uuid: uuid.to_owned(),
+                }),
+                file_id: 0,
+                revision_id: revision.id,
+            };
+            let content = client
+                .get_revision_content(tonic::Request::new(revision_request(&revisions[0])))
+                .await
+                .expect("failed to get revision content")
                 .into_inner();
-            assert_eq!(res.client_id, uuid);
-            assert_eq!(res.server_id, server_id.to_string());
+            assert_eq!(content.body, b"# f 2.md content");
+
+            let restored = client
+                .restore_revision(tonic::Request::new(revision_request(&revisions[0])))
+                .await
+                .expect("failed to restore revision")
+                .into_inner()
+                .file
+                .expect("expected restored file");
+            assert_eq!(restored.sha256, revisions[0].sha256);
+            let after = client
+                .list_revisions(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                }))
+                .await
+                .expect("failed to list revisions")
+                .into_inner()
+                .revisions;
+            assert_eq!(after.len(), 3);
+            assert_eq!(after[2].author, uuid);
+            assert_eq!(after[2].sha256, revisions[0].sha256);
+
+            let missing = Revision {
+                id: 42,
+                ..Default::default()
+            };
+            let status = client
+                .get_revision_content(tonic::Request::new(revision_request(&missing)))
+                .await
+                .expect_err("revision does not exist");
+            assert_eq!(status.code(), tonic::Code::NotFound);
         })
         .await
         .expect("failed to run client");
@@ -232,86 +4245,161 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn client_can_get_all_collections() {
-        let port = 50053;
+    async fn client_can_diff_revisions() {
+        let port = 50076;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
         let oxygen_service = crate::OxygenService::default();
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
-                .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let _ = client
-                .register(tonic::Request::new(crate::oxygen::ClientId {
-                    uuid: uuid.to_owned(),
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            client
+                .update_file_content(tonic::Request::new(UpdateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                    body: b"# f 2.md content\n- added\n".to_vec(),
+                    if_match: String::new(),
                 }))
                 .await
-                .expect("failed to register with server");
-            let collection_res = client
-                .get_all_collections(tonic::Request::new(crate::oxygen::ClientId {
+                .expect("failed to update file content");
+            let diff_request = |to| DiffRequest {
+                client_id: Some(ClientId {
                     uuid: uuid.to_owned(),
-                }))
+                }),
+                file_id: 0,
+                from_revision_id: 1,
+                to,
+                context: 0,
+            };
+
+            let diff = client
+                .diff_file(tonic::Request::new(diff_request(None)))
                 .await
-                .expect("failed to get get all collections")
+                .expect("failed to diff file")
                 .into_inner();
-            // XXX: hardcoded collection
-            assert!(collection_res.collections.len() == 5);
+            assert!(diff.unified_diff.starts_with("--- f 2.md@1\n+++ f 2.md\n"));
+            assert_eq!(diff.hunks.len(), 1);
+            let kinds: Vec<_> = diff.hunks[0]
+                .lines
+                .iter()
+                .map(|line| (line.kind(), line.text.as_str()))
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    (diff_line::Kind::Removed, "# f 2.md content"),
+                    (diff_line::Kind::Added, "# f 2.md content"),
+                    (diff_line::Kind::Added, "- added"),
+                ]
+            );
+
+            let same = client
+                .diff_file(tonic::Request::new(diff_request(Some(
+                    diff_request::To::ToRevisionId(1),
+                ))))
+                .await
+                .expect("failed to diff file")
+                .into_inner();
+            assert!(same.hunks.is_empty());
+
+            let status = client
+                .diff_file(tonic::Request::new(diff_request(Some(
+                    diff_request::To::ToRevisionId(42),
+                ))))
+                .await
+                .expect_err("revision does not exist");
+            assert_eq!(status.code(), tonic::Code::NotFound);
         })
         .await
         .expect("failed to run client");
         join_handle.abort()
     }
+
     #[tokio::test]
-    async fn client_can_get_collection_by_id() {
-        let port = 50054;
+    async fn stale_writes_are_rejected_with_the_current_version() {
+        let port = 50077;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
         let oxygen_service = crate::OxygenService::default();
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
+            let (mut client, uuid) = registered_client(port).await;
+            // XXX: hardcoded storage
+            let file = client
+                .get_file(tonic::Request::new(FileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                }))
                 .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let _ = client
-                .register(tonic::Request::new(crate::oxygen::ClientId {
+                .expect("failed to get file")
+                .into_inner()
+                .file
+                .expect("expected file");
+            let update = |body: &[u8]| UpdateFileRequest {
+                client_id: Some(ClientId {
                     uuid: uuid.to_owned(),
+                }),
+                file_id: 0,
+                body: body.to_vec(),
+                if_match: file.etag.clone(),
+            };
+            let updated = client
+                .update_file_content(tonic::Request::new(update(b"# first")))
+                .await
+                .expect("failed to update file at its current version")
+                .into_inner()
+                .file
+                .expect("expected updated file");
+
+            let status = client
+                .update_file_content(tonic::Request::new(update(b"# second")))
+                .await
+                .expect_err("write is stale");
+            assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+            let current = File::decode(status.details()).expect("expected the current file");
+            assert_eq!(current, updated);
+
+            let status = client
+                .delete_file(tonic::Request::new(DeleteFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    file_id: 0,
+                    if_match: file.etag.clone(),
                 }))
                 .await
-                .expect("failed to register with server");
-            // XXX: hardcoded storage
-            for id in 0..5 {
-                let collection_request = CollectionRequest {
+                .expect_err("delete is stale");
+            assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+            let content = client
+                .get_file_content(tonic::Request::new(FileRequest {
                     client_id: Some(ClientId {
                         uuid: uuid.to_owned(),
                     }),
-                    collection_id: id,
-                };
-                let _ = client
-                    .get_collection(tonic::Request::new(collection_request))
-                    .await
-                    .expect("failed to get get collection");
-            }
+                    file_id: 0,
+                }))
+                .await
+                .expect("failed to get file content")
+                .into_inner();
+            assert_eq!(content.body, b"# first");
         })
         .await
         .expect("failed to run client");
@@ -319,91 +4407,285 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn server_handle_invalid_collection_ids() {
-        let port = 50055;
+    async fn deleted_items_can_be_restored_or_purged() {
+        let port = 50078;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
-        let oxygen_service = crate::OxygenService::default();
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
-                .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let _ = client
-                .register(tonic::Request::new(crate::oxygen::ClientId {
+            let (mut owner, owner_uuid) = registered_client(port).await;
+            let (mut other, other_uuid) = registered_client(port).await;
+            let client_id = |uuid: &str| {
+                Some(ClientId {
                     uuid: uuid.to_owned(),
+                })
+            };
+            // XXX: hardcoded storage, collection 2 holds files 1 and 2 and
+            // file 0 is in collection 3
+            owner
+                .set_acl(as_admin(SetAclRequest {
+                    client_id: client_id(&owner_uuid),
+                    collection_id: 2,
+                    entries: vec![AclEntry {
+                        principal: Some(Principal::ClientUuid(owner_uuid.to_owned())),
+                        permission: Permission::Admin as i32,
+                    }],
                 }))
                 .await
-                .expect("failed to register with server");
-            // XXX: hardcoded storage
-            for id in [100, 10000000] {
-                let collection_request = CollectionRequest {
-                    client_id: Some(ClientId {
-                        uuid: uuid.to_owned(),
-                    }),
-                    collection_id: id,
-                };
-                let _ = client
-                    .get_collection(tonic::Request::new(collection_request))
-                    .await
-                    .expect_err("server should fail to get invalid collection ids");
-            }
+                .expect("failed to set ACL");
+            owner
+                .delete_file(tonic::Request::new(DeleteFileRequest {
+                    client_id: client_id(&owner_uuid),
+                    file_id: 0,
+                    if_match: String::new(),
+                }))
+                .await
+                .expect("failed to delete file");
+            owner
+                .delete_collection(tonic::Request::new(DeleteCollectionRequest {
+                    client_id: client_id(&owner_uuid),
+                    collection_id: 2,
+                    recursive: true,
+                }))
+                .await
+                .expect("failed to delete collection");
+
+            // the restricted collection is hidden from the other client
+            let trash = other
+                .list_trash(tonic::Request::new(ClientId {
+                    uuid: other_uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to list the trash")
+                .into_inner()
+                .items;
+            assert_eq!(trash.len(), 1);
+            assert_eq!(trash[0].lineage, vec![4, 3]);
+            assert_eq!(trash[0].path, "collection 3/f 2.md");
+            let Some(trash_item::Item::File(file)) = &trash[0].item else {
+                panic!("expected the deleted file");
+            };
+            assert_eq!((file.id, file.collection_id), (0, 3));
+            let restore = |uuid: &str, item, parent_id: Option<u64>| RestoreTrashRequest {
+                client_id: client_id(uuid),
+                item: Some(item),
+                to: parent_id.map(restore_trash_request::To::ParentId),
+            };
+            let status = other
+                .restore_trash(tonic::Request::new(restore(
+                    &other_uuid,
+                    restore_trash_request::Item::CollectionId(2),
+                    None,
+                )))
+                .await
+                .expect_err("restricted collections can't be restored");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            let restored = other
+                .restore_trash(tonic::Request::new(restore(
+                    &other_uuid,
+                    restore_trash_request::Item::FileId(0),
+                    None,
+                )))
+                .await
+                .expect("failed to restore file")
+                .into_inner()
+                .item;
+            let Some(restore_trash_response::Item::File(file)) = restored else {
+                panic!("expected the restored file");
+            };
+            assert_eq!((file.id, file.collection_id), (0, 3));
+            let content = other
+                .get_file_content(tonic::Request::new(FileRequest {
+                    client_id: client_id(&other_uuid),
+                    file_id: 0,
+                }))
+                .await
+                .expect("failed to get restored file content")
+                .into_inner();
+            assert_eq!(content.body, b"# f 2.md content");
+
+            // restored collections keep their ACL
+            let restored = owner
+                .restore_trash(tonic::Request::new(restore(
+                    &owner_uuid,
+                    restore_trash_request::Item::CollectionId(2),
+                    Some(1),
+                )))
+                .await
+                .expect("failed to restore collection")
+                .into_inner()
+                .item;
+            let Some(restore_trash_response::Item::Collection(collection)) = restored else {
+                panic!("expected the restored collection");
+            };
+            assert_eq!(collection.id, 2);
+            assert_eq!(collection.files.len(), 2);
+            let path = owner
+                .get_path(tonic::Request::new(GetPathRequest {
+                    client_id: client_id(&owner_uuid),
+                    item: Some(get_path_request::Item::CollectionId(2)),
+                }))
+                .await
+                .expect("failed to get path")
+                .into_inner()
+                .path;
+            assert_eq!(path, "collection 3/collection 1/collection 2");
+            let status = other
+                .get_collection(tonic::Request::new(CollectionRequest {
+                    client_id: client_id(&other_uuid),
+                    collection_id: 2,
+                }))
+                .await
+                .expect_err("restored collection is still restricted");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+            owner
+                .delete_collection(tonic::Request::new(DeleteCollectionRequest {
+                    client_id: client_id(&owner_uuid),
+                    collection_id: 2,
+                    recursive: true,
+                }))
+                .await
+                .expect("failed to delete collection");
+            let purge = |uuid: &str| TrashRequest {
+                client_id: client_id(uuid),
+                item: Some(trash_request::Item::CollectionId(2)),
+            };
+            let status = other
+                .purge_trash(tonic::Request::new(purge(&other_uuid)))
+                .await
+                .expect_err("restricted collections can't be purged");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            let purged = owner
+                .purge_trash(tonic::Request::new(purge(&owner_uuid)))
+                .await
+                .expect("failed to purge collection")
+                .into_inner()
+                .item
+                .expect("expected the purged collection");
+            assert_eq!(purged.lineage, vec![4, 3, 1]);
+            let status = owner
+                .purge_trash(tonic::Request::new(purge(&owner_uuid)))
+                .await
+                .expect_err("purged items are gone");
+            assert_eq!(status.code(), tonic::Code::NotFound);
+            let trash = owner
+                .list_trash(tonic::Request::new(ClientId {
+                    uuid: owner_uuid.to_owned(),
+                }))
+                .await
+                .expect("failed to list the trash")
+                .into_inner()
+                .items;
+            assert_eq!(trash, vec![]);
         })
         .await
         .expect("failed to run client");
         join_handle.abort()
     }
 
+    #[test]
+    fn stored_acls_are_loaded() {
+        let storage = HardCodedStorage::new();
+        let grant = AclEntry {
+            principal: Some(Principal::ClientUuid("owner".to_string())),
+            permission: Permission::Read as i32,
+        };
+        storage
+            .set_acl(2, vec![grant.clone()])
+            .expect("failed to set ACL");
+        let oxygen_service = crate::OxygenService::new(storage);
+        assert_eq!(oxygen_service.acl.get(2), vec![grant]);
+        let other = AuthenticatedClient {
+            uuid: "other".to_string(),
+            session: String::new(),
+            user: None,
+        };
+        assert_eq!(
+            oxygen_service.acl.permission(&other, &[4, 3, 2]),
+            Permission::None
+        );
+    }
+
     #[tokio::test]
-    async fn client_can_get_file_by_id() {
-        let port = 50056;
+    async fn first_acl_of_open_collections_needs_the_admin_secret() {
+        let port = 50080;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
-        let oxygen_service = crate::OxygenService::default();
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
-                .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let _ = client
-                .register(tonic::Request::new(crate::oxygen::ClientId {
+            let (mut owner, owner_uuid) = registered_client(port).await;
+            let (mut other, other_uuid) = registered_client(port).await;
+            let set_acl = |uuid: &str, owner: &str| SetAclRequest {
+                client_id: Some(ClientId {
                     uuid: uuid.to_owned(),
+                }),
+                // XXX: hardcoded storage, collection 2 holds files 1 and 2
+                collection_id: 2,
+                entries: vec![AclEntry {
+                    principal: Some(Principal::ClientUuid(owner.to_owned())),
+                    permission: Permission::Admin as i32,
+                }],
+            };
+
+            // open collections can be changed but not taken over
+            other
+                .update_file_content(tonic::Request::new(UpdateFileRequest {
+                    client_id: Some(ClientId {
+                        uuid: other_uuid.to_owned(),
+                    }),
+                    file_id: 1,
+                    body: b"changed".to_vec(),
+                    if_match: String::new(),
                 }))
                 .await
-                .expect("failed to register with server");
-            // XXX: hardcoded storage
-            for id in 0..4 {
-                let file_request = FileRequest {
+                .expect("anyone can write to open collections");
+            let status = other
+                .set_acl(tonic::Request::new(set_acl(&other_uuid, &other_uuid)))
+                .await
+                .expect_err("only the admin can restrict open collections");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            let status = other
+                .get_acl(tonic::Request::new(CollectionRequest {
                     client_id: Some(ClientId {
-                        uuid: uuid.to_owned(),
+                        uuid: other_uuid.to_owned(),
                     }),
-                    file_id: id,
-                };
-                let _ = client
-                    .get_file(tonic::Request::new(file_request))
-                    .await
-                    .expect("failed to get file by id");
-            }
+                    collection_id: 2,
+                }))
+                .await
+                .expect_err("only the admin can see the ACL of open collections");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+            // the admin hands the collection over to its owner
+            owner
+                .set_acl(as_admin(set_acl(&owner_uuid, &owner_uuid)))
+                .await
+                .expect("the admin can restrict open collections");
+            owner
+                .set_acl(tonic::Request::new(set_acl(&owner_uuid, &owner_uuid)))
+                .await
+                .expect("owners can change the ACL without the secret");
+            let status = other
+                .set_acl(as_admin(set_acl(&other_uuid, &other_uuid)))
+                .await
+                .expect_err("restricted collections need admin permission");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
         })
         .await
         .expect("failed to run client");
@@ -411,58 +4693,47 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn client_can_get_file_content_by_id() {
-        let port = 50057;
+    async fn servers_without_admin_secret_have_no_admin() {
+        let port = 50082;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
         let oxygen_service = crate::OxygenService::default();
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
-                .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let _ = client
-                .register(tonic::Request::new(crate::oxygen::ClientId {
-                    uuid: uuid.to_owned(),
+            let (mut client, uuid) = registered_client(port).await;
+            let status = client
+                .create_user(as_admin(CreateUserRequest {
+                    client_id: Some(ClientId {
+                        uuid: uuid.to_owned(),
+                    }),
+                    username: "alice".to_string(),
+                    password: "secret".to_string(),
                 }))
                 .await
-                .expect("failed to register with server");
-            // XXX: hardcoded storage
-            for id in 0..4 {
-                let file_request = FileRequest {
+                .expect_err("users can't be created without an admin secret");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            let status = client
+                .set_acl(as_admin(SetAclRequest {
                     client_id: Some(ClientId {
                         uuid: uuid.to_owned(),
                     }),
-                    file_id: id,
-                };
-                let content = client
-                    .get_file_content(tonic::Request::new(file_request.clone()))
-                    .await
-                    .expect("failed to get file content")
-                    .into_inner();
-                let actual =
-                    std::str::from_utf8(&content.body).expect("expect body to be valid utf-8");
-                // XXX: hardcoded content
-                let file = client
-                    .get_file(tonic::Request::new(file_request))
-                    .await
-                    .expect("failed to get file details")
-                    .into_inner()
-                    .file
-                    .expect("unexpected");
-                let expected = format!("# {} content", file.name);
-                assert_eq!(actual, expected);
-            }
+                    // XXX: hardcoded storage, collection 2 holds files 1 and 2
+                    collection_id: 2,
+                    entries: vec![AclEntry {
+                        principal: Some(Principal::ClientUuid(uuid.to_owned())),
+                        permission: Permission::Admin as i32,
+                    }],
+                }))
+                .await
+                .expect_err("open collections stay open without an admin secret");
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
         })
         .await
         .expect("failed to run client");
@@ -470,45 +4741,116 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn server_handle_invalid_file_ids() {
-        let port = 50058;
+    async fn expired_items_are_purged_from_the_trash() {
+        let storage = AsyncStorage::new(HardCodedStorage::new());
+        let acl = AccessControl::default();
+        storage
+            .delete_file(3, None)
+            .await
+            .expect("failed to delete file");
+        crate::purge_expired_trash(&storage, &acl, Duration::from_secs(60)).await;
+        let trash = storage
+            .list_trash()
+            .await
+            .expect("failed to list the trash");
+        assert_eq!(trash.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        crate::purge_expired_trash(&storage, &acl, Duration::ZERO).await;
+        let trash: Vec<TrashItem> = storage
+            .list_trash()
+            .await
+            .expect("failed to list the trash");
+        assert_eq!(trash, vec![]);
+    }
+
+    #[tokio::test]
+    async fn client_can_search_the_files_it_can_read() {
+        let port = 50079;
         let addr = format!("[::1]:{}", port)
             .parse()
             .expect("Hardcoded IP address must be valid");
-        let oxygen_service = crate::OxygenService::default();
+        let oxygen_service = crate::OxygenService::default().with_admin_secret("s3cret");
         let join_handle = tokio::spawn(async move {
             tonic::transport::Server::builder()
-                .add_service(crate::oxygen::oxygen_server::OxygenServer::new(
-                    oxygen_service,
-                ))
+                .add_service(oxygen_service.into_server())
                 .serve(addr)
                 .await
                 .expect("failed to start the server");
         });
         tokio::spawn(async move {
-            let mut client = OxygenClient::connect(format!("http://[::1]:{}", port))
-                .await
-                .expect("failed to create client");
-            let uuid = uuid::Uuid::new_v4().to_string();
-            let _ = client
-                .register(tonic::Request::new(crate::oxygen::ClientId {
+            let (mut owner, owner_uuid) = registered_client(port).await;
+            let (mut other, other_uuid) = registered_client(port).await;
+            let search = |uuid: &str, query: &str| SearchRequest {
+                client_id: Some(ClientId {
                     uuid: uuid.to_owned(),
-                }))
-                .await
-                .expect("failed to register with server");
-            // XXX: hardcoded storage
-            for id in [100, 10000000] {
-                let file_request = FileRequest {
-                    client_id: Some(ClientId {
-                        uuid: uuid.to_owned(),
-                    }),
-                    file_id: id,
-                };
-                let _ = client
-                    .get_file(tonic::Request::new(file_request))
+                }),
+                query: query.to_string(),
+                limit: 0,
+            };
+            // XXX: hardcoded storage, collection 2 holds files 1 and 2
+            for (collection_id, name) in [(2, "secret.md"), (1, "open.md")] {
+                owner
+                    .create_file(tonic::Request::new(CreateFileRequest {
+                        client_id: Some(ClientId {
+                            uuid: owner_uuid.to_owned(),
+                        }),
+                        collection_id,
+                        name: name.to_string(),
+                        body: b"# Quarterly planning".to_vec(),
+                    }))
                     .await
-                    .expect_err("server should fail to get invalid file ids");
+                    .expect("failed to create file");
             }
+            owner
+                .set_acl(as_admin(SetAclRequest {
+                    client_id: Some(ClientId {
+                        uuid: owner_uuid.to_owned(),
+                    }),
+                    collection_id: 2,
+                    entries: vec![AclEntry {
+                        principal: Some(Principal::ClientUuid(owner_uuid.to_owned())),
+                        permission: Permission::Admin as i32,
+                    }],
+                }))
+                .await
+                .expect("failed to set ACL");
+
+            let hits = owner
+                .search(tonic::Request::new(search(
+                    &owner_uuid,
+                    "quarter* planning",
+                )))
+                .await
+                .expect("failed to search")
+                .into_inner()
+                .hits;
+            assert_eq!(hits.len(), 2);
+            let hits = other
+                .search(tonic::Request::new(search(
+                    &other_uuid,
+                    "quarter* planning",
+                )))
+                .await
+                .expect("failed to search")
+                .into_inner()
+                .hits;
+            assert_eq!(hits.len(), 1);
+            let hit = &hits[0];
+            assert_eq!(hit.file.as_ref().map(|file| file.collection_id), Some(1));
+            assert_eq!(hit.snippet, "# Quarterly planning");
+            let highlighted: Vec<_> = hit
+                .highlights
+                .iter()
+                .map(|highlight| &hit.snippet[highlight.start as usize..highlight.end as usize])
+                .collect();
+            assert_eq!(highlighted, ["Quarterly", "planning"]);
+
+            let status = other
+                .search(tonic::Request::new(search(&other_uuid, " \"\" ")))
+                .await
+                .expect_err("queries need words");
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
         })
         .await
         .expect("failed to run client");